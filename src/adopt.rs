@@ -0,0 +1,32 @@
+//! `--adopt` command: take over the existing records of the monitored hosts and the desired records.
+//!
+//! Records which were created by hand are not managed by this app: they are never deleted, e.g. when they are no longer
+//! desired or are of a type which is not monitored anymore. Adopting them marks them as managed in their
+//! comment, without changing their content, so that this app can take over their whole lifecycle without creating
+//! them again.
+
+use crate::clock::SystemClock;
+use crate::config::Config;
+use crate::connect_zones;
+use log::info;
+
+/// Adopt the existing records of every configured zone
+pub fn run(config: &Config) -> Result<(), ()> {
+    let mut zones = connect_zones(config, &[])?;
+    let mut failed = false;
+    for zone in &mut zones {
+        match zone.adopt(&SystemClock) {
+            Ok(ids) if ids.is_empty() => {
+                info!("No record to adopt in zone '{}'", zone.zone.id)
+            }
+            Ok(ids) => info!(
+                "Adopted <{}> records in zone '{}': {}",
+                ids.len(),
+                zone.zone.id,
+                ids.join(", ")
+            ),
+            Err(_) => failed = true,
+        }
+    }
+    if failed { Err(()) } else { Ok(()) }
+}
@@ -0,0 +1,173 @@
+//! Append-only audit trail of the DNS changes made by this app.
+//!
+//! When `CF_DNS_AUDIT_FILE` is set, one JSON line is appended to the file for every record which is successfully
+//! created, updated or deleted. Each line is flushed to disk before the next change is made.
+
+use crate::RecordType;
+use log::error;
+use serde_json::{Value, json};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::sync::{Mutex, OnceLock};
+
+static AUDIT_FILE: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Kind of change made to a record
+#[derive(Debug, Clone, Copy)]
+pub enum Action {
+    Create,
+    Update,
+    Delete,
+}
+
+impl Action {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Action::Create => "create",
+            Action::Update => "update",
+            Action::Delete => "delete",
+        }
+    }
+}
+
+/// A single change made to a record
+#[derive(Debug, Clone)]
+pub struct Entry<'a> {
+    pub action: Action,
+    pub zone_id: &'a str,
+    pub record_id: &'a str,
+    pub host: &'a str,
+    pub rtype: RecordType,
+    pub old_content: Option<IpAddr>,
+    pub new_content: Option<IpAddr>,
+}
+
+/// Open the audit file for appending, creating it if needed
+pub fn open(path: &str) -> Result<(), ()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| {
+            error!("Could not open audit file '{path}' for appending: {e}");
+        })?;
+
+    AUDIT_FILE.set(Mutex::new(file)).map_err(|_| {
+        error!("Audit file is already open");
+    })
+}
+
+/// Append an entry to the audit file, if one is open
+pub fn record(entry: &Entry) {
+    let Some(file) = AUDIT_FILE.get() else {
+        return;
+    };
+
+    let line = entry_line(entry);
+
+    let Ok(mut file) = file.lock() else {
+        error!("Could not write to the audit file: {line}");
+        return;
+    };
+
+    if let Err(e) = append(&mut file, &line) {
+        error!("Could not write to the audit file: {e}: {line}");
+    }
+}
+
+/// JSON line of an entry, timestamped now
+fn entry_line(entry: &Entry) -> Value {
+    json!({
+        "timestamp": jiff::Timestamp::now().to_string(),
+        "action": entry.action.as_str(),
+        "zone_id": entry.zone_id,
+        "record_id": entry.record_id,
+        "host": entry.host,
+        "type": entry.rtype.to_string(),
+        "old_content": entry.old_content,
+        "new_content": entry.new_content,
+    })
+}
+
+/// Append `line` to `file`, and make sure it is on disk
+fn append(file: &mut File, line: &Value) -> io::Result<()> {
+    writeln!(file, "{line}")?;
+    file.sync_data()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging;
+    use std::{env, fs};
+
+    #[test]
+    fn appends_a_json_line_for_each_change() {
+        let path = env::temp_dir().join(format!("cf-dns-rs-audit-{}.jsonl", uuid::Uuid::new_v4()));
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .unwrap();
+        let update = Entry {
+            action: Action::Update,
+            zone_id: "zone",
+            record_id: "record",
+            host: "www.example.com",
+            rtype: RecordType::A,
+            old_content: Some("203.0.113.1".parse().unwrap()),
+            new_content: Some("203.0.113.2".parse().unwrap()),
+        };
+        let delete = Entry {
+            action: Action::Delete,
+            new_content: None,
+            ..update.clone()
+        };
+
+        append(&mut file, &entry_line(&update)).unwrap();
+        append(&mut file, &entry_line(&delete)).unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let lines: Vec<Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert!(
+            lines[0]["timestamp"]
+                .as_str()
+                .unwrap()
+                .parse::<jiff::Timestamp>()
+                .is_ok()
+        );
+        assert_eq!(lines[0]["action"], "update");
+        assert_eq!(lines[0]["zone_id"], "zone");
+        assert_eq!(lines[0]["record_id"], "record");
+        assert_eq!(lines[0]["host"], "www.example.com");
+        assert_eq!(lines[0]["type"], "A");
+        assert_eq!(lines[0]["old_content"], "203.0.113.1");
+        assert_eq!(lines[0]["new_content"], "203.0.113.2");
+        assert_eq!(lines[1]["action"], "delete");
+        assert_eq!(lines[1]["new_content"], Value::Null);
+    }
+
+    #[test]
+    fn refuses_an_audit_file_which_cannot_be_opened() {
+        logging::init_for_tests();
+        let path = env::temp_dir()
+            .join(format!("cf-dns-rs-missing-{}", uuid::Uuid::new_v4()))
+            .join("audit.jsonl");
+        let path = path.to_string_lossy();
+
+        let (result, errors) = logging::capture_errors(|| open(&path));
+
+        assert!(result.is_err());
+        assert!(
+            errors[0].starts_with(&format!("Could not open audit file '{path}' for appending")),
+            "{errors:?}"
+        );
+        assert!(AUDIT_FILE.get().is_none());
+    }
+}
@@ -0,0 +1,306 @@
+//! BIND zone file fragments.
+//!
+//! `--export-bind` prints the managed records of every zone (those of the monitored hosts and the desired records) as
+//! zone file lines, and a records file whose name ends in `.zone` is read as such lines instead of TOML:
+//!
+//! ```text
+//! $ORIGIN example.com.
+//! @       300 IN A    203.0.113.7 ; proxied
+//! www         IN AAAA 2001:db8::7 ; not proxied
+//! ```
+//!
+//! Only `A` and `AAAA` records are supported, on a single line each. Names are relative to the last `$ORIGIN` unless
+//! they end with a `.`, and `@` is the origin itself. A record without a TTL has the one of the last `$TTL`, or the
+//! automatic TTL if there is none. Whether a record is proxied is given by its `; proxied` or `; not proxied` comment.
+
+use crate::clock::SystemClock;
+use crate::config::Config;
+use crate::zone::ZoneClient;
+use crate::{Record, RecordType, Ttl};
+use log::error;
+use std::fmt::Write;
+
+/// A record of a zone file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZoneFileRecord {
+    /// Fully qualified name, without the final `.`
+    pub name: String,
+    pub rtype: String,
+    pub content: String,
+    /// TTL in seconds, `None` for the automatic TTL
+    pub ttl: Option<u32>,
+    pub proxied: Option<bool>,
+}
+
+/// Render `records` as zone file lines, with names relative to `origin` if it is known
+pub fn render(origin: Option<&str>, records: &[Record]) -> String {
+    let mut out = String::new();
+    if let Some(origin) = origin {
+        let _ = writeln!(out, "$ORIGIN {origin}.");
+    }
+    for record in records {
+        let name = record.name.to_lowercase();
+        let name = match origin.map(|origin| origin.to_lowercase()) {
+            Some(origin) if name == origin => "@".to_string(),
+            Some(origin) => match name.strip_suffix(&format!(".{origin}")) {
+                Some(relative) => relative.to_string(),
+                None => format!("{name}."),
+            },
+            None => format!("{name}."),
+        };
+        let ttl = match record.ttl {
+            Ttl::Auto => String::new(),
+            Ttl::Seconds(seconds) => seconds.to_string(),
+        };
+        let proxied = if record.proxied {
+            "proxied"
+        } else {
+            "not proxied"
+        };
+        let _ = writeln!(
+            out,
+            "{name:<24} {ttl:>5} IN {:<4} {} ; {proxied}",
+            record.rtype().to_string(),
+            record.content
+        );
+    }
+    out
+}
+
+/// Parse the zone file lines of `text`, read from `path`
+pub fn parse(text: &str, path: &str) -> Result<Vec<ZoneFileRecord>, ()> {
+    let mut origin: Option<String> = None;
+    let mut default_ttl = None;
+    let mut previous_name: Option<String> = None;
+    let mut records = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let location = format!("line <{}> of zone file '{path}'", index + 1);
+        let (data, comment) = match line.split_once(';') {
+            Some((data, comment)) => (data, Some(comment.trim())),
+            None => (line, None),
+        };
+        let mut fields = data.split_whitespace().collect::<Vec<_>>();
+        if fields.is_empty() {
+            continue;
+        }
+
+        match fields[0].to_ascii_uppercase().as_str() {
+            "$ORIGIN" => {
+                let [_, name] = fields[..] else {
+                    error!("`$ORIGIN` at {location} should have a single name");
+                    return Err(());
+                };
+                origin = Some(qualify(name, origin.as_deref(), &location)?);
+                continue;
+            }
+            "$TTL" => {
+                let ttl = fields.get(1).and_then(|ttl| ttl.parse::<u32>().ok());
+                let (Some(ttl), 2) = (ttl, fields.len()) else {
+                    error!("`$TTL` at {location} should have a single TTL in seconds");
+                    return Err(());
+                };
+                default_ttl = Some(ttl);
+                continue;
+            }
+            directive if directive.starts_with('$') => {
+                error!("Directive '{directive}' at {location} is not supported");
+                return Err(());
+            }
+            _ => {}
+        }
+
+        // A line which starts with a blank has the name of the previous record
+        let name = if line.starts_with(char::is_whitespace) {
+            previous_name.clone().ok_or_else(|| {
+                error!("Record at {location} has no name, and there is no previous record");
+            })?
+        } else {
+            qualify(fields.remove(0), origin.as_deref(), &location)?
+        };
+
+        // The TTL and the class are optional, and can come in any order
+        let mut ttl = None;
+        while let Some(field) = fields.first() {
+            if field.eq_ignore_ascii_case("IN") {
+                fields.remove(0);
+            } else if let Ok(seconds) = field.parse::<u32>() {
+                ttl = Some(seconds);
+                fields.remove(0);
+            } else {
+                break;
+            }
+        }
+        let [rtype, content] = fields[..] else {
+            error!(
+                "Record '{name}' at {location} should be `<name> [<ttl>] [IN] <type> <content>`"
+            );
+            return Err(());
+        };
+        if RecordType::try_from(rtype).is_err() {
+            error!(
+                "Record '{name}' at {location} has type '{rtype}', only `A` and `AAAA` are supported"
+            );
+            return Err(());
+        }
+        let proxied = match comment.map(|comment| comment.to_ascii_lowercase()) {
+            Some(comment) if comment == "proxied" => Some(true),
+            Some(comment) if comment == "not proxied" => Some(false),
+            _ => None,
+        };
+
+        previous_name = Some(name.clone());
+        records.push(ZoneFileRecord {
+            name,
+            rtype: rtype.to_ascii_uppercase(),
+            content: content.to_string(),
+            ttl: ttl.or(default_ttl),
+            proxied,
+        });
+    }
+    Ok(records)
+}
+
+/// Fully qualified form of `name`, without the final `.`
+fn qualify(name: &str, origin: Option<&str>, location: &str) -> Result<String, ()> {
+    if let Some(name) = name.strip_suffix('.') {
+        return Ok(name.to_lowercase());
+    }
+    let Some(origin) = origin else {
+        error!("Name '{name}' at {location} is relative, but there is no `$ORIGIN` before it");
+        return Err(());
+    };
+    Ok(match name {
+        "@" => origin.to_string(),
+        name => format!("{}.{origin}", name.to_lowercase()),
+    })
+}
+
+/// Print the managed records of every configured zone as zone file lines
+pub fn run(config: &Config) -> Result<(), ()> {
+    let zones = config
+        .zones
+        .iter()
+        .map(|zone| ZoneClient::connect(zone, config, &[]))
+        .collect::<Result<Vec<_>, ()>>()?;
+
+    for mut zone in zones {
+        let records = zone.managed_records(&SystemClock)?;
+        print!("{}", render(zone.zone.name.as_deref(), &records));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging;
+
+    fn record(name: &str, ttl: Ttl, content: &str, proxied: bool) -> Record {
+        Record {
+            name: name.to_string(),
+            ttl,
+            content: content.parse().unwrap(),
+            proxied,
+        }
+    }
+
+    fn parsed(
+        name: &str,
+        rtype: &str,
+        content: &str,
+        ttl: Option<u32>,
+        proxied: bool,
+    ) -> ZoneFileRecord {
+        ZoneFileRecord {
+            name: name.to_string(),
+            rtype: rtype.to_string(),
+            content: content.to_string(),
+            ttl,
+            proxied: Some(proxied),
+        }
+    }
+
+    #[test]
+    fn reads_back_the_rendered_records() {
+        let records = [
+            record("example.com", Ttl::Seconds(300), "203.0.113.7", true),
+            record("WWW.example.com", Ttl::Auto, "2001:db8::7", false),
+            record("api.example.org", Ttl::Seconds(60), "198.51.100.1", false),
+        ];
+
+        let rendered = render(Some("example.com"), &records);
+
+        assert_eq!(
+            rendered.lines().take(3).collect::<Vec<_>>(),
+            [
+                "$ORIGIN example.com.",
+                "@                          300 IN A    203.0.113.7 ; proxied",
+                "www                            IN AAAA 2001:db8::7 ; not proxied",
+            ]
+        );
+        assert_eq!(
+            parse(&rendered, "export.zone"),
+            Ok(vec![
+                parsed("example.com", "A", "203.0.113.7", Some(300), true),
+                parsed("www.example.com", "AAAA", "2001:db8::7", None, false),
+                parsed("api.example.org", "A", "198.51.100.1", Some(60), false),
+            ])
+        );
+    }
+
+    #[test]
+    fn qualifies_the_names_and_applies_the_default_ttl() {
+        let text = "\
+$ORIGIN example.com.
+$TTL 600
+@        IN A    203.0.113.7
+         IN AAAA 2001:db8::7 ; proxied
+www 120  A       203.0.113.8
+$ORIGIN dev.example.com.
+api      IN A    203.0.113.9
+mail.example.net. IN A 203.0.113.10
+";
+
+        let records = parse(text, "records.zone").unwrap();
+
+        let names_and_ttls = records
+            .iter()
+            .map(|r| (r.name.as_str(), r.ttl, r.proxied))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names_and_ttls,
+            [
+                ("example.com", Some(600), None),
+                ("example.com", Some(600), Some(true)),
+                ("www.example.com", Some(120), None),
+                ("api.dev.example.com", Some(600), None),
+                ("mail.example.net", Some(600), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn refuses_the_unsupported_lines() {
+        logging::init_for_tests();
+        let parse_errors = |text| logging::capture_errors(|| parse(text, "records.zone")).1;
+
+        assert_eq!(
+            parse_errors("www IN A 203.0.113.7"),
+            [
+                "Name 'www' at line <1> of zone file 'records.zone' is relative, but there is no `$ORIGIN` before it"
+            ]
+        );
+        assert_eq!(
+            parse_errors("www.example.com. IN CNAME example.com."),
+            [
+                "Record 'www.example.com' at line <1> of zone file 'records.zone' has type 'CNAME', only `A` and \
+                `AAAA` are supported"
+            ]
+        );
+        assert_eq!(
+            parse_errors("$INCLUDE other.zone"),
+            ["Directive '$INCLUDE' at line <1> of zone file 'records.zone' is not supported"]
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! Detection of IPs which change too often.
+//!
+//! HTTP endpoints return the address the request came from, which for IPv6 is often a temporary (privacy) address that
+//! the OS replaces every few hours. The records then follow every new address. When the IP of a type changes more than
+//! `CF_DNS_IP_CHURN_THRESHOLD` times within `CF_DNS_IP_CHURN_WINDOW_SECONDS`, a warning is logged once for that type.
+
+use crate::{IpSource, RecordType};
+use log::warn;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Number of changes of the IP of a type within a window above which the IP is considered churning
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChurnLimit {
+    pub changes: u32,
+    pub window: Duration,
+}
+
+/// Times of the recent IP changes of each type
+#[derive(Debug, Default)]
+pub struct ChurnWatch {
+    changes: BTreeMap<RecordType, VecDeque<Instant>>,
+    /// Types for which the churn warning was already logged
+    warned: BTreeSet<RecordType>,
+}
+
+impl ChurnWatch {
+    /// Record that the IP of type `rtype`, detected from `source`, changed at `now`, and warn the first time it changed
+    /// more than `limit.changes` times within `limit.window`
+    ///
+    /// Returns whether the warning was logged.
+    pub fn record(
+        &mut self,
+        rtype: RecordType,
+        source: &IpSource,
+        now: Instant,
+        limit: &ChurnLimit,
+    ) -> bool {
+        let changes = self.changes.entry(rtype).or_default();
+        changes.push_back(now);
+        while changes
+            .front()
+            .is_some_and(|time| now.saturating_duration_since(*time) > limit.window)
+        {
+            changes.pop_front();
+        }
+
+        let warn = changes.len() > limit.changes as usize && self.warned.insert(rtype);
+        if warn {
+            let hint = match (rtype, source) {
+                (RecordType::AAAA, IpSource::Http(_)) => {
+                    ". This is likely a temporary (privacy) IPv6 address, consider reading a stable one from the \
+                    network interface with `IPV6_ENDPOINT=iface:<name>` and `CF_DNS_IPV6_SELECT=stable`"
+                }
+                _ => "",
+            };
+            warn!(
+                "'{rtype}' IP from '{source}' changed <{}> times within <{}> seconds{hint}",
+                changes.len(),
+                limit.window.as_secs()
+            );
+        }
+        warn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+
+    const LIMIT: ChurnLimit = ChurnLimit {
+        changes: 3,
+        window: Duration::from_secs(3600),
+    };
+
+    #[test]
+    fn warns_once_about_an_ip_which_changes_too_often() {
+        let source = IpSource::Http("https://ipv6.ip.test".to_string());
+        let clock = MockClock::new(Duration::ZERO);
+        let mut watch = ChurnWatch::default();
+        let mut change = |rtype, after| {
+            clock.advance(after);
+            watch.record(rtype, &source, clock.now(), &LIMIT)
+        };
+
+        // A new temporary address every 10 minutes
+        let warned = (0..6)
+            .map(|_| change(RecordType::AAAA, Duration::from_secs(600)))
+            .collect::<Vec<_>>();
+        assert_eq!(warned, [false, false, false, true, false, false]);
+        // The other type is watched on its own
+        assert!(!change(RecordType::A, Duration::ZERO));
+    }
+
+    #[test]
+    fn forgets_the_changes_older_than_the_window() {
+        let source = IpSource::Http("https://ipv6.ip.test".to_string());
+        let clock = MockClock::new(Duration::ZERO);
+        let mut watch = ChurnWatch::default();
+
+        // A new address every 20 minutes, so at most 3 changes within any hour
+        for _ in 0..10 {
+            clock.advance(Duration::from_secs(1201));
+            assert!(!watch.record(RecordType::AAAA, &source, clock.now(), &LIMIT));
+        }
+    }
+}
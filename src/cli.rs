@@ -0,0 +1,257 @@
+//! Command line arguments.
+//!
+//! Everything else is configured with environment variables, see the README.
+
+use crate::config::Config;
+use log::error;
+use std::env;
+use std::sync::OnceLock;
+
+static DRY_RUN: OnceLock<bool> = OnceLock::new();
+
+/// What the app was asked to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Keep the DNS records up to date
+    Run,
+    /// Query every IP endpoint once and report whether they agree
+    EndpointsTest,
+    /// Write a redacted diagnostic bundle to the given file, or to stdout
+    Diagnose(Option<String>),
+    /// Print the managed records as BIND zone file lines (`--export-bind`)
+    ExportBind,
+    /// Print the managed records with their live and desired TTL (`--list`)
+    List,
+    /// Mark the existing records of the hosts and desired records as managed (`--adopt`)
+    Adopt,
+}
+
+/// Parsed command line arguments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Args {
+    pub command: Command,
+    /// Whether every record is updated on the first cycle, even if it already has the desired state
+    pub force: bool,
+    /// Hosts which are reconciled during this run
+    pub hosts: HostFilter,
+    /// Whether the app exits after creating the missing records, without updating the existing ones
+    pub provision_only: bool,
+}
+
+/// Hosts given with `--only` and `--skip`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostFilter {
+    /// If not empty, the only hosts which are reconciled
+    only: Vec<String>,
+    /// Hosts which are not reconciled
+    skip: Vec<String>,
+}
+
+impl HostFilter {
+    fn is_empty(&self) -> bool {
+        self.only.is_empty() && self.skip.is_empty()
+    }
+
+    /// Disable the configured hosts which are excluded by the filter
+    ///
+    /// Excluded hosts are disabled rather than removed, so that their records are left alone instead of being pruned.
+    pub fn apply(&self, config: &mut Config) -> Result<(), ()> {
+        let hosts = config
+            .zones
+            .iter()
+            .flat_map(|zone| &zone.hosts)
+            .map(|host| host.name.as_str())
+            .collect::<Vec<_>>();
+        for (flag, names) in [("--only", &self.only), ("--skip", &self.skip)] {
+            if let Some(name) = names
+                .iter()
+                .find(|name| !hosts.iter().any(|host| host.eq_ignore_ascii_case(name)))
+            {
+                error!("Host '{name}' given to `{flag}` is not a configured host");
+                return Err(());
+            }
+        }
+
+        let contains =
+            |names: &[String], host: &str| names.iter().any(|name| name.eq_ignore_ascii_case(host));
+        for host in config.zones.iter_mut().flat_map(|zone| &mut zone.hosts) {
+            if (!self.only.is_empty() && !contains(&self.only, &host.name))
+                || contains(&self.skip, &host.name)
+            {
+                host.enabled = false;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether changes are only logged instead of being sent to Cloudflare (`--dry-run`)
+pub fn dry_run() -> bool {
+    DRY_RUN.get().copied().unwrap_or_default()
+}
+
+/// Parse the command line arguments
+pub fn parse() -> Result<Args, ()> {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let usage = || {
+        error!(
+            "Unknown arguments '{}'. Usage: {} [--force] [--dry-run] [--provision-only] [--only <host>]... \
+            [--skip <host>]... | --export-bind | --list | --adopt [--dry-run] | endpoints-test | diagnose [<file>]",
+            args.join(" "),
+            env!("CARGO_PKG_NAME")
+        );
+    };
+
+    let mut force = false;
+    let mut dry_run = false;
+    let mut provision_only = false;
+    // Command given as a flag instead of a positional argument
+    let mut flag_command = None;
+    let mut hosts = HostFilter::default();
+    let mut positional = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--force" => force = true,
+            "--dry-run" => dry_run = true,
+            "--provision-only" => provision_only = true,
+            "--export-bind" => flag_command = Some(Command::ExportBind),
+            "--list" => flag_command = Some(Command::List),
+            "--adopt" => flag_command = Some(Command::Adopt),
+            flag @ ("--only" | "--skip") => {
+                let Some(host) = iter.next() else {
+                    usage();
+                    return Err(());
+                };
+                let names = if flag == "--only" {
+                    &mut hosts.only
+                } else {
+                    &mut hosts.skip
+                };
+                names.push(host.trim().to_string());
+            }
+            arg => positional.push(arg),
+        }
+    }
+
+    let command = match (&positional[..], flag_command) {
+        ([], Some(command)) => command,
+        (_, Some(_)) => {
+            usage();
+            return Err(());
+        }
+        ([], None) => Command::Run,
+        (["endpoints-test"], None) => Command::EndpointsTest,
+        (["diagnose"], None) => Command::Diagnose(None),
+        (["diagnose", path], None) => Command::Diagnose(Some(path.to_string())),
+        _ => {
+            usage();
+            return Err(());
+        }
+    };
+    if command != Command::Run
+        && (force || (dry_run && command != Command::Adopt) || provision_only || !hosts.is_empty())
+    {
+        usage();
+        return Err(());
+    }
+
+    let _ = DRY_RUN.set(dry_run);
+    Ok(Args {
+        command,
+        force,
+        hosts,
+        provision_only,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RecordType;
+    use crate::config::{HostConfig, Manage, ZoneConfig, ZoneRef};
+    use crate::logging;
+
+    fn config(hosts: &[&str]) -> Config {
+        let mut config = Config::for_tests(&[RecordType::A]);
+        config.zones = vec![ZoneConfig {
+            zone_ref: ZoneRef::Id("zone".to_string()),
+            proxied_default: None,
+            hosts: hosts
+                .iter()
+                .map(|name| HostConfig {
+                    name: name.to_string(),
+                    proxied: None,
+                    webhook: None,
+                    enabled: true,
+                    manage: Manage::Full,
+                })
+                .collect(),
+            auth: None,
+            records: None,
+        }];
+        config
+    }
+
+    fn enabled(config: &Config) -> Vec<&str> {
+        config.zones[0]
+            .hosts
+            .iter()
+            .filter(|host| host.enabled)
+            .map(|host| host.name.as_str())
+            .collect()
+    }
+
+    fn filter(only: &[&str], skip: &[&str]) -> HostFilter {
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        HostFilter {
+            only: names(only),
+            skip: names(skip),
+        }
+    }
+
+    #[test]
+    fn disables_the_hosts_excluded_by_the_filter() {
+        let hosts = ["www.example.com", "api.example.com", "mail.example.com"];
+
+        let mut all = config(&hosts);
+        assert_eq!(filter(&[], &[]).apply(&mut all), Ok(()));
+        assert_eq!(enabled(&all), hosts);
+
+        let mut only = config(&hosts);
+        let only_filter = filter(&["WWW.example.com", "api.example.com"], &[]);
+        assert_eq!(only_filter.apply(&mut only), Ok(()));
+        assert_eq!(enabled(&only), ["www.example.com", "api.example.com"]);
+
+        let mut skipped = config(&hosts);
+        assert_eq!(
+            filter(&[], &["api.example.com"]).apply(&mut skipped),
+            Ok(())
+        );
+        assert_eq!(enabled(&skipped), ["www.example.com", "mail.example.com"]);
+
+        let mut both = config(&hosts);
+        let both_filter = filter(
+            &["www.example.com", "api.example.com"],
+            &["api.example.com"],
+        );
+        assert_eq!(both_filter.apply(&mut both), Ok(()));
+        assert_eq!(enabled(&both), ["www.example.com"]);
+    }
+
+    #[test]
+    fn refuses_a_filtered_host_which_is_not_configured() {
+        logging::init_for_tests();
+        let mut config = config(&["www.example.com"]);
+
+        let (applied, errors) =
+            logging::capture_errors(|| filter(&[], &["ftp.example.com"]).apply(&mut config));
+
+        assert_eq!(applied, Err(()));
+        assert_eq!(
+            errors,
+            ["Host 'ftp.example.com' given to `--skip` is not a configured host"]
+        );
+        assert_eq!(enabled(&config), ["www.example.com"]);
+    }
+}
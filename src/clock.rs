@@ -0,0 +1,182 @@
+//! Time source for the scheduling logic.
+//!
+//! All interval related code, and the retry waits of the Cloudflare API requests, get the current time and sleep
+//! through a [`Clock`], so that they do not depend on `std::time` directly. Tests use a `MockClock` instead, which
+//! only advances virtually.
+
+use crate::shutdown;
+use std::time::{Duration, Instant, SystemTime};
+
+pub trait Clock {
+    /// Current time
+    fn now(&self) -> Instant;
+
+    /// Current wall-clock time
+    fn wall_now(&self) -> SystemTime;
+
+    /// Block for the given duration
+    fn sleep(&self, duration: Duration);
+}
+
+/// [`Clock`] backed by the system's monotonic clock. Sleeping is interrupted when a shutdown is requested.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn wall_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        shutdown::wait(duration);
+    }
+}
+
+/// [`Clock`] which only advances when it sleeps or is advanced, for tests
+#[cfg(test)]
+#[derive(Debug)]
+pub struct MockClock {
+    now: std::cell::Cell<Instant>,
+    wall_now: std::cell::Cell<SystemTime>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    /// Clock whose wall-clock time is `since_epoch` after the Unix epoch
+    pub fn new(since_epoch: Duration) -> Self {
+        Self {
+            now: std::cell::Cell::new(Instant::now()),
+            wall_now: std::cell::Cell::new(SystemTime::UNIX_EPOCH + since_epoch),
+        }
+    }
+
+    /// Move the time forward by `duration`, as if an operation took that long
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+        self.wall_now.set(self.wall_now.get() + duration);
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
+
+    fn wall_now(&self) -> SystemTime {
+        self.wall_now.get()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+/// Time left to sleep until the next cycle, so that cycles start every `interval` regardless of how long each one
+/// takes.
+///
+/// Returns `None` if the cycle which started at `cycle_start` already took longer than `interval`.
+pub fn time_until_next_cycle(
+    clock: &impl Clock,
+    cycle_start: Instant,
+    interval: Duration,
+) -> Option<Duration> {
+    interval.checked_sub(clock.now().saturating_duration_since(cycle_start))
+}
+
+/// Time left to sleep until the next wall-clock time which is a multiple of `interval` since the Unix epoch (e.g. every
+/// `:00` and `:30` for 30 minutes), so that cycles start at the same times on every host.
+///
+/// The wall-clock time is read again for every cycle, so that the alignment follows clock adjustments. Being computed
+/// in UTC, it is not affected by DST changes.
+pub fn time_until_aligned(clock: &impl Clock, interval: Duration) -> Duration {
+    let since_epoch = clock
+        .wall_now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let interval_nanos = interval.as_nanos().max(1);
+    let remaining = interval_nanos - since_epoch.as_nanos() % interval_nanos;
+    Duration::from_nanos(remaining as u64)
+}
+
+/// Point in time after which a run is aborted. Operations check it before they start, so an operation which is
+/// already in progress is only bounded by its own timeout.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Deadline(Option<Instant>);
+
+impl Deadline {
+    /// Deadline `timeout` from now, or no deadline if `timeout` is `None`
+    pub fn after(clock: &impl Clock, timeout: Option<Duration>) -> Self {
+        Self(timeout.map(|timeout| clock.now() + timeout))
+    }
+
+    /// Whether the deadline has passed
+    pub fn expired(&self, clock: &impl Clock) -> bool {
+        self.0.is_some_and(|deadline| clock.now() >= deadline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycles_start_at_a_fixed_interval_whatever_their_duration() {
+        let clock = MockClock::new(Duration::ZERO);
+        let interval = Duration::from_secs(60);
+        let first_start = clock.now();
+
+        for (cycle, duration) in [5, 42, 0, 59].into_iter().enumerate() {
+            let cycle_start = clock.now();
+            assert_eq!(
+                cycle_start.duration_since(first_start),
+                interval * cycle as u32
+            );
+            clock.advance(Duration::from_secs(duration));
+            let remaining = time_until_next_cycle(&clock, cycle_start, interval).unwrap();
+            assert_eq!(remaining, interval - Duration::from_secs(duration));
+            clock.sleep(remaining);
+        }
+    }
+
+    #[test]
+    fn a_cycle_longer_than_the_interval_has_no_time_left() {
+        let clock = MockClock::new(Duration::ZERO);
+        let cycle_start = clock.now();
+        clock.advance(Duration::from_secs(61));
+        assert_eq!(
+            time_until_next_cycle(&clock, cycle_start, Duration::from_secs(60)),
+            None
+        );
+    }
+
+    #[test]
+    fn aligned_cycles_start_at_multiples_of_the_interval() {
+        let clock = MockClock::new(Duration::from_secs(17 * 60 + 30));
+        let interval = Duration::from_secs(30 * 60);
+        assert_eq!(
+            time_until_aligned(&clock, interval),
+            Duration::from_secs(12 * 60 + 30)
+        );
+
+        // Right on an aligned time, the next one is a whole interval away
+        clock.sleep(time_until_aligned(&clock, interval));
+        assert_eq!(time_until_aligned(&clock, interval), interval);
+    }
+
+    #[test]
+    fn deadline_expires_after_its_timeout() {
+        let clock = MockClock::new(Duration::ZERO);
+        let deadline = Deadline::after(&clock, Some(Duration::from_secs(10)));
+        clock.advance(Duration::from_secs(9));
+        assert!(!deadline.expired(&clock));
+        clock.advance(Duration::from_secs(1));
+        assert!(deadline.expired(&clock));
+
+        assert!(!Deadline::after(&clock, None).expired(&clock));
+    }
+}
@@ -0,0 +1,1965 @@
+//! Cloudflare API requests.
+
+use crate::clock::{Clock, SystemClock};
+use crate::config::Config;
+use crate::provider::DnsProvider;
+use crate::shutdown;
+use crate::{CfRecord, Record, RecordType, Ttl};
+use log::{Level, debug, error, info, log_enabled, warn};
+use reqwest::blocking::{RequestBuilder, Response};
+use reqwest::header::HeaderMap;
+use reqwest::{Method, ResponseBuilderExt, StatusCode};
+use serde_json::{Map, Value, json};
+use std::fmt::{Debug, Display};
+use std::fs;
+use std::future::{Future, ready};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::pin::pin;
+use std::str::FromStr;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Credentials of the Cloudflare API
+#[derive(Clone, PartialEq, Eq)]
+pub enum Auth {
+    /// Scoped API token, sent as a bearer token
+    Token(String),
+    /// Account email and global API key, the legacy scheme
+    GlobalKey { email: String, key: String },
+}
+
+impl Auth {
+    /// Authentication headers of a request, as names and values
+    fn headers(&self) -> Vec<(&'static str, String)> {
+        match self {
+            Auth::Token(token) => vec![("Authorization", format!("Bearer {token}"))],
+            Auth::GlobalKey { email, key } => {
+                vec![("X-Auth-Email", email.clone()), ("X-Auth-Key", key.clone())]
+            }
+        }
+    }
+
+    /// The secret part of the credentials, which must never be logged
+    pub fn secret(&self) -> &str {
+        match self {
+            Auth::Token(token) => token,
+            Auth::GlobalKey { key, .. } => key,
+        }
+    }
+
+    /// Replace the secret, and the email of a global API key, wherever they appear in `text`
+    fn redact(&self, text: &str) -> String {
+        let text = match self.secret() {
+            "" => text.to_string(),
+            secret => text.replace(secret, "<credentials>"),
+        };
+        match self {
+            Auth::GlobalKey { email, .. } if !email.is_empty() => {
+                text.replace(email, "<auth email>")
+            }
+            _ => text,
+        }
+    }
+}
+
+/// Shows the kind of credentials, but never the secret
+impl std::fmt::Debug for Auth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Auth::Token(_) => write!(f, "Token(<redacted>)"),
+            Auth::GlobalKey { email, .. } => {
+                write!(f, "GlobalKey {{ email: {email:?}, key: <redacted> }}")
+            }
+        }
+    }
+}
+
+/// URL of the Cloudflare API, to which the request paths are appended
+pub const API_URL: &str = "https://api.cloudflare.com/client/v4";
+
+/// Header carrying the id generated by this tool for each Cloudflare API request
+const REQUEST_ID_HEADER: &str = "X-Cf-Dns-Rs-Request-Id";
+
+/// Headers set by the app on every API request, which can't be given as extra headers
+pub const RESERVED_HEADERS: [&str; 5] = [
+    "Authorization",
+    "X-Auth-Email",
+    "X-Auth-Key",
+    "Content-Type",
+    REQUEST_ID_HEADER,
+];
+
+/// Ids of a single Cloudflare API request, which can be used to find it in Cloudflare's audit log or to reference it
+/// in a support ticket
+#[derive(Debug, Clone)]
+pub struct RequestIds {
+    /// Id generated by this tool, sent in the [`REQUEST_ID_HEADER`] header
+    request_id: String,
+    /// Id assigned by Cloudflare, returned in the `cf-ray` response header
+    cf_ray: Option<String>,
+}
+
+impl RequestIds {
+    /// Ids of a new request, which has no `cf-ray` id until its response is received
+    pub fn new() -> Self {
+        Self {
+            request_id: Uuid::new_v4().to_string(),
+            cf_ray: None,
+        }
+    }
+
+    /// Take the `cf-ray` id from the `headers` of the response
+    pub fn received(&mut self, headers: &HeaderMap) {
+        self.cf_ray = cf_ray(headers);
+    }
+}
+
+impl Display for RequestIds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.cf_ray {
+            Some(cf_ray) => write!(f, "request id '{}', cf-ray '{cf_ray}'", self.request_id),
+            None => write!(f, "request id '{}'", self.request_id),
+        }
+    }
+}
+
+/// Cloudflare error code returned when the API token can't be used from the IP the request comes from
+const IP_NOT_ALLOWED_CODE: u64 = 9109;
+
+/// Number of times a rate limited request is sent again
+pub const RATE_LIMIT_RETRIES: u32 = 2;
+
+/// Wait before sending a rate limited request again, when its `Retry-After` header is missing or can't be parsed
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(10);
+
+/// Failure of a Cloudflare API request
+#[derive(Debug)]
+pub enum CfError {
+    /// The request could not be sent, or its response could not be received
+    Request(reqwest::Error),
+    /// The API token is not allowed to make the request
+    Forbidden(String),
+    /// The API token's IP allow-list doesn't include the IP the request comes from. This is not transient, so the
+    /// request should not be retried.
+    IpNotAllowed(String),
+}
+
+impl Display for CfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CfError::Request(e) => write!(f, "{e}"),
+            CfError::Forbidden(body) => write!(f, "access denied: {body}"),
+            CfError::IpNotAllowed(message) => write!(
+                f,
+                "{message}. Your API token's IP allow-list (Client IP Address Filtering) may not include this host's IP"
+            ),
+        }
+    }
+}
+
+/// Credentials and settings of the Cloudflare API requests
+#[derive(Debug, Clone)]
+pub struct CfApi {
+    pub auth: Auth,
+    /// URL of the API, to which the request paths are appended
+    pub api_url: String,
+    /// JSON pointer to the errors in the API responses, e.g. when the API is behind a gateway which wraps them
+    pub error_pointer: String,
+    /// Maximum length of the API response bodies which are logged, `None` for no limit
+    pub max_log_body: Option<usize>,
+    /// Directory to which every request and its response are written when logging at debug level
+    pub dump_dir: Option<String>,
+    /// Headers added to every API request, e.g. for an auditing proxy. They must not be any of the
+    /// [`RESERVED_HEADERS`].
+    pub extra_headers: Vec<(String, String)>,
+    /// Longest wait before sending a rate limited request again, whatever its `Retry-After` header asks for
+    pub retry_after_max: Duration,
+    /// Number of times an idempotent request is sent again after a network failure
+    pub network_retries: u32,
+    /// Wait before sending a request again after a network failure, multiplied by the number of the attempt
+    pub network_retry_wait: Duration,
+    /// Tag added to the records which are created or updated
+    pub managed_tag: Option<String>,
+}
+
+impl CfApi {
+    /// Requests authenticated with `auth`, with the default settings
+    #[cfg(any(test, feature = "async"))]
+    pub fn new(auth: Auth) -> Self {
+        Self {
+            auth,
+            api_url: API_URL.to_string(),
+            error_pointer: "/errors".to_string(),
+            max_log_body: None,
+            dump_dir: None,
+            extra_headers: Vec::new(),
+            retry_after_max: Duration::from_secs(300),
+            network_retries: 2,
+            network_retry_wait: Duration::from_secs(1),
+            managed_tag: None,
+        }
+    }
+
+    /// Requests authenticated with `auth`, with the settings of `config`
+    pub fn with_config(auth: &Auth, config: &Config) -> Self {
+        Self {
+            auth: auth.clone(),
+            api_url: API_URL.to_string(),
+            error_pointer: config.error_pointer.clone(),
+            max_log_body: config.max_log_body,
+            dump_dir: config.dump_responses_dir.clone(),
+            extra_headers: config.extra_headers.clone(),
+            retry_after_max: config.retry_after_max,
+            network_retries: config.network_retries,
+            network_retry_wait: config.network_retry_wait,
+            managed_tag: config.managed_tag.clone(),
+        }
+    }
+}
+
+/// Headers of the API request with the given `ids`: the extra, authentication, content type and request id headers
+pub fn cf_headers(api: &CfApi, ids: &RequestIds) -> Vec<(String, String)> {
+    let mut headers = api.extra_headers.clone();
+    headers.extend(
+        api.auth
+            .headers()
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value)),
+    );
+    headers.push(("Content-Type".to_string(), "application/json".to_string()));
+    headers.push((REQUEST_ID_HEADER.to_string(), ids.request_id.clone()));
+    headers
+}
+
+/// Whether a request with `method` can be sent again after a network failure, which may have happened after it was
+/// applied
+///
+/// `PATCH` is not idempotent in general, but every `PATCH` request sets fields of a record by id, so applying it twice
+/// is the same as applying it once. A `POST` is never sent again, since it may create a duplicate.
+pub fn retries_network_failure(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::PATCH | Method::DELETE
+    )
+}
+
+/// Wait asked for by the `Retry-After` header of a rate limited response, received at `now`, clamped to `0..=max`
+///
+/// The header is either a number of seconds, which is preferred since it doesn't depend on the clocks being in sync, or
+/// an HTTP date. A date in the past, e.g. because the local clock is ahead, means no wait. A missing or unparseable
+/// header gives the [`DEFAULT_RETRY_AFTER`].
+pub fn retry_after(headers: &HeaderMap, now: jiff::Timestamp, max: Duration) -> Duration {
+    let Some(value) = headers.get("retry-after") else {
+        return DEFAULT_RETRY_AFTER.min(max);
+    };
+    let raw = value.to_str().unwrap_or_default().trim();
+
+    let wait = if let Ok(seconds) = raw.parse::<u64>() {
+        Duration::from_secs(seconds)
+    } else if let Ok(date) = jiff::fmt::rfc2822::parse(raw) {
+        let until = date.timestamp().duration_since(now);
+        Duration::try_from(until).unwrap_or(Duration::ZERO)
+    } else {
+        warn!(
+            "Could not parse the `Retry-After` header '{raw}' of a rate limited response, waiting <{}> seconds",
+            DEFAULT_RETRY_AFTER.as_secs()
+        );
+        DEFAULT_RETRY_AFTER
+    };
+    wait.min(max)
+}
+
+/// `body` truncated to the maximum logged length, with an ellipsis and the number of characters left out
+fn cf_truncated(body: String, api: &CfApi) -> String {
+    match api
+        .max_log_body
+        .and_then(|max| body.char_indices().nth(max))
+    {
+        Some((end, _)) => format!(
+            "{}... (<{}> more characters)",
+            &body[..end],
+            body[end..].chars().count()
+        ),
+        None => body,
+    }
+}
+
+/// Codes and messages of the errors in an API response
+///
+/// The value at the error pointer is either an array of Cloudflare errors (with a `code` and `message`), a single
+/// such error, or a message string.
+fn cf_errors(json: &Value, api: &CfApi) -> Vec<(Option<u64>, String)> {
+    let error = |e: &Value| match e {
+        Value::String(message) => (None, message.clone()),
+        e => (
+            e["code"].as_u64(),
+            e["message"]
+                .as_str()
+                .map(|message| message.to_string())
+                .unwrap_or_else(|| e.to_string()),
+        ),
+    };
+
+    match json.pointer(&api.error_pointer) {
+        Some(Value::Array(errors)) => errors.iter().map(error).collect(),
+        Some(Value::Null) | None => Vec::new(),
+        Some(e) => vec![error(e)],
+    }
+}
+
+/// Error messages of a failed API response, or its whole body if it has no errors at the error pointer
+fn cf_error_message(res: Response, api: &CfApi) -> String {
+    match res.text() {
+        Ok(body) => cf_body_error_message(body, api),
+        Err(e) => e.to_string(),
+    }
+}
+
+/// Error messages of the `body` of a failed API response, or the whole body if it has no errors at the error pointer
+pub fn cf_body_error_message(body: String, api: &CfApi) -> String {
+    serde_json::from_str::<Value>(&body)
+        .ok()
+        .filter(|json| !cf_errors(json, api).is_empty())
+        .and_then(|json| cf_failure(&json, api))
+        .unwrap_or_else(|| cf_truncated(body, api))
+}
+
+/// Error messages of a response whose `success` is `false` or which has errors, `None` if it succeeded
+pub fn cf_failure(json: &Value, api: &CfApi) -> Option<String> {
+    let errors = cf_errors(json, api);
+    if json["success"].as_bool() != Some(false) && errors.is_empty() {
+        return None;
+    }
+    if errors.is_empty() {
+        return Some("request failed without an error message".to_string());
+    }
+
+    Some(
+        errors
+            .into_iter()
+            .map(|(code, message)| match code {
+                Some(code) => format!("{message} (code {code})"),
+                None => message,
+            })
+            .collect::<Vec<_>>()
+            .join(", "),
+    )
+}
+
+/// Pretty printed response, with the credentials redacted in case they are echoed back
+pub fn cf_redacted(json: &Value, api: &CfApi) -> String {
+    cf_truncated(
+        api.auth
+            .redact(&serde_json::to_string_pretty(json).unwrap_or_default()),
+        api,
+    )
+}
+
+/// Turn a `403 Forbidden` response into the matching [`CfError`]
+fn cf_forbidden(res: Response, api: &CfApi) -> CfError {
+    let body = res.text().unwrap_or_default();
+    let not_allowed = serde_json::from_str::<Value>(&body).ok().and_then(|json| {
+        cf_errors(&json, api)
+            .into_iter()
+            .find(|(code, _)| *code == Some(IP_NOT_ALLOWED_CODE))
+            .map(|(_, message)| message)
+    });
+
+    match not_allowed {
+        Some(message) => CfError::IpNotAllowed(message),
+        None => CfError::Forbidden(cf_truncated(body, api)),
+    }
+}
+
+/// Extract the `cf-ray` id from the headers of a Cloudflare API response
+fn cf_ray(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("cf-ray")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
+
+/// Send a request to the Cloudflare API
+///
+/// Adds the extra, authentication, content type and request id headers to the request. The returned [`RequestIds`] should
+/// be included in any log message about the request. A `403 Forbidden` response is returned as an error. A
+/// `429 Too Many Requests` response is retried after the wait its `Retry-After` header asks for. A network failure is
+/// only retried for idempotent requests (e.g. `GET`, `PATCH` and `DELETE` by id): a `POST` which failed after being
+/// sent may have been applied, see [`cf_create_record`].
+fn cf_send(
+    mut request: RequestBuilder,
+    api: &CfApi,
+    clock: &impl Clock,
+) -> (Result<Response, CfError>, RequestIds) {
+    let mut ids = RequestIds::new();
+    for (name, value) in cf_headers(api, &ids) {
+        request = request.header(name, value);
+    }
+    let retryable = request
+        .try_clone()
+        .and_then(|request| request.build().ok())
+        .is_some_and(|request| retries_network_failure(request.method()));
+    let (network_retries, network_retry_wait) = (api.network_retries, api.network_retry_wait);
+    let mut attempt = 0;
+    let mut network_attempt = 0;
+    let res = loop {
+        let retry = request.try_clone();
+        let res = match &api.dump_dir {
+            Some(dir) if log_enabled!(Level::Debug) => {
+                cf_send_dumped(request, api, dir, &ids, clock)
+            }
+            _ => request.send(),
+        };
+        match (&res, retry) {
+            (Ok(r), Some(retry))
+                if r.status() == StatusCode::TOO_MANY_REQUESTS && attempt < RATE_LIMIT_RETRIES =>
+            {
+                attempt += 1;
+                let now = jiff::Timestamp::try_from(clock.wall_now()).unwrap_or_default();
+                let wait = retry_after(r.headers(), now, api.retry_after_max);
+                warn!(
+                    "Rate limited by the Cloudflare API, retrying in <{}> seconds ({attempt}/{RATE_LIMIT_RETRIES}) \
+                    ({ids})",
+                    wait.as_secs()
+                );
+                clock.sleep(wait);
+                if shutdown::requested() {
+                    break res;
+                }
+                request = retry;
+            }
+            (Err(e), Some(retry)) if retryable && network_attempt < network_retries => {
+                network_attempt += 1;
+                let wait = network_retry_wait * network_attempt;
+                warn!(
+                    "Cloudflare API request failed: {e}, retrying in <{}> ms ({network_attempt}/\
+                    {network_retries}) ({ids})",
+                    wait.as_millis()
+                );
+                clock.sleep(wait);
+                if shutdown::requested() {
+                    break res;
+                }
+                request = retry;
+            }
+            _ => break res,
+        }
+    };
+
+    match &res {
+        Ok(r) => {
+            ids.received(r.headers());
+            debug!(
+                "Cloudflare API '{}' returned '{}' ({ids})",
+                r.url(),
+                r.status()
+            );
+        }
+        Err(e) => debug!("Cloudflare API request failed: {e} ({ids})"),
+    }
+
+    let res = match res {
+        Ok(r) if r.status() == StatusCode::FORBIDDEN => Err(cf_forbidden(r, api)),
+        res => res.map_err(CfError::Request),
+    };
+    (res, ids)
+}
+
+/// Send `request`, and write it together with its response to a file in `dir`, with the credentials redacted
+///
+/// The response body is read to be written, so the returned response is rebuilt from it.
+fn cf_send_dumped(
+    request: RequestBuilder,
+    api: &CfApi,
+    dir: &str,
+    ids: &RequestIds,
+    clock: &impl Clock,
+) -> reqwest::Result<Response> {
+    let mut dump = String::new();
+    if let Some(Ok(request)) = request.try_clone().map(|r| r.build()) {
+        dump.push_str(&format!("> {} {}\n", request.method(), request.url()));
+        let body = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .map(String::from_utf8_lossy)
+            .unwrap_or_default();
+        dump.push_str(&format!("{body}\n\n"));
+    }
+
+    let res = request.send()?;
+    let status = res.status();
+    let url = res.url().clone();
+    let headers = res.headers().clone();
+    let body = res.bytes()?;
+
+    dump.push_str(&format!("< {status}\n"));
+    for (name, value) in &headers {
+        dump.push_str(&format!(
+            "< {name}: {}\n",
+            value.to_str().unwrap_or_default()
+        ));
+    }
+    dump.push_str(&format!("{}\n", String::from_utf8_lossy(&body)));
+
+    let path = format!(
+        "{dir}/{}-{}.txt",
+        jiff::Timestamp::try_from(clock.wall_now())
+            .unwrap_or_default()
+            .strftime("%Y%m%dT%H%M%S%.fZ"),
+        ids.request_id
+    );
+    match fs::write(&path, api.auth.redact(&dump)) {
+        Ok(_) => debug!("Wrote the request to '{url}' and its response to '{path}' ({ids})"),
+        Err(e) => warn!(
+            "Could not write the request to '{url}' and its response to '{path}': {e} ({ids})"
+        ),
+    }
+
+    let mut rebuilt = http::Response::builder().status(status);
+    if let Some(rebuilt_headers) = rebuilt.headers_mut() {
+        *rebuilt_headers = headers;
+    }
+    let rebuilt = rebuilt
+        .url(url)
+        .body(body)
+        .unwrap_or_else(|_| http::Response::new(Default::default()));
+    Ok(Response::from(rebuilt))
+}
+
+/// [`DnsProvider`] for the zones of a Cloudflare account
+#[derive(Debug, Clone)]
+pub struct CloudflareProvider<C = SystemClock> {
+    api: CfApi,
+    /// Clock of the retry waits
+    clock: C,
+}
+
+impl CloudflareProvider {
+    pub fn new(api: CfApi) -> Self {
+        Self {
+            api,
+            clock: SystemClock,
+        }
+    }
+}
+
+impl<C: Clock + Debug> DnsProvider for CloudflareProvider<C> {
+    fn list_records(&self, zone_id: &str) -> Result<Vec<CfRecord>, ()> {
+        cf_get_records(zone_id, None, &self.api, &self.clock)
+    }
+
+    fn list_tagged_records(&self, zone_id: &str, tag: &str) -> Result<Vec<CfRecord>, ()> {
+        cf_get_records(zone_id, Some(tag), &self.api, &self.clock)
+    }
+
+    fn get_record(&self, zone_id: &str, record_id: &str) -> Result<CfRecord, ()> {
+        cf_get_record(zone_id, record_id, &self.api, &self.clock)
+    }
+
+    fn create_record(
+        &self,
+        zone_id: &str,
+        record: &Record,
+        comment: Option<&str>,
+    ) -> Result<String, ()> {
+        cf_create_record(record, comment, zone_id, &self.api, &self.clock)
+    }
+
+    fn update_record_ip(
+        &self,
+        zone_id: &str,
+        current: &CfRecord,
+        ip: &IpAddr,
+        comment: Option<&str>,
+    ) -> Result<(), ()> {
+        cf_update_record_ip(
+            zone_id,
+            &current.id,
+            ip,
+            comment,
+            &current.extra,
+            &self.api,
+            &self.clock,
+        )
+    }
+
+    fn update_record(
+        &self,
+        zone_id: &str,
+        current: &CfRecord,
+        record: &Record,
+        comment: &str,
+    ) -> Result<(), ()> {
+        cf_update_record(
+            zone_id,
+            &current.id,
+            record,
+            comment,
+            &current.extra,
+            &self.api,
+            &self.clock,
+        )
+    }
+
+    fn delete_record(&self, zone_id: &str, record_id: &str) -> Result<(), ()> {
+        cf_delete_record(zone_id, record_id, &self.api, &self.clock)
+    }
+
+    fn verify_token(&self) -> Result<String, ()> {
+        cf_verify_token(&self.api, &self.clock)
+    }
+}
+
+/// Fields of a record which are read-only, or which are parsed into [`CfRecord`], and are not kept in
+/// [`CfRecord::extra`]
+const PARSED_FIELDS: [&str; 15] = [
+    "id",
+    "type",
+    "name",
+    "content",
+    "ttl",
+    "proxied",
+    "proxiable",
+    "locked",
+    "comment",
+    "meta",
+    "zone_id",
+    "zone_name",
+    "created_on",
+    "modified_on",
+    "comment_modified_on",
+];
+
+/// Update a Cloudflare DNS record
+///
+/// The record comment is only changed if a `comment` is given. The `extra` fields are sent back unchanged.
+pub fn cf_update_record_ip(
+    zone_id: &str,
+    record_id: &str,
+    ip: &IpAddr,
+    comment: Option<&str>,
+    extra: &Map<String, Value>,
+    api: &CfApi,
+    clock: &impl Clock,
+) -> Result<(), ()> {
+    let client = reqwest::blocking::Client::new();
+
+    let url = format!(
+        "{}/zones/{}/dns_records/{}",
+        api.api_url, zone_id, record_id
+    );
+
+    let mut body = Value::Object(extra.clone());
+    body["content"] = json!(ip);
+    if let Some(comment) = comment {
+        body["comment"] = json!(comment);
+    }
+    cf_add_tag(&mut body, api.managed_tag.as_deref());
+
+    let (res, ids) = cf_send(client.patch(&url).json(&body), api, clock);
+    let res = res.map_err(|e| {
+        error!("Could not update record with id '{record_id}': {e} ({ids})");
+    })?;
+
+    if res.status().is_success() {
+        Ok(())
+    } else {
+        error!(
+            "Failed to update record: {} ({ids})",
+            cf_error_message(res, api)
+        );
+        Err(())
+    }
+}
+
+/// Set the name, content, TTL, proxied status and comment of a Cloudflare DNS record
+///
+/// The `extra` fields are sent back unchanged.
+pub fn cf_update_record(
+    zone_id: &str,
+    record_id: &str,
+    record: &Record,
+    comment: &str,
+    extra: &Map<String, Value>,
+    api: &CfApi,
+    clock: &impl Clock,
+) -> Result<(), ()> {
+    let client = reqwest::blocking::Client::new();
+    let url = format!("{}/zones/{zone_id}/dns_records/{record_id}", api.api_url);
+
+    let body = cf_update_body(record, comment, extra, api.managed_tag.as_deref());
+    let (res, ids) = cf_send(client.patch(&url).json(&body), api, clock);
+    let res = res.map_err(|e| {
+        error!("Could not update record with id '{record_id}': {e} ({ids})");
+    })?;
+
+    if res.status().is_success() {
+        Ok(())
+    } else {
+        error!(
+            "Failed to update record: {} ({ids})",
+            cf_error_message(res, api)
+        );
+        Err(())
+    }
+}
+
+/// Body of the request setting the name, content, TTL, proxied status and comment of a record, with its `extra`
+/// fields unchanged besides the optional `tag`
+pub fn cf_update_body(
+    record: &Record,
+    comment: &str,
+    extra: &Map<String, Value>,
+    tag: Option<&str>,
+) -> Value {
+    let mut body = Value::Object(extra.clone());
+    body["name"] = json!(record.name);
+    body["content"] = json!(record.content.to_string());
+    body["ttl"] = json!(u32::from(record.ttl));
+    body["proxied"] = json!(record.proxied);
+    body["comment"] = json!(comment);
+    cf_add_tag(&mut body, tag);
+    body
+}
+
+/// Add `tag` to the tags of the record `body`, unless it already has it
+fn cf_add_tag(body: &mut Value, tag: Option<&str>) {
+    let Some(tag) = tag else {
+        return;
+    };
+    if !body["tags"].is_array() {
+        body["tags"] = json!([]);
+    }
+    if let Some(tags) = body["tags"].as_array_mut()
+        && !tags.iter().any(|t| t.as_str() == Some(tag))
+    {
+        tags.push(json!(tag));
+    }
+}
+
+/// Body of the request creating `record`, with an optional comment and tag
+pub fn cf_create_body(record: &Record, comment: Option<&str>, tag: Option<&str>) -> Value {
+    let mut body = serde_json::json!({
+        "name": record.name,
+        "type": record.rtype().to_string(),
+        "content": record.content.to_string(),
+        "ttl": u32::from(record.ttl) ,
+        "proxied": record.proxied
+    });
+    if let Some(comment) = comment {
+        body["comment"] = json!(comment);
+    }
+    cf_add_tag(&mut body, tag);
+    body
+}
+
+/// Outcome of a request sent by a [`CreateTransport`]
+pub enum Sent {
+    /// Parsed response, or `Err` if the request failed, which is already logged
+    Response(Result<Value, ()>),
+    /// The request failed before its response was received: it may or may not have been applied
+    Lost { error: String, ids: RequestIds },
+}
+
+/// How the requests creating a record are sent, by the blocking or the async client
+///
+/// The requests are made by [`cf_create_with`], so that both clients handle a lost response the same way.
+pub trait CreateTransport {
+    /// Send the `POST` request creating a record in the zone with `body`
+    fn post(&self, zone_id: &str, body: &Value) -> impl Future<Output = Sent>;
+
+    /// Send the `GET` request listing the records of the zone which match `query`, logging why it failed if it did
+    fn find(
+        &self,
+        zone_id: &str,
+        query: &[(&'static str, String)],
+    ) -> impl Future<Output = Result<Value, ()>>;
+}
+
+/// Create `record` through `transport`, with an optional comment, and return its id
+///
+/// When the response to the creation is lost, the record may have been created all the same: it is looked up, and only
+/// created again if it isn't found, so that no duplicate is created.
+pub async fn cf_create_with(
+    transport: &impl CreateTransport,
+    record: &Record,
+    comment: Option<&str>,
+    zone_id: &str,
+    api: &CfApi,
+) -> Result<String, ()> {
+    let body = cf_create_body(record, comment, api.managed_tag.as_deref());
+    let mut sent = transport.post(zone_id, &body).await;
+    if let Sent::Lost { error, ids } = &sent {
+        warn!(
+            "Could not create DNS record for host '{}' with ip '{}': {error}, checking whether it was created ({ids})",
+            record.name, record.content
+        );
+        let found = transport.find(zone_id, &cf_find_query(record)).await?;
+        if let Some(id) = found["result"]
+            .as_array()
+            .and_then(|records| records.first())
+            .and_then(|record| record["id"].as_str())
+        {
+            info!(
+                "DNS record for host '{}' with ip '{}' was created although its response was lost",
+                record.name, record.content
+            );
+            return Ok(id.to_string());
+        }
+        sent = transport.post(zone_id, &body).await;
+    }
+
+    let json = match sent {
+        Sent::Response(json) => json?,
+        Sent::Lost { error, ids } => {
+            error!(
+                "Could not create DNS record for host '{}' with ip '{}': {error} ({ids})",
+                record.name, record.content
+            );
+            return Err(());
+        }
+    };
+    match json["result"]["id"].as_str() {
+        Some(id) => Ok(id.to_string()),
+        None => {
+            error!(
+                "Created DNS record for host '{}' has no id:\n{}",
+                record.name,
+                cf_redacted(&json, api)
+            );
+            Err(())
+        }
+    }
+}
+
+/// Run a future which never waits, such as the ones of the blocking [`CreateTransport`]
+fn cf_block_on<F: Future>(future: F) -> F::Output {
+    let mut context = Context::from_waker(Waker::noop());
+    match pin!(future).poll(&mut context) {
+        Poll::Ready(output) => output,
+        Poll::Pending => unreachable!("a blocking request never waits"),
+    }
+}
+
+/// [`CreateTransport`] of the blocking client
+struct BlockingTransport<'a, C> {
+    client: reqwest::blocking::Client,
+    api: &'a CfApi,
+    clock: &'a C,
+}
+
+impl<C: Clock> BlockingTransport<'_, C> {
+    /// Parse the response to a request made to `action`, logging why it failed if it did
+    fn json(&self, res: Response, ids: RequestIds, action: &str) -> Result<Value, ()> {
+        let status = res.status();
+        if !status.is_success() {
+            error!(
+                "Failed to {action}: {} (status '{status}', {ids})",
+                cf_error_message(res, self.api)
+            );
+            return Err(());
+        }
+        let json = res.json::<Value>().map_err(|e| {
+            error!("Could not parse the response when trying to {action}: {e} (status '{status}', {ids})");
+        })?;
+        if let Some(errors) = cf_failure(&json, self.api) {
+            error!("Failed to {action}: {errors} (status '{status}', {ids})");
+            return Err(());
+        }
+        Ok(json)
+    }
+}
+
+impl<C: Clock> CreateTransport for BlockingTransport<'_, C> {
+    fn post(&self, zone_id: &str, body: &Value) -> impl Future<Output = Sent> {
+        let url = format!("{}/zones/{zone_id}/dns_records", self.api.api_url);
+        let (res, ids) = cf_send(self.client.post(&url).json(body), self.api, self.clock);
+        let action = format!("create DNS record in zone '{zone_id}'");
+        ready(match res {
+            Err(CfError::Request(e)) => Sent::Lost {
+                error: e.to_string(),
+                ids,
+            },
+            Err(e) => {
+                error!("Could not {action}: {e} ({ids})");
+                Sent::Response(Err(()))
+            }
+            Ok(res) => Sent::Response(self.json(res, ids, &action)),
+        })
+    }
+
+    fn find(
+        &self,
+        zone_id: &str,
+        query: &[(&'static str, String)],
+    ) -> impl Future<Output = Result<Value, ()>> {
+        let url = format!("{}/zones/{zone_id}/dns_records", self.api.api_url);
+        let (res, ids) = cf_send(self.client.get(&url).query(query), self.api, self.clock);
+        let action = format!("look up DNS record in zone '{zone_id}'");
+        ready(match res {
+            Ok(res) => self.json(res, ids, &action),
+            Err(e) => {
+                error!("Could not {action}: {e} ({ids})");
+                Err(())
+            }
+        })
+    }
+}
+
+/// Create a new Cloudflare DNS record, with an optional comment
+///
+/// Returns the id of the created record.
+pub fn cf_create_record(
+    record: &Record,
+    comment: Option<&str>,
+    zone_id: &str,
+    api: &CfApi,
+    clock: &impl Clock,
+) -> Result<String, ()> {
+    let transport = BlockingTransport {
+        client: reqwest::blocking::Client::new(),
+        api,
+        clock,
+    };
+    cf_block_on(cf_create_with(&transport, record, comment, zone_id, api))
+}
+
+/// Query parameters of the request looking up the records with the type, name and content of `record`
+pub fn cf_find_query(record: &Record) -> [(&'static str, String); 3] {
+    [
+        ("type", record.rtype().to_string()),
+        ("name", record.name.clone()),
+        ("content", record.content.to_string()),
+    ]
+}
+
+/// Delete a Cloudflare DNS record
+pub fn cf_delete_record(
+    zone_id: &str,
+    record_id: &str,
+    api: &CfApi,
+    clock: &impl Clock,
+) -> Result<(), ()> {
+    let client = reqwest::blocking::Client::new();
+    let url = format!("{}/zones/{zone_id}/dns_records/{record_id}", api.api_url);
+
+    let (res, ids) = cf_send(client.delete(&url), api, clock);
+    let res = res.map_err(|e| {
+        error!("Could not delete record with id '{record_id}': {e} ({ids})");
+    })?;
+
+    if res.status().is_success() {
+        Ok(())
+    } else {
+        error!(
+            "Failed to delete record with id '{record_id}': {} ({ids})",
+            cf_error_message(res, api)
+        );
+        Err(())
+    }
+}
+
+/// Get the id of the zone with the given name
+///
+/// Fails if no zone, or more than one zone, has the given name.
+pub fn cf_resolve_zone_id(zone_name: &str, api: &CfApi, clock: &impl Clock) -> Result<String, ()> {
+    let client = reqwest::blocking::Client::new();
+    let url = format!("{}/zones", api.api_url);
+
+    let (res, ids) = cf_send(client.get(url).query(&[("name", zone_name)]), api, clock);
+    let res = res.map_err(|e| {
+        error!("Could not look up zone '{zone_name}': {e} ({ids})");
+    })?;
+
+    let json = res.json::<Value>().map_err(|e| {
+        error!("Could not parse zones named '{zone_name}': {e} ({ids})");
+    })?;
+
+    let Some(zones) = json["result"].as_array() else {
+        error!(
+            "Could not parse zones named '{zone_name}' ({ids}):\n{}",
+            cf_redacted(&json, api)
+        );
+        return Err(());
+    };
+
+    match zones.as_slice() {
+        [zone] => match zone["id"].as_str() {
+            Some(id) => Ok(id.to_string()),
+            None => {
+                error!(
+                    "Zone '{zone_name}' has no id ({ids}):\n{}",
+                    cf_redacted(zone, api)
+                );
+                Err(())
+            }
+        },
+        [] => {
+            error!(
+                "No zone named '{zone_name}' found. Make sure the API token has Zone Read permission for it ({ids})"
+            );
+            Err(())
+        }
+        _ => {
+            error!(
+                "<{}> zones named '{zone_name}' found, use the zone id instead ({ids})",
+                zones.len()
+            );
+            Err(())
+        }
+    }
+}
+
+/// Zone metadata, as returned by Cloudflare
+#[derive(Debug, Clone)]
+pub struct ZoneInfo {
+    pub name: String,
+    /// Zone status, e.g. `active` or `pending`
+    pub status: String,
+}
+
+/// Get the status of the API token (e.g. `active`, `disabled` or `expired`) from the token verify endpoint
+///
+/// Only scoped API tokens can be verified this way.
+pub fn cf_verify_token(api: &CfApi, clock: &impl Clock) -> Result<String, ()> {
+    let client = reqwest::blocking::Client::new();
+    let url = format!("{}/user/tokens/verify", api.api_url);
+
+    let (res, ids) = cf_send(client.get(url), api, clock);
+    let res = res.map_err(|e| {
+        error!("Could not verify the API token: {e} ({ids})");
+    })?;
+    if !res.status().is_success() {
+        error!(
+            "Could not verify the API token: {} ({ids})",
+            cf_error_message(res, api)
+        );
+        return Err(());
+    }
+
+    let json = res.json::<Value>().map_err(|e| {
+        error!("Could not parse the API token verification: {e} ({ids})");
+    })?;
+    match json["result"]["status"].as_str() {
+        Some(status) => Ok(status.to_string()),
+        None => {
+            error!(
+                "Could not parse the API token verification ({ids}):\n{}",
+                cf_redacted(&json, api)
+            );
+            Err(())
+        }
+    }
+}
+
+/// Get the name and status of the given zone
+pub fn cf_get_zone(zone_id: &str, api: &CfApi, clock: &impl Clock) -> Result<ZoneInfo, ()> {
+    let client = reqwest::blocking::Client::new();
+    let url = format!("{}/zones/{zone_id}", api.api_url);
+
+    let (res, ids) = cf_send(client.get(&url), api, clock);
+    let res = res.map_err(|e| {
+        error!("Could not get zone '{zone_id}': {e} ({ids})");
+    })?;
+
+    let json = res.json::<Value>().map_err(|e| {
+        error!("Could not parse zone '{zone_id}': {e} ({ids})");
+    })?;
+
+    let name = json["result"]["name"].as_str();
+    let status = json["result"]["status"].as_str();
+    match (name, status) {
+        (Some(name), Some(status)) => Ok(ZoneInfo {
+            name: name.to_string(),
+            status: status.to_string(),
+        }),
+        _ => {
+            error!(
+                "Could not parse zone '{zone_id}' ({ids}):\n{}",
+                cf_redacted(&json, api)
+            );
+            Err(())
+        }
+    }
+}
+
+/// Number of records requested per page when listing the records of a zone
+pub const RECORDS_PER_PAGE: u32 = 100;
+
+/// Get all DNS records of type `A` and `AAAA` in the given zone, or only those with the given `tag`
+///
+/// Any record which cannot be parsed will be ignored. The records are listed page by page, and the whole listing fails
+/// if any page can't be fetched: acting on a partial list would create the records of the missing pages again.
+pub fn cf_get_records(
+    zone_id: &str,
+    tag: Option<&str>,
+    api: &CfApi,
+    clock: &impl Clock,
+) -> Result<Vec<CfRecord>, ()> {
+    let client = reqwest::blocking::Client::new();
+    let mut records = Vec::new();
+    let mut page = 1;
+    loop {
+        let total_pages = cf_get_records_page(&client, zone_id, tag, page, api, clock, &mut records)
+            .map_err(|_| {
+                error!(
+                    "Could not get page <{page}> of the DNS records of zone '{zone_id}', not using the pages which \
+                    were already fetched"
+                );
+            })?;
+        if page >= total_pages {
+            return Ok(records);
+        }
+        page += 1;
+    }
+}
+
+/// Get the DNS records of the given `page` of the zone's records, add them to `records`, and return the total number
+/// of pages
+fn cf_get_records_page(
+    client: &reqwest::blocking::Client,
+    zone_id: &str,
+    tag: Option<&str>,
+    page: u32,
+    api: &CfApi,
+    clock: &impl Clock,
+    records: &mut Vec<CfRecord>,
+) -> Result<u32, ()> {
+    let url = format!(
+        "{}/zones/{zone_id}/dns_records?page={page}&per_page={RECORDS_PER_PAGE}",
+        api.api_url
+    );
+
+    let request = match tag {
+        Some(tag) => client.get(&url).query(&[("tag", tag)]),
+        None => client.get(&url),
+    };
+    let (res, ids) = cf_send(request, api, clock);
+    let res = res.map_err(|e| {
+        error!("Could not get DNS records: {} ({ids})", e);
+    })?;
+    let status = res.status();
+
+    let json = match res.json::<serde_json::Value>() {
+        Ok(v) => v,
+        Err(e) => {
+            error!(
+                "Could not parse DNS records: {} (status '{status}', {ids})",
+                e
+            );
+            return Err(());
+        }
+    };
+
+    if let Some(errors) = cf_failure(&json, api) {
+        error!("Could not get DNS records: {errors} (status '{status}', {ids})");
+        return Err(());
+    }
+
+    let json_records = match json["result"].as_array() {
+        Some(arr) => arr,
+        None => {
+            error!(
+                "Could not parse array of DNS records (status '{status}', {ids}):\n{}",
+                cf_redacted(&json, api)
+            );
+            return Err(());
+        }
+    };
+
+    records.extend(
+        json_records
+            .iter()
+            .map(cf_parse_record)
+            .filter_map(|rec_res| rec_res.ok())
+            .inspect(|record| check_zone(record, zone_id)),
+    );
+    // Without pagination info, the response is taken to hold every record
+    Ok(json["result_info"]["total_pages"]
+        .as_u64()
+        .map(|pages| pages as u32)
+        .unwrap_or(page))
+}
+
+/// Get the raw response listing the first `count` DNS records of the given zone, as returned by Cloudflare
+pub fn cf_get_records_raw(
+    zone_id: &str,
+    count: u32,
+    api: &CfApi,
+    clock: &impl Clock,
+) -> Result<Value, ()> {
+    let client = reqwest::blocking::Client::new();
+    let url = format!(
+        "{}/zones/{zone_id}/dns_records?per_page={count}",
+        api.api_url
+    );
+
+    let (res, ids) = cf_send(client.get(&url), api, clock);
+    let res = res.map_err(|e| {
+        error!("Could not get DNS records: {e} ({ids})");
+    })?;
+
+    res.json::<Value>().map_err(|e| {
+        error!("Could not parse DNS records: {e} ({ids})");
+    })
+}
+
+/// Get a single DNS record of type `A` or `AAAA`
+pub fn cf_get_record(
+    zone_id: &str,
+    record_id: &str,
+    api: &CfApi,
+    clock: &impl Clock,
+) -> Result<CfRecord, ()> {
+    let client = reqwest::blocking::Client::new();
+    let url = format!("{}/zones/{zone_id}/dns_records/{record_id}", api.api_url);
+
+    let (res, ids) = cf_send(client.get(&url), api, clock);
+    let res = res.map_err(|e| {
+        error!("Could not get record with id '{record_id}': {e} ({ids})");
+    })?;
+
+    let json = res.json::<Value>().map_err(|e| {
+        error!("Could not parse record with id '{record_id}': {e} ({ids})");
+    })?;
+
+    let record = cf_parse_record(&json["result"]).map_err(|_| {
+        error!(
+            "Could not parse record with id '{record_id}' ({ids}):\n{}",
+            cf_redacted(&json, api)
+        );
+    })?;
+    check_zone(&record, zone_id);
+    Ok(record)
+}
+
+/// Warn if `record` doesn't belong to the zone with id `zone_id`, e.g. because a zone id of another zone was configured
+pub fn check_zone(record: &CfRecord, zone_id: &str) {
+    if let Some(mismatch) = zone_mismatch(record, zone_id) {
+        warn!("{mismatch}");
+    }
+}
+
+/// Describe how `record` doesn't belong to the zone with id `zone_id`, `None` if it does or its zone is unknown
+fn zone_mismatch(record: &CfRecord, zone_id: &str) -> Option<String> {
+    let record_zone_id = record.zone_id.as_ref().filter(|id| *id != zone_id)?;
+    Some(format!(
+        "'{}' record '{}' with id '{}' belongs to zone '{}' ({record_zone_id}) instead of the requested zone '{zone_id}'",
+        record.record.rtype(),
+        record.record.name,
+        record.id,
+        record.zone_name.as_deref().unwrap_or("unknown")
+    ))
+}
+
+/// Try to parse a DNS record of type `A` and `AAAA`
+pub fn cf_parse_record(value: &Value) -> Result<CfRecord, ()> {
+    let id = value.get("id").ok_or(())?.as_str().ok_or(())?.to_string();
+    let rtype = value.get("type").ok_or(())?.as_str().ok_or(())?;
+
+    // Bail if the record type is not recognized as either `A` or `AAAA`
+    let rtype = RecordType::try_from(rtype)?;
+
+    let name = value.get("name").ok_or(())?.as_str().ok_or(())?.to_string();
+    let ttl = value.get("ttl").ok_or(())?.as_u64().ok_or(())? as u32;
+    let ttl: Ttl = ttl.try_into().map_err(|_| {
+        error!("Record '{name}' with id '{id}': could not parse TTL value '{ttl}'");
+    })?;
+
+    let content = value.get("content").ok_or(())?.as_str().ok_or(())?;
+    let content = match rtype {
+        RecordType::A => IpAddr::V4(Ipv4Addr::from_str(content).map_err(|e| {
+            error!("Record '{name}' with id '{id}' of type '{rtype}': could not parse IPv4 value '{content}': {e}");
+        })?),
+        RecordType::AAAA => IpAddr::V6(Ipv6Addr::from_str(content).map_err(|e| {
+            error!("Record '{name}' with id '{id}' of type '{rtype}': could not parse IPv6 value '{content}': {e}");
+        })?),
+    };
+    let proxied = value.get("proxied").ok_or(())?.as_bool().ok_or(())?;
+    let locked = value
+        .get("locked")
+        .and_then(|locked| locked.as_bool())
+        .unwrap_or(false);
+    let auto_added = value
+        .pointer("/meta/auto_added")
+        .and_then(|auto_added| auto_added.as_bool())
+        .unwrap_or(false);
+    let comment = value
+        .get("comment")
+        .and_then(|comment| comment.as_str())
+        .map(|comment| comment.to_string());
+
+    let modified_on = value
+        .get("modified_on")
+        .and_then(|modified_on| modified_on.as_str())
+        .map(|modified_on| modified_on.to_string());
+
+    let zone_id = value
+        .get("zone_id")
+        .and_then(|zone_id| zone_id.as_str())
+        .map(|zone_id| zone_id.to_string());
+    let zone_name = value
+        .get("zone_name")
+        .and_then(|zone_name| zone_name.as_str())
+        .map(|zone_name| zone_name.to_string());
+
+    let extra = value
+        .as_object()
+        .map(|fields| {
+            fields
+                .iter()
+                .filter(|(field, _)| !PARSED_FIELDS.contains(&field.as_str()))
+                .map(|(field, value)| (field.clone(), value.clone()))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(CfRecord {
+        id,
+        locked,
+        auto_added,
+        comment,
+        modified_on,
+        zone_id,
+        zone_name,
+        extra,
+        record: Record {
+            name,
+            ttl,
+            content,
+            proxied,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::logging;
+    use reqwest::header::HeaderValue;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::env;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    const MAX: Duration = Duration::from_secs(300);
+
+    fn now() -> jiff::Timestamp {
+        "2026-10-15T12:00:00Z".parse().unwrap()
+    }
+
+    fn retry_after(value: &str) -> Duration {
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", HeaderValue::from_str(value).unwrap());
+        super::retry_after(&headers, now(), MAX)
+    }
+
+    #[test]
+    fn retry_after_in_seconds() {
+        assert_eq!(retry_after("42"), Duration::from_secs(42));
+        assert_eq!(retry_after(" 0 "), Duration::ZERO);
+    }
+
+    #[test]
+    fn retry_after_as_a_date() {
+        assert_eq!(
+            retry_after("Thu, 15 Oct 2026 12:01:30 GMT"),
+            Duration::from_secs(90)
+        );
+    }
+
+    #[test]
+    fn retry_after_above_the_max_is_clamped() {
+        assert_eq!(retry_after("86400"), MAX);
+        assert_eq!(retry_after("Fri, 16 Oct 2026 12:00:00 GMT"), MAX);
+    }
+
+    #[test]
+    fn retry_after_a_date_in_the_past_is_no_wait() {
+        assert_eq!(retry_after("Thu, 15 Oct 2026 11:00:00 GMT"), Duration::ZERO);
+    }
+
+    #[test]
+    fn retry_after_which_cannot_be_parsed_is_the_default() {
+        assert_eq!(retry_after("soon"), DEFAULT_RETRY_AFTER);
+        assert_eq!(retry_after("-5"), DEFAULT_RETRY_AFTER);
+        assert_eq!(
+            super::retry_after(&HeaderMap::new(), now(), MAX),
+            DEFAULT_RETRY_AFTER
+        );
+        assert_eq!(
+            super::retry_after(&HeaderMap::new(), now(), Duration::from_secs(3)),
+            Duration::from_secs(3)
+        );
+    }
+
+    fn api() -> CfApi {
+        CfApi::new(Auth::Token("token".to_string()))
+    }
+
+    #[test]
+    fn reads_the_errors_at_the_error_pointer_of_the_api() {
+        let body = r#"{"error": {"code": 10000, "message": "Authentication error"}}"#;
+        let mut api = api();
+        assert_eq!(cf_body_error_message(body.to_string(), &api), body);
+
+        api.error_pointer = "/error".to_string();
+        assert_eq!(
+            cf_body_error_message(body.to_string(), &api),
+            "Authentication error (code 10000)"
+        );
+    }
+
+    #[test]
+    fn truncates_the_logged_bodies_to_the_max_of_the_api() {
+        let mut api = api();
+        api.max_log_body = Some(5);
+
+        assert_eq!(
+            cf_body_error_message("Bad Gateway".to_string(), &api),
+            "Bad G... (<6> more characters)"
+        );
+    }
+
+    #[test]
+    fn sends_the_extra_headers_of_the_api() {
+        let mut api = api();
+        api.extra_headers = vec![("X-Audit".to_string(), "cf-dns-rs".to_string())];
+        let ids = RequestIds::new();
+
+        let headers = cf_headers(&api, &ids);
+
+        assert_eq!(headers[0], ("X-Audit".to_string(), "cf-dns-rs".to_string()));
+        assert!(headers.contains(&("Authorization".to_string(), "Bearer token".to_string())));
+        assert!(headers.contains(&(REQUEST_ID_HEADER.to_string(), ids.request_id)));
+    }
+
+    /// [`CreateTransport`] answering the creations in turn with `posts`, and the look-ups with `found`
+    struct FakeTransport {
+        posts: RefCell<VecDeque<Sent>>,
+        found: Value,
+        requests: RefCell<Vec<String>>,
+    }
+
+    impl FakeTransport {
+        fn new(posts: Vec<Sent>, found: Value) -> Self {
+            Self {
+                posts: RefCell::new(posts.into()),
+                found,
+                requests: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl CreateTransport for FakeTransport {
+        async fn post(&self, zone_id: &str, body: &Value) -> Sent {
+            self.requests
+                .borrow_mut()
+                .push(format!("POST {zone_id} {}", body["name"]));
+            self.posts.borrow_mut().pop_front().unwrap()
+        }
+
+        async fn find(&self, zone_id: &str, query: &[(&'static str, String)]) -> Result<Value, ()> {
+            let query = query.iter().map(|(name, value)| format!("{name}={value}"));
+            self.requests.borrow_mut().push(format!(
+                "GET {zone_id} {}",
+                query.collect::<Vec<_>>().join("&")
+            ));
+            Ok(json!({ "success": true, "result": self.found }))
+        }
+    }
+
+    fn lost() -> Sent {
+        Sent::Lost {
+            error: "connection closed before message completed".to_string(),
+            ids: RequestIds::new(),
+        }
+    }
+
+    fn create(transport: &FakeTransport) -> Result<String, ()> {
+        let record = Record {
+            name: "www.example.com".to_string(),
+            ttl: Ttl::Auto,
+            content: "203.0.113.7".parse().unwrap(),
+            proxied: false,
+        };
+        cf_block_on(cf_create_with(transport, &record, None, "zone", &api()))
+    }
+
+    #[test]
+    fn finds_a_created_record_whose_response_was_lost() {
+        let transport = FakeTransport::new(vec![lost()], json!([{ "id": "7" }]));
+
+        assert_eq!(create(&transport), Ok("7".to_string()));
+        assert_eq!(
+            *transport.requests.borrow(),
+            [
+                "POST zone \"www.example.com\"",
+                "GET zone type=A&name=www.example.com&content=203.0.113.7",
+            ]
+        );
+    }
+
+    #[test]
+    fn creates_again_a_record_which_was_not_created_when_its_response_was_lost() {
+        let created = Sent::Response(Ok(json!({ "success": true, "result": { "id": "8" } })));
+        let transport = FakeTransport::new(vec![lost(), created], json!([]));
+
+        assert_eq!(create(&transport), Ok("8".to_string()));
+        assert_eq!(transport.requests.borrow().len(), 3);
+    }
+
+    #[test]
+    fn creates_again_only_once() {
+        let transport = FakeTransport::new(vec![lost(), lost()], json!([]));
+
+        assert_eq!(create(&transport), Err(()));
+        assert_eq!(transport.requests.borrow().len(), 3);
+    }
+
+    fn response(status: u16, body: Value) -> Response {
+        Response::from(
+            http::Response::builder()
+                .status(status)
+                .body(body.to_string())
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn detects_a_token_whose_ip_allow_list_blocks_the_request() {
+        let body = json!({
+            "success": false,
+            "errors": [{ "code": 9109, "message": "Cannot use the access token from location: 203.0.113.7" }],
+        });
+
+        let e = cf_forbidden(response(403, body), &api());
+
+        assert!(matches!(&e, CfError::IpNotAllowed(message) if message.ends_with("203.0.113.7")));
+        assert!(e.to_string().contains("IP allow-list"));
+    }
+
+    #[test]
+    fn reports_any_other_forbidden_response_as_such() {
+        let body = json!({
+            "success": false,
+            "errors": [{ "code": 10000, "message": "Authentication error" }],
+        });
+
+        let e = cf_forbidden(response(403, body.clone()), &api());
+
+        assert!(
+            matches!(&e, CfError::Forbidden(message) if serde_json::from_str::<Value>(message).unwrap() == body)
+        );
+    }
+
+    /// Status and body of the mock server's answer to a request, by method and target
+    type Handler = fn(&str, &str) -> (u16, Value);
+
+    /// `cf-ray` id of the responses of the [`mock_server`]
+    const CF_RAY: &str = "8a1b2c3d4e5f6a7b-AMS";
+
+    /// Answer the requests sent to the API of the returned settings with `handler`, and keep them in the returned list,
+    /// as method and target
+    fn mock_server(handler: Handler) -> (CfApi, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let api = CfApi {
+            api_url: format!("http://{}/client/v4", listener.local_addr().unwrap()),
+            ..api()
+        };
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().map_while(Result::ok) {
+                let mut data = Vec::new();
+                let mut buf = [0; 4096];
+                // The requests of the tests have no body
+                while !data.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buf).unwrap();
+                    assert_ne!(read, 0, "incomplete request");
+                    data.extend_from_slice(&buf[..read]);
+                }
+                let text = String::from_utf8_lossy(&data);
+                let mut request_line = text.lines().next().unwrap().split(' ');
+                let (method, target) = (request_line.next().unwrap(), request_line.next().unwrap());
+                let (status, body) = handler(method, target);
+                received.lock().unwrap().push(format!("{method} {target}"));
+                let body = body.to_string();
+                let response = format!(
+                    "HTTP/1.1 {status} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+                    cf-ray: {CF_RAY}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (api, requests)
+    }
+
+    fn get_records(api: &CfApi) -> (Result<Vec<CfRecord>, ()>, Vec<String>) {
+        logging::init_for_tests();
+        let clock = MockClock::new(Duration::ZERO);
+        logging::capture_errors(|| cf_get_records("zone", None, api, &clock))
+    }
+
+    #[test]
+    fn reports_an_error_response_to_the_listing_as_such() {
+        let (api, _) = mock_server(|_, _| {
+            (
+                400,
+                json!({
+                    "success": false,
+                    "errors": [{ "code": 10000, "message": "Authentication error" }],
+                    "result": null,
+                }),
+            )
+        });
+
+        let (records, errors) = get_records(&api);
+
+        assert!(records.is_err());
+        assert!(
+            errors[0].starts_with(
+                "Could not get DNS records: Authentication error (code 10000) (status '400"
+            ),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn reports_the_body_of_a_listing_with_an_unexpected_shape() {
+        let (api, _) = mock_server(|_, _| (200, json!({ "result": { "unexpected": true } })));
+
+        let (records, errors) = get_records(&api);
+
+        assert!(records.is_err());
+        assert!(
+            errors[0].starts_with("Could not parse array of DNS records (status '200"),
+            "{errors:?}"
+        );
+        assert!(errors[0].contains("\"unexpected\": true"), "{errors:?}");
+    }
+
+    #[test]
+    fn fails_the_whole_listing_when_a_page_cannot_be_fetched() {
+        let (api, requests) = mock_server(|_, target| match target.contains("page=1&") {
+            true => (
+                200,
+                json!({
+                    "success": true,
+                    "result": [{
+                        "id": "1",
+                        "type": "A",
+                        "name": "www.example.com",
+                        "content": "203.0.113.7",
+                        "ttl": 1,
+                        "proxied": false,
+                    }],
+                    "result_info": { "page": 1, "total_pages": 3 },
+                }),
+            ),
+            false => (
+                500,
+                json!({ "success": false, "errors": [{ "code": 10001, "message": "Internal error" }] }),
+            ),
+        });
+
+        let (records, errors) = get_records(&api);
+
+        assert!(records.is_err());
+        assert_eq!(
+            errors.last().unwrap(),
+            "Could not get page <2> of the DNS records of zone 'zone', not using the pages which were already fetched"
+        );
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn lists_only_the_records_with_the_managed_tag() {
+        let (api, requests) = mock_server(|_, _| (200, json!({ "success": true, "result": [] })));
+        logging::init_for_tests();
+        let clock = MockClock::new(Duration::ZERO);
+
+        let records = cf_get_records("zone", Some("cf-dns-rs"), &api, &clock).unwrap();
+
+        assert!(records.is_empty());
+        assert_eq!(
+            requests.lock().unwrap()[..],
+            ["GET /client/v4/zones/zone/dns_records?page=1&per_page=100&tag=cf-dns-rs"]
+        );
+    }
+
+    #[test]
+    fn tags_the_created_and_updated_records() {
+        let record = Record {
+            name: "www.example.com".to_string(),
+            ttl: Ttl::Auto,
+            content: "203.0.113.7".parse().unwrap(),
+            proxied: false,
+        };
+        let body = cf_create_body(&record, None, Some("cf-dns-rs"));
+        assert_eq!(body["tags"], json!(["cf-dns-rs"]));
+        assert!(cf_create_body(&record, None, None).get("tags").is_none());
+
+        let extra = json!({ "tags": ["owner:ops", "cf-dns-rs"] });
+        let body = cf_update_body(&record, "", extra.as_object().unwrap(), Some("cf-dns-rs"));
+        assert_eq!(body["tags"], json!(["owner:ops", "cf-dns-rs"]));
+        let extra = json!({ "tags": ["owner:ops"] });
+        let body = cf_update_body(&record, "", extra.as_object().unwrap(), Some("cf-dns-rs"));
+        assert_eq!(body["tags"], json!(["owner:ops", "cf-dns-rs"]));
+    }
+
+    #[test]
+    fn redacts_the_credentials_in_the_dumped_responses() {
+        let (api, _) = mock_server(|_, _| {
+            (
+                200,
+                json!({ "email": "me@example.com", "key": "s3cr3t-key", "success": true }),
+            )
+        });
+        let api = CfApi {
+            auth: Auth::GlobalKey {
+                email: "me@example.com".to_string(),
+                key: "s3cr3t-key".to_string(),
+            },
+            ..api
+        };
+        let dir = env::temp_dir().join(format!("cf-dns-rs-dumps-{}", Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        let ids = RequestIds::new();
+        let request = reqwest::blocking::Client::new().get(format!("{}/zones", api.api_url));
+
+        let res = cf_send_dumped(
+            request,
+            &api,
+            dir.to_str().unwrap(),
+            &ids,
+            &MockClock::new(Duration::ZERO),
+        );
+
+        // The response is passed on whole
+        assert!(res.unwrap().text().unwrap().contains("s3cr3t-key"));
+        let dumps = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(dumps.len(), 1);
+        let dump = fs::read_to_string(&dumps[0]).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(dump.starts_with("> GET http://"), "{dump}");
+        assert!(dump.contains(r#""email":"<auth email>""#), "{dump}");
+        assert!(dump.contains(r#""key":"<credentials>""#), "{dump}");
+        assert!(
+            !dump.contains("s3cr3t-key") && !dump.contains("me@example.com"),
+            "{dump}"
+        );
+    }
+
+    #[test]
+    fn retries_a_network_failure_only_for_requests_which_cant_create_duplicates() {
+        for method in [Method::GET, Method::PATCH, Method::PUT, Method::DELETE] {
+            assert!(retries_network_failure(&method), "{method}");
+        }
+        assert!(!retries_network_failure(&Method::POST));
+    }
+
+    #[test]
+    fn logs_the_request_id_and_the_cf_ray_of_a_failed_request() {
+        let (api, _) = mock_server(|_, _| {
+            (
+                400,
+                json!({ "success": false, "errors": [{ "code": 10000, "message": "Authentication error" }] }),
+            )
+        });
+
+        let (_, errors) = get_records(&api);
+
+        let ids = errors[0]
+            .rsplit_once("(status '400 Bad Request', request id '")
+            .map(|(_, ids)| ids)
+            .unwrap_or_else(|| panic!("{errors:?}"));
+        let (request_id, cf_ray) = ids.split_once("', cf-ray '").unwrap();
+        assert!(Uuid::parse_str(request_id).is_ok(), "{request_id}");
+        assert_eq!(cf_ray, format!("{CF_RAY}')"));
+    }
+
+    #[test]
+    fn sends_a_new_request_id_with_every_request() {
+        let (first, second) = (RequestIds::new(), RequestIds::new());
+        assert_ne!(first.request_id, second.request_id);
+        let headers = cf_headers(&api(), &first);
+        assert!(headers.contains(&(REQUEST_ID_HEADER.to_string(), first.request_id.clone())));
+        assert_eq!(
+            first.to_string(),
+            format!("request id '{}'", first.request_id)
+        );
+    }
+
+    #[test]
+    fn resolves_the_zone_id_of_a_single_matching_zone() {
+        let (api, requests) = mock_server(|_, _| {
+            (
+                200,
+                json!({ "success": true, "result": [{ "id": "zone-id", "name": "example.com" }] }),
+            )
+        });
+        logging::init_for_tests();
+        let clock = MockClock::new(Duration::ZERO);
+
+        assert_eq!(
+            cf_resolve_zone_id("example.com", &api, &clock),
+            Ok("zone-id".to_string())
+        );
+        assert_eq!(
+            requests.lock().unwrap()[..],
+            ["GET /client/v4/zones?name=example.com"]
+        );
+    }
+
+    #[test]
+    fn refuses_a_zone_name_with_no_matching_zone() {
+        let (api, _) = mock_server(|_, _| (200, json!({ "success": true, "result": [] })));
+        logging::init_for_tests();
+        let clock = MockClock::new(Duration::ZERO);
+
+        let (result, errors) =
+            logging::capture_errors(|| cf_resolve_zone_id("example.com", &api, &clock));
+
+        assert!(result.is_err());
+        assert!(
+            errors[0].starts_with("No zone named 'example.com' found"),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn refuses_a_zone_name_with_several_matching_zones() {
+        let (api, _) = mock_server(|_, _| {
+            (
+                200,
+                json!({ "success": true, "result": [{ "id": "zone-a" }, { "id": "zone-b" }] }),
+            )
+        });
+        logging::init_for_tests();
+        let clock = MockClock::new(Duration::ZERO);
+
+        let (result, errors) =
+            logging::capture_errors(|| cf_resolve_zone_id("example.com", &api, &clock));
+
+        assert!(result.is_err());
+        assert!(
+            errors[0].starts_with("<2> zones named 'example.com' found"),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn parses_the_locked_flag_of_a_record() {
+        let mut record = json!({
+            "id": "1",
+            "type": "A",
+            "name": "www.example.com",
+            "ttl": 1,
+            "content": "203.0.113.7",
+            "proxied": false,
+        });
+        assert!(!cf_parse_record(&record).unwrap().locked);
+
+        record["locked"] = json!(true);
+        assert!(cf_parse_record(&record).unwrap().locked);
+    }
+
+    #[test]
+    fn sends_the_unmanaged_fields_of_a_record_back_on_update() {
+        let value = json!({
+            "id": "1",
+            "type": "A",
+            "name": "www.example.com",
+            "ttl": 1,
+            "content": "198.51.100.1",
+            "proxied": false,
+            "settings": { "ipv4_only": true },
+            "data": { "flags": 0 },
+        });
+        let cf_rec = cf_parse_record(&value).unwrap();
+        assert_eq!(
+            cf_rec.extra.keys().collect::<Vec<_>>(),
+            ["data", "settings"]
+        );
+
+        let record = Record {
+            content: "203.0.113.7".parse().unwrap(),
+            ..cf_rec.record.clone()
+        };
+        let body = cf_update_body(&record, "[cf-dns-rs]", &cf_rec.extra, None);
+
+        assert_eq!(body["settings"], json!({ "ipv4_only": true }));
+        assert_eq!(body["data"], json!({ "flags": 0 }));
+        assert_eq!(body["content"], "203.0.113.7");
+        assert_eq!(body["comment"], "[cf-dns-rs]");
+    }
+
+    #[test]
+    fn warns_about_a_record_of_another_zone() {
+        let mut record = json!({
+            "id": "1",
+            "type": "A",
+            "name": "www.example.com",
+            "ttl": 1,
+            "content": "203.0.113.7",
+            "proxied": false,
+        });
+        assert_eq!(
+            zone_mismatch(&cf_parse_record(&record).unwrap(), "zone-a"),
+            None
+        );
+
+        record["zone_id"] = json!("zone-a");
+        record["zone_name"] = json!("example.com");
+        assert_eq!(
+            zone_mismatch(&cf_parse_record(&record).unwrap(), "zone-a"),
+            None
+        );
+
+        assert_eq!(
+            zone_mismatch(&cf_parse_record(&record).unwrap(), "zone-b"),
+            Some(
+                "'A' record 'www.example.com' with id '1' belongs to zone 'example.com' (zone-a) instead of the \
+                requested zone 'zone-b'"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn sends_the_headers_of_each_auth_mode() {
+        let auth_headers = |auth| {
+            let api = CfApi::new(auth);
+            cf_headers(&api, &RequestIds::new())
+                .into_iter()
+                .filter(|(name, _)| name == "Authorization" || name.starts_with("X-Auth-"))
+                .collect::<Vec<_>>()
+        };
+        let header = |name: &str, value: &str| (name.to_string(), value.to_string());
+
+        assert_eq!(
+            auth_headers(Auth::Token("s3cr3t-token".to_string())),
+            [header("Authorization", "Bearer s3cr3t-token")]
+        );
+        assert_eq!(
+            auth_headers(Auth::GlobalKey {
+                email: "me@example.com".to_string(),
+                key: "s3cr3t-key".to_string(),
+            }),
+            [
+                header("X-Auth-Email", "me@example.com"),
+                header("X-Auth-Key", "s3cr3t-key")
+            ]
+        );
+    }
+}
@@ -0,0 +1,72 @@
+//! Cloudflare's own addresses.
+//!
+//! A proxied record resolves to Cloudflare's anycast addresses, but its content must be the origin IP. An address from
+//! Cloudflare's published ranges in a record, or detected as the current IP, is almost certainly a mistake: e.g. the
+//! content was copied from a lookup of the proxied name, or the IP was detected through Cloudflare's WARP.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Cloudflare's IPv4 ranges, as published at <https://www.cloudflare.com/ips-v4>
+const V4_RANGES: [(Ipv4Addr, u8); 15] = [
+    (Ipv4Addr::new(173, 245, 48, 0), 20),
+    (Ipv4Addr::new(103, 21, 244, 0), 22),
+    (Ipv4Addr::new(103, 22, 200, 0), 22),
+    (Ipv4Addr::new(103, 31, 4, 0), 22),
+    (Ipv4Addr::new(141, 101, 64, 0), 18),
+    (Ipv4Addr::new(108, 162, 192, 0), 18),
+    (Ipv4Addr::new(190, 93, 240, 0), 20),
+    (Ipv4Addr::new(188, 114, 96, 0), 20),
+    (Ipv4Addr::new(197, 234, 240, 0), 22),
+    (Ipv4Addr::new(198, 41, 128, 0), 17),
+    (Ipv4Addr::new(162, 158, 0, 0), 15),
+    (Ipv4Addr::new(104, 16, 0, 0), 13),
+    (Ipv4Addr::new(104, 24, 0, 0), 14),
+    (Ipv4Addr::new(172, 64, 0, 0), 13),
+    (Ipv4Addr::new(131, 0, 72, 0), 22),
+];
+
+/// Cloudflare's IPv6 ranges, as published at <https://www.cloudflare.com/ips-v6>
+const V6_RANGES: [(Ipv6Addr, u8); 7] = [
+    (Ipv6Addr::new(0x2400, 0xcb00, 0, 0, 0, 0, 0, 0), 32),
+    (Ipv6Addr::new(0x2606, 0x4700, 0, 0, 0, 0, 0, 0), 32),
+    (Ipv6Addr::new(0x2803, 0xf800, 0, 0, 0, 0, 0, 0), 32),
+    (Ipv6Addr::new(0x2405, 0xb500, 0, 0, 0, 0, 0, 0), 32),
+    (Ipv6Addr::new(0x2405, 0x8100, 0, 0, 0, 0, 0, 0), 32),
+    (Ipv6Addr::new(0x2a06, 0x98c0, 0, 0, 0, 0, 0, 0), 29),
+    (Ipv6Addr::new(0x2c0f, 0xf248, 0, 0, 0, 0, 0, 0), 32),
+];
+
+/// Whether `ip` is in one of Cloudflare's published ranges
+pub fn is_cloudflare_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => V4_RANGES.iter().any(|(network, len)| {
+            let mask = u32::MAX.checked_shl(32 - u32::from(*len)).unwrap_or(0);
+            u32::from(*ip) & mask == u32::from(*network)
+        }),
+        IpAddr::V6(ip) => V6_RANGES.iter().any(|(network, len)| {
+            let mask = u128::MAX.checked_shl(128 - u32::from(*len)).unwrap_or(0);
+            u128::from(*ip) & mask == u128::from(*network)
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_cloudflare(ip: &str) -> bool {
+        is_cloudflare_ip(&ip.parse().unwrap())
+    }
+
+    #[test]
+    fn recognizes_the_addresses_of_cloudflare_ranges_only() {
+        assert!(is_cloudflare("104.16.132.229"));
+        assert!(is_cloudflare("173.245.63.255"));
+        assert!(is_cloudflare("2606:4700::6810:84e5"));
+
+        assert!(!is_cloudflare("173.245.64.0"));
+        assert!(!is_cloudflare("104.28.0.1"));
+        assert!(!is_cloudflare("203.0.113.7"));
+        assert!(!is_cloudflare("2001:db8::1"));
+    }
+}
@@ -0,0 +1,110 @@
+//! Record comments rendered from a template.
+//!
+//! The template given in `CF_DNS_COMMENT_TEMPLATE` can contain the following placeholders:
+//! - `{tool}`: the name and version of this app
+//! - `{timestamp}`: the current UTC time, in RFC 3339 format
+//! - `{ip}`: the IP the record is set to
+//! - `{host}`: the record name
+//!
+//! Records created by this app, and records updated from the desired state, have their comment prefixed with
+//! [`MANAGED_MARKER`].
+
+use log::warn;
+use std::net::IpAddr;
+
+/// Maximum length of a record comment on Cloudflare's Free plan. Longer comments are truncated.
+pub const MAX_COMMENT_LEN: usize = 100;
+
+/// Marks the comment of a record which is managed by this app
+pub const MANAGED_MARKER: &str = "[cf-dns-rs]";
+
+/// Template for the comment set on records when they are created or updated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommentTemplate(String);
+
+impl CommentTemplate {
+    pub fn new(template: &str) -> Self {
+        Self(template.to_string())
+    }
+
+    /// Render the comment for a record of `host` set to `ip`, truncating it to [`MAX_COMMENT_LEN`] characters
+    pub fn render(&self, host: &str, ip: &IpAddr) -> String {
+        let comment = self
+            .0
+            .replace(
+                "{tool}",
+                concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")),
+            )
+            .replace(
+                "{timestamp}",
+                &jiff::Timestamp::now()
+                    .strftime("%Y-%m-%dT%H:%M:%SZ")
+                    .to_string(),
+            )
+            .replace("{ip}", &ip.to_string())
+            .replace("{host}", host);
+
+        if comment.chars().count() > MAX_COMMENT_LEN {
+            warn!(
+                "Comment for record '{host}' is longer than {MAX_COMMENT_LEN} characters and will be truncated: '{comment}'"
+            );
+            comment.chars().take(MAX_COMMENT_LEN).collect()
+        } else {
+            comment
+        }
+    }
+}
+
+/// Comment of a managed record, made of the [`MANAGED_MARKER`] followed by the rendered `template` if any
+pub fn managed(template: Option<&CommentTemplate>, host: &str, ip: &IpAddr) -> String {
+    let comment = match template {
+        Some(template) => format!("{MANAGED_MARKER} {}", template.render(host, ip)),
+        None => MANAGED_MARKER.to_string(),
+    };
+    comment.chars().take(MAX_COMMENT_LEN).collect()
+}
+
+/// Whether a record with the given comment is managed by this app
+pub fn is_managed(comment: Option<&str>) -> bool {
+    comment.is_some_and(|comment| comment.starts_with(MANAGED_MARKER))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip() -> IpAddr {
+        "203.0.113.7".parse().unwrap()
+    }
+
+    #[test]
+    fn renders_the_placeholders_of_the_template() {
+        let template = CommentTemplate::new("{tool} set {host} to {ip} at {timestamp}");
+
+        let comment = template.render("www.example.com", &ip());
+
+        let prefix = format!(
+            "{} {} set www.example.com to 203.0.113.7 at ",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        );
+        let timestamp = comment.strip_prefix(&prefix).unwrap();
+        assert!(timestamp.parse::<jiff::Timestamp>().is_ok(), "{timestamp}");
+        assert!(timestamp.ends_with('Z'));
+    }
+
+    #[test]
+    fn truncates_a_long_comment() {
+        let template = CommentTemplate::new(&format!("{} {{host}}", "x".repeat(95)));
+
+        let comment = template.render("www.example.com", &ip());
+        assert_eq!(comment.chars().count(), MAX_COMMENT_LEN);
+        assert!(comment.ends_with(" www."));
+
+        // The marker is kept in front of the truncated comment
+        let comment = managed(Some(&template), "www.example.com", &ip());
+        assert_eq!(comment.chars().count(), MAX_COMMENT_LEN);
+        assert!(is_managed(Some(&comment)));
+        assert_eq!(managed(None, "www.example.com", &ip()), MANAGED_MARKER);
+    }
+}
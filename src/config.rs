@@ -0,0 +1,2072 @@
+//! App configuration.
+//!
+//! Settings are read from environment variables. A single zone is configured with the `CF_DNS_ZONE_ID` and
+//! `CF_DNS_HOSTS` environment variables. Several zones can be configured instead with a TOML file, whose path is given
+//! in `CF_DNS_CONFIG_FILE`. The file can also override the IP endpoints and the repeat interval:
+//!
+//! ```toml
+//! repeat_interval_seconds = 60
+//! ipv4_endpoint = "https://api.ipify.org"
+//!
+//! [[zones]]
+//! id = "zzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz"
+//! proxied_default = true
+//! hosts = ["example.com", { name = "ssh.example.com", proxied = false }]
+//! ```
+//!
+//! Instead of a single file, the `*.toml` files in the directory given in `CF_DNS_CONFIG_DIR` can be used. They are
+//! merged in the order of their names.
+//!
+//! The config files are checked for changes on every cycle, and reloaded if one of them was modified. The records which should
+//! exist in each zone can also be listed in a separate file, see [`crate::desired`].
+
+use crate::churn::ChurnLimit;
+use crate::cloudflare::{Auth, RESERVED_HEADERS};
+use crate::comment::CommentTemplate;
+use crate::desired::{self, Content, DesiredRecord};
+use crate::interface::Ipv6Select;
+use crate::propagation::PropagationCheck;
+use crate::secret;
+#[cfg(feature = "event-sink")]
+use crate::sink::{self, EventSink};
+use crate::{ChangePrefix, EndpointSelection, IpRetry, IpSource, RecordType, Ttl, TtlLimits};
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// Configuration of a single monitored host
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct HostConfig {
+    pub name: String,
+    /// Whether a record created for this host is proxied. Overrides the zone and global defaults.
+    pub proxied: Option<bool>,
+    /// Webhook to which the changes of this host are notified. Overrides `CF_DNS_WEBHOOK_URL`.
+    pub webhook: Option<String>,
+    /// Whether the records of this host are reconciled. A disabled host's records are left alone, and never pruned.
+    #[serde(default = "enabled_default")]
+    pub enabled: bool,
+    /// How much of the host's records is managed
+    #[serde(default)]
+    pub manage: Manage,
+}
+
+fn enabled_default() -> bool {
+    true
+}
+
+/// How much of a host's records is managed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Manage {
+    /// The records are created if missing, and kept up to date
+    #[default]
+    Full,
+    /// The records are created with the current IP if missing, and then left alone
+    CreateOnly,
+}
+
+/// A host in the config file is either just its name, or a table with its name and settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum HostEntry {
+    Name(String),
+    Config(HostConfig),
+}
+
+impl From<HostEntry> for HostConfig {
+    fn from(entry: HostEntry) -> Self {
+        match entry {
+            HostEntry::Name(name) => HostConfig {
+                name,
+                proxied: None,
+                webhook: None,
+                enabled: true,
+                manage: Manage::Full,
+            },
+            HostEntry::Config(config) => config,
+        }
+    }
+}
+
+/// How a zone is identified in the configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ZoneRef {
+    /// Zone id, as shown on the zone's "Overview" page
+    Id(String),
+    /// Zone name (e.g. `example.com`), resolved to the zone id at startup
+    Name(String),
+}
+
+impl ZoneRef {
+    fn new(id: Option<&str>, name: Option<&str>) -> Result<Self, ()> {
+        match (id, name) {
+            (Some(id), None) => Ok(Self::Id(id.trim().to_string())),
+            (None, Some(name)) => Ok(Self::Name(name.trim().to_string())),
+            _ => {
+                error!("Exactly one of the zone id or the zone name must be given");
+                Err(())
+            }
+        }
+    }
+}
+
+impl Display for ZoneRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ZoneRef::Id(id) => write!(f, "{id}"),
+            ZoneRef::Name(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// What to do when the same host is configured more than once in a zone with different settings
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateHosts {
+    /// Refuse the configuration
+    #[default]
+    Error,
+    /// Warn, and use the settings of the last entry
+    Last,
+}
+
+impl TryFrom<&str> for DuplicateHosts {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, ()> {
+        match value {
+            "error" => Ok(Self::Error),
+            "last" => Ok(Self::Last),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How the zone of a host is chosen, when several configured zones contain it (e.g. `example.com` and
+/// `sub.example.com` both contain `www.sub.example.com`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ZoneSelection {
+    /// The zone with the longest name, i.e. the most specific one
+    #[default]
+    LongestSuffixMatch,
+    /// The first zone, in the configured order
+    FirstMatch,
+}
+
+impl TryFrom<&str> for ZoneSelection {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, ()> {
+        match value {
+            "longest-suffix-match" => Ok(Self::LongestSuffixMatch),
+            "first-match" => Ok(Self::FirstMatch),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for ZoneSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ZoneSelection::LongestSuffixMatch => write!(f, "longest-suffix-match"),
+            ZoneSelection::FirstMatch => write!(f, "first-match"),
+        }
+    }
+}
+
+/// Whether created records are fetched again to check that they were created as requested
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VerifyCreate {
+    /// Trust the API response
+    #[default]
+    Off,
+    /// Log a warning if the created record differs from the request
+    Warn,
+    /// Consider the creation failed if the created record differs from the request
+    Fail,
+}
+
+impl TryFrom<&str> for VerifyCreate {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, ()> {
+        match value {
+            "off" => Ok(Self::Off),
+            "warn" => Ok(Self::Warn),
+            "fail" => Ok(Self::Fail),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Configuration of a single Cloudflare zone
+#[derive(Debug, Clone)]
+pub struct ZoneConfig {
+    pub zone_ref: ZoneRef,
+    /// Whether records created in this zone are proxied, unless overridden by the host. Overrides the global default.
+    pub proxied_default: Option<bool>,
+    pub hosts: Vec<HostConfig>,
+    /// Credentials used for the requests about this zone, instead of the global ones
+    pub auth: Option<Auth>,
+    /// Records which should exist in the zone, read from `CF_DNS_RECORDS_FILE`. `None` if no records file is used.
+    pub records: Option<Vec<DesiredRecord>>,
+}
+
+impl ZoneConfig {
+    /// Credentials used for the requests about this zone: its own, or else the global ones
+    pub fn auth<'a>(&'a self, config: &'a Config) -> &'a Auth {
+        self.auth.as_ref().unwrap_or(&config.auth)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ZoneEntry {
+    id: Option<String>,
+    name: Option<String>,
+    proxied_default: Option<bool>,
+    #[serde(default)]
+    hosts: Vec<HostEntry>,
+    api_token: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ConfigFile {
+    repeat_interval_seconds: Option<u64>,
+    ipv4_endpoint: Option<String>,
+    ipv6_endpoint: Option<String>,
+    #[serde(default)]
+    zones: Vec<ZoneEntry>,
+}
+
+/// Complete app configuration
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Credentials of the Cloudflare API
+    pub auth: Auth,
+    pub zones: Vec<ZoneConfig>,
+    /// Source of the current IP, for each monitored record type
+    pub endpoints: BTreeMap<RecordType, IpSource>,
+    /// Seconds between IP change checks. `0` means the app runs only once.
+    pub repeat_interval: u64,
+    pub create_records_allowed: bool,
+    pub verify_create: VerifyCreate,
+    /// Whether created records are proxied, unless overridden by the zone or host
+    pub proxied_default: bool,
+    /// Whether created records of each type are proxied, overriding `proxied_default`
+    pub proxied_by_type: BTreeMap<RecordType, bool>,
+    /// TTL of the created records of each type, automatic for the types which are not set
+    pub ttl_by_type: BTreeMap<RecordType, Ttl>,
+    pub require_active_zone: bool,
+    /// Whether the managed records of a host of the type which is not monitored are deleted, when only one type is
+    /// monitored
+    pub replace_on_family_change: bool,
+    /// Whether the managed duplicate records of a monitored host are deleted
+    pub prune_duplicates: bool,
+    /// Types of the records which may be deleted, all of them if `None`
+    pub prune_types: Option<Vec<RecordType>>,
+    /// Whether `A` records are left unchanged when the current IPv4 is a CGNAT address
+    pub skip_cgnat: bool,
+    /// Retries of failed requests to the IP endpoints
+    pub ip_retry: IpRetry,
+    /// Time for which changed IPs must stay the same before the records are changed, `0` to change them immediately
+    pub debounce: Duration,
+    /// Number of IP changes within a window after which a warning about churning IPs is logged, `None` to not watch
+    pub ip_churn: Option<ChurnLimit>,
+    /// JSON pointer to the errors in the Cloudflare API responses
+    pub error_pointer: String,
+    /// Longest wait before retrying a rate limited API request
+    pub retry_after_max: Duration,
+    /// Number of times an idempotent API request is sent again after a network failure
+    pub network_retries: u32,
+    /// Wait before sending an API request again after a network failure, multiplied by the number of the attempt
+    pub network_retry_wait: Duration,
+    /// Maximum length of the logged API response bodies, `None` for no limit
+    pub max_log_body: Option<usize>,
+    /// Directory to which the API requests and responses are written when logging at debug level
+    pub dump_responses_dir: Option<String>,
+    /// Headers added to every API request, as names and values
+    pub extra_headers: Vec<(String, String)>,
+    /// How the zone of a host is chosen when several zones contain it
+    pub zone_selection: ZoneSelection,
+    /// Check that changed records are visible from public resolvers, `None` to not check
+    pub propagation: Option<PropagationCheck>,
+    /// Time after a record was written during which it is not checked again
+    pub post_update_cooldown: Duration,
+    /// Maximum number of records created, updated or deleted in a single cycle, `None` if unlimited
+    pub max_writes_per_cycle: Option<u32>,
+    /// Bounds of the TTLs of the records which are created or updated
+    pub ttl_limits: TtlLimits,
+    /// Prefixes within which a new IP doesn't change the records of the hosts
+    pub ignore_change_within: ChangePrefix,
+    /// Maximum size of the responses of the IP endpoints
+    pub ip_max_response_bytes: usize,
+    /// Proxy used only for IP detection requests (e.g. `socks5://127.0.0.1:1080`)
+    pub ip_detect_proxy: Option<String>,
+    /// Whether invalid TLS certificates are accepted for IP detection requests
+    pub ip_detect_insecure_tls: bool,
+    /// PEM file with an additional CA certificate trusted for IP detection requests
+    pub ip_detect_ca_file: Option<String>,
+    /// Template of the comment set on created and updated records
+    pub comment_template: Option<CommentTemplate>,
+    /// Tag set on created and updated records, through which only the tagged records are listed
+    pub managed_tag: Option<String>,
+    /// File to which every DNS change is appended. Not changed by reloading the config file.
+    pub audit_file: Option<String>,
+    /// File which is replaced with the JSON report of every cycle
+    pub report_file: Option<String>,
+    /// Address on which metrics are served. Not changed by reloading the config file.
+    pub metrics_addr: Option<String>,
+    /// Path of the Unix socket on which commands are accepted. Not changed by reloading the config file.
+    pub control_socket: Option<String>,
+    /// Whether records whose name differs from the configured one only in case are renamed to the configured case
+    pub normalize_name_case: bool,
+    /// Whether records added automatically by Cloudflare are updated and pruned like the others
+    pub manage_auto_added: bool,
+    /// Number of consecutive failed cycles after which the app exits with an error, `0` to never exit
+    pub max_consecutive_failures: u32,
+    /// Whether cycles after the first one start at wall-clock times which are multiples of the repeat interval
+    pub align_to_clock: bool,
+    /// Number of cycles for which the records of a zone are reused instead of being fetched again
+    pub record_cache_cycles: u32,
+    /// Time during which the records of a zone are reused instead of being fetched again, `None` to not reuse them
+    pub record_cache_ttl: Option<Duration>,
+    /// Whether only errors and changes are reported, when running only once
+    pub quiet: bool,
+    /// Time after which a run is aborted, when running only once
+    pub run_timeout: Option<Duration>,
+    /// Time after which the app exits, when running repeatedly
+    pub max_runtime: Option<Duration>,
+    /// Webhook to which notifications are sent. Not changed by reloading the config file.
+    pub webhook_url: Option<String>,
+    /// Period at which an "alive" line is logged, `None` to not log it
+    pub heartbeat_every: Option<Duration>,
+    /// Whether a notification is sent when the app starts and stops
+    pub notify_lifecycle: bool,
+    /// Message broker to which the change events are published
+    #[cfg(feature = "event-sink")]
+    pub event_sink: Option<EventSink>,
+}
+
+#[cfg(test)]
+impl Config {
+    /// Configuration with the defaults of every setting, monitoring the given record types
+    pub fn for_tests(rtypes: &[RecordType]) -> Self {
+        Self {
+            auth: Auth::Token("token".to_string()),
+            zones: Vec::new(),
+            endpoints: rtypes
+                .iter()
+                .map(|rtype| (*rtype, IpSource::Http(format!("https://{rtype}.ip.test"))))
+                .collect(),
+            repeat_interval: 0,
+            create_records_allowed: false,
+            verify_create: VerifyCreate::default(),
+            proxied_default: false,
+            proxied_by_type: BTreeMap::new(),
+            ttl_by_type: BTreeMap::new(),
+            require_active_zone: false,
+            replace_on_family_change: false,
+            prune_duplicates: false,
+            prune_types: None,
+            skip_cgnat: false,
+            ip_retry: IpRetry {
+                retries: 0,
+                delay: Duration::from_secs(1),
+            },
+            debounce: Duration::ZERO,
+            ip_churn: None,
+            error_pointer: "/errors".to_string(),
+            retry_after_max: Duration::from_secs(300),
+            network_retries: 2,
+            network_retry_wait: Duration::from_secs(1),
+            max_log_body: None,
+            dump_responses_dir: None,
+            extra_headers: Vec::new(),
+            zone_selection: ZoneSelection::default(),
+            propagation: None,
+            post_update_cooldown: Duration::ZERO,
+            max_writes_per_cycle: None,
+            ttl_limits: TtlLimits::default(),
+            ignore_change_within: ChangePrefix::default(),
+            ip_max_response_bytes: 256,
+            ip_detect_proxy: None,
+            ip_detect_insecure_tls: false,
+            ip_detect_ca_file: None,
+            comment_template: None,
+            managed_tag: None,
+            audit_file: None,
+            report_file: None,
+            metrics_addr: None,
+            control_socket: None,
+            normalize_name_case: false,
+            manage_auto_added: false,
+            max_consecutive_failures: 0,
+            align_to_clock: false,
+            record_cache_cycles: 0,
+            record_cache_ttl: None,
+            quiet: false,
+            run_timeout: None,
+            max_runtime: None,
+            webhook_url: None,
+            heartbeat_every: None,
+            notify_lifecycle: false,
+            #[cfg(feature = "event-sink")]
+            event_sink: None,
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration from the environment and, if `CF_DNS_CONFIG_FILE` or `CF_DNS_CONFIG_DIR` is set, from the
+    /// config files
+    pub fn load() -> Result<Self, ()> {
+        let file = match config_file_paths()? {
+            Some(paths) => Some(read_config_files(&paths)?),
+            None => None,
+        };
+
+        let auth = match (env::var("CF_DNS_AUTH_EMAIL"), env::var("CF_DNS_AUTH_KEY")) {
+            (Ok(email), Ok(key)) => {
+                warn!(
+                    "Authenticating with the global API key of '{}', a scoped API token in `CF_DNS_API_TOKEN` is preferred",
+                    email.trim()
+                );
+                Auth::GlobalKey {
+                    email: email.trim().to_string(),
+                    key: key.trim().to_string(),
+                }
+            }
+            (Ok(_), Err(_)) | (Err(_), Ok(_)) => {
+                error!("`CF_DNS_AUTH_EMAIL` and `CF_DNS_AUTH_KEY` must be set together");
+                return Err(());
+            }
+            (Err(_), Err(_)) => match secret::keyring_api_token()? {
+                Some(token) => Auth::Token(token.trim().to_string()),
+                None => Auth::Token(
+                    env::var("CF_DNS_API_TOKEN")
+                        .map_err(|_| {
+                            error!("`CF_DNS_API_TOKEN` must be set");
+                        })?
+                        .trim()
+                        .to_string(),
+                ),
+            },
+        };
+
+        let duplicate_hosts = match env::var("CF_DNS_DUPLICATE_HOSTS") {
+            Ok(policy) => DuplicateHosts::try_from(policy.trim()).map_err(|_| {
+                error!("Could not parse `CF_DNS_DUPLICATE_HOSTS` which should be either `error` or `last`");
+            })?,
+            Err(_) => DuplicateHosts::default(),
+        };
+
+        let zone_selection = match env::var("CF_DNS_ZONE_SELECTION") {
+            Ok(selection) => ZoneSelection::try_from(selection.trim()).map_err(|_| {
+                error!(
+                    "Could not parse `CF_DNS_ZONE_SELECTION` which should be either `longest-suffix-match` or `first-match`"
+                );
+            })?,
+            Err(_) => ZoneSelection::default(),
+        };
+
+        let records_file = env::var("CF_DNS_RECORDS_FILE")
+            .ok()
+            .map(|path| path.trim().to_string());
+
+        let mut zones: Vec<ZoneConfig> = match &file {
+            Some(file) => file
+                .zones
+                .iter()
+                .map(|zone| {
+                    Ok(ZoneConfig {
+                        zone_ref: ZoneRef::new(zone.id.as_deref(), zone.name.as_deref())?,
+                        proxied_default: zone.proxied_default,
+                        hosts: dedup_hosts(
+                            zone.hosts.iter().cloned().map(HostConfig::from),
+                            duplicate_hosts,
+                        )?,
+                        auth: zone
+                            .api_token
+                            .as_ref()
+                            .map(|token| Auth::Token(token.trim().to_string())),
+                        records: None,
+                    })
+                })
+                .collect::<Result<_, ()>>()?,
+            None => {
+                let zone_id = env::var("CF_DNS_ZONE_ID").ok();
+                let zone_name = env::var("CF_DNS_ZONE_NAME").ok();
+                if zone_id.is_none() && zone_name.is_none() {
+                    error!(
+                        "One of `CF_DNS_CONFIG_FILE`, `CF_DNS_CONFIG_DIR`, `CF_DNS_ZONE_ID` or `CF_DNS_ZONE_NAME` must be set"
+                    );
+                    return Err(());
+                }
+                let hosts = match env::var("CF_DNS_HOSTS") {
+                    Ok(hosts) => hosts,
+                    // The records file can be used instead of the list of hosts
+                    Err(_) if records_file.is_some() => String::new(),
+                    Err(_) => {
+                        error!(
+                            "`CF_DNS_HOSTS` must be set when no config file and no `CF_DNS_RECORDS_FILE` are used"
+                        );
+                        return Err(());
+                    }
+                };
+
+                vec![ZoneConfig {
+                    zone_ref: ZoneRef::new(zone_id.as_deref(), zone_name.as_deref())?,
+                    proxied_default: None,
+                    hosts: dedup_hosts(parse_hosts(&hosts)?.into_iter(), duplicate_hosts)?,
+                    auth: None,
+                    records: None,
+                }]
+            }
+        };
+
+        if let Some(path) = &records_file {
+            let zone_refs = zones.iter().map(|z| z.zone_ref.clone()).collect::<Vec<_>>();
+            let hosts = zones
+                .iter()
+                .flat_map(|z| &z.hosts)
+                .map(|h| h.name.as_str())
+                .collect::<Vec<_>>();
+            let strict = env_bool("CF_DNS_CONFIG_STRICT", false)?;
+            let records =
+                desired::read_records_file(path, &zone_refs, zone_selection, &hosts, strict)?;
+            for (zone, records) in zones.iter_mut().zip(records) {
+                if let Some(record) = records
+                    .iter()
+                    .find(|r| zone.hosts.iter().any(|h| h.name == r.name))
+                {
+                    error!(
+                        "'{}' is both a monitored host and a desired record in zone '{}'",
+                        record.name, zone.zone_ref
+                    );
+                    return Err(());
+                }
+                zone.records = Some(records);
+            }
+        }
+
+        check_some_hosts(&zones, env_bool("CF_DNS_ALLOW_NO_HOSTS", false)?)?;
+
+        let ipv6_select = match env::var("CF_DNS_IPV6_SELECT") {
+            Ok(select) => Ipv6Select::try_from(select.trim()).map_err(|_| {
+                error!(
+                    "Could not parse `CF_DNS_IPV6_SELECT` which should be one of `stable`, `temporary` or `largest-lifetime`"
+                );
+            })?,
+            Err(_) => Ipv6Select::default(),
+        };
+        let selection = match env::var("CF_DNS_ENDPOINT_SELECTION") {
+            Ok(selection) => EndpointSelection::try_from(selection.trim()).map_err(|_| {
+                error!(
+                    "Could not parse `CF_DNS_ENDPOINT_SELECTION` which should be either `ordered` or `random`"
+                );
+            })?,
+            Err(_) => EndpointSelection::default(),
+        };
+        let ipv4_endpoint = file
+            .as_ref()
+            .and_then(|f| f.ipv4_endpoint.clone())
+            .or(env::var("IPV4_ENDPOINT").ok());
+        let ipv6_endpoint = file
+            .as_ref()
+            .and_then(|f| f.ipv6_endpoint.clone())
+            .or(env::var("IPV6_ENDPOINT").ok());
+        let mut endpoints = BTreeMap::new();
+        if let Some(endpoint) = ipv4_endpoint {
+            endpoints.insert(
+                RecordType::A,
+                IpSource::new(endpoint.trim(), ipv6_select, selection)?,
+            );
+        }
+        if let Some(endpoint) = ipv6_endpoint {
+            endpoints.insert(
+                RecordType::AAAA,
+                IpSource::new(endpoint.trim(), ipv6_select, selection)?,
+            );
+        }
+        if endpoints.is_empty() {
+            error!("At least one IP API endpoint must be defined!");
+            return Err(());
+        }
+        if let Ok(types) = env::var("CF_DNS_RECORD_TYPES") {
+            select_record_types(&types, &mut endpoints)?;
+        }
+        for record in zones.iter().flat_map(|zone| zone.records.iter().flatten()) {
+            if record.content == Content::Dynamic && !endpoints.contains_key(&record.rtype) {
+                error!(
+                    "Desired '{}' record '{}' is dynamic, but no IP endpoint is defined for its type",
+                    record.rtype, record.name
+                );
+                return Err(());
+            }
+        }
+
+        #[cfg(not(feature = "event-sink"))]
+        if env::var("CF_DNS_EVENT_SINK").is_ok() {
+            warn!(
+                "`CF_DNS_EVENT_SINK` is set, but this build has no event sink support (`event-sink` feature), ignoring it"
+            );
+        }
+
+        let repeat_interval = match file.as_ref().and_then(|f| f.repeat_interval_seconds) {
+            Some(interval) => interval,
+            None => env_parse("REPEAT_INTERVAL_SECONDS", 0).map_err(|_| {
+                error!(
+                    "Could not parse the value of `REPEAT_INTERVAL_SECONDS`. Make sure it is an unsigned value in the form `REPEAT_INTERVAL_SECONDS=60`"
+                );
+            })?,
+        };
+
+        Ok(Self {
+            auth,
+            zones,
+            endpoints,
+            repeat_interval,
+            create_records_allowed: env_bool("CF_DNS_CREATE_HOST_RECORDS", false)?,
+            verify_create: match env::var("CF_DNS_VERIFY_CREATE") {
+                Ok(verify) => VerifyCreate::try_from(verify.trim()).map_err(|_| {
+                    error!("Could not parse `CF_DNS_VERIFY_CREATE` which should be one of `off`, `warn` or `fail`");
+                })?,
+                Err(_) => VerifyCreate::default(),
+            },
+            proxied_default: env_bool("CF_DNS_PROXIED", false)?,
+            proxied_by_type: proxied_by_type()?,
+            ttl_by_type: ttl_by_type()?,
+            require_active_zone: env_bool("CF_DNS_REQUIRE_ACTIVE_ZONE", false)?,
+            replace_on_family_change: env_bool("CF_DNS_REPLACE_ON_FAMILY_CHANGE", false)?,
+            prune_duplicates: env_bool("CF_DNS_PRUNE_DUPLICATES", false)?,
+            prune_types: match env::var("CF_DNS_PRUNE_TYPES") {
+                Ok(types) => Some(
+                    types
+                        .split(',')
+                        .map(|rtype| rtype.trim())
+                        .filter(|rtype| !rtype.is_empty())
+                        .map(|rtype| {
+                            RecordType::try_from(rtype).map_err(|_| {
+                                error!(
+                                    "Could not parse `CF_DNS_PRUNE_TYPES` which should be a `,` separated list of `A` and `AAAA`"
+                                );
+                            })
+                        })
+                        .collect::<Result<Vec<_>, ()>>()?,
+                ),
+                Err(_) => None,
+            },
+            skip_cgnat: env_bool("CF_DNS_SKIP_CGNAT", false)?,
+            debounce: Duration::from_millis(env_parse("CF_DNS_DEBOUNCE_MS", 0).map_err(|_| {
+                error!("Could not parse `CF_DNS_DEBOUNCE_MS` which should be an unsigned value");
+            })?),
+            error_pointer: match env::var("CF_DNS_ERROR_POINTER") {
+                Ok(pointer) if pointer.trim().is_empty() || pointer.trim().starts_with('/') => {
+                    pointer.trim().to_string()
+                }
+                Ok(_) => {
+                    error!(
+                        "Could not parse `CF_DNS_ERROR_POINTER` which should be a JSON pointer starting with `/`"
+                    );
+                    return Err(());
+                }
+                Err(_) => "/errors".to_string(),
+            },
+            retry_after_max: Duration::from_secs(
+                env_parse("CF_DNS_RETRY_AFTER_MAX_SECONDS", 300).map_err(|_| {
+                    error!("Could not parse `CF_DNS_RETRY_AFTER_MAX_SECONDS` which should be an unsigned value");
+                })?,
+            ),
+            network_retries: env_parse("CF_DNS_NETWORK_RETRIES", 2).map_err(|_| {
+                error!("Could not parse `CF_DNS_NETWORK_RETRIES` which should be an unsigned value");
+            })?,
+            network_retry_wait: Duration::from_millis(
+                env_parse("CF_DNS_NETWORK_RETRY_WAIT_MS", 1000).map_err(|_| {
+                    error!("Could not parse `CF_DNS_NETWORK_RETRY_WAIT_MS` which should be an unsigned value");
+                })?,
+            ),
+            max_log_body: match env_parse("CF_DNS_MAX_LOG_BODY", 0).map_err(|_| {
+                error!("Could not parse `CF_DNS_MAX_LOG_BODY` which should be an unsigned value");
+            })? {
+                0 => None,
+                max => Some(max),
+            },
+            dump_responses_dir: env::var("CF_DNS_DEBUG_DUMP_RESPONSES")
+                .ok()
+                .map(|dir| dir.trim().to_string())
+                .filter(|dir| !dir.is_empty()),
+            extra_headers: extra_headers()?,
+            ip_churn: ip_churn()?,
+            propagation: propagation_check()?,
+            zone_selection,
+            heartbeat_every: match env_parse("CF_DNS_HEARTBEAT_EVERY_SECONDS", 0) {
+                Ok(0) => None,
+                Ok(seconds) => Some(Duration::from_secs(seconds)),
+                Err(_) => {
+                    error!(
+                        "Could not parse `CF_DNS_HEARTBEAT_EVERY_SECONDS` which should be an unsigned value"
+                    );
+                    return Err(());
+                }
+            },
+            post_update_cooldown: Duration::from_secs(
+                env_parse("CF_DNS_POST_UPDATE_COOLDOWN_SECONDS", 0).map_err(|_| {
+                    error!(
+                        "Could not parse `CF_DNS_POST_UPDATE_COOLDOWN_SECONDS` which should be an unsigned value"
+                    );
+                })?,
+            ),
+            max_writes_per_cycle: match env::var("CF_DNS_MAX_WRITES_PER_CYCLE") {
+                Ok(max) => match max.trim().parse::<u32>() {
+                    Ok(max) if max > 0 => Some(max),
+                    _ => {
+                        error!(
+                            "Could not parse `CF_DNS_MAX_WRITES_PER_CYCLE` which should be a positive value"
+                        );
+                        return Err(());
+                    }
+                },
+                Err(_) => None,
+            },
+            ttl_limits: ttl_limits()?,
+            ignore_change_within: ChangePrefix {
+                v4: env_prefix("CF_DNS_IGNORE_CHANGE_WITHIN_PREFIX", 32)?,
+                v6: env_prefix("CF_DNS_IGNORE_CHANGE_WITHIN_PREFIX_V6", 128)?,
+            },
+            ip_retry: IpRetry {
+                retries: env_parse("CF_DNS_IP_RETRIES", 0).map_err(|_| {
+                    error!("Could not parse `CF_DNS_IP_RETRIES` which should be an unsigned value");
+                })?,
+                delay: Duration::from_millis(env_parse("CF_DNS_IP_RETRY_DELAY_MS", 1000).map_err(
+                    |_| {
+                        error!(
+                            "Could not parse `CF_DNS_IP_RETRY_DELAY_MS` which should be an unsigned value"
+                        );
+                    },
+                )?),
+            },
+            ip_max_response_bytes: env_parse("CF_DNS_IP_MAX_RESPONSE_BYTES", 256).map_err(|_| {
+                error!("Could not parse `CF_DNS_IP_MAX_RESPONSE_BYTES` which should be an unsigned value");
+            })?,
+            ip_detect_proxy: ip_detect_proxy()?,
+            ip_detect_insecure_tls: env_bool("CF_DNS_IP_DETECT_INSECURE_TLS", false)?,
+            ip_detect_ca_file: env::var("CF_DNS_IP_DETECT_CA_FILE")
+                .ok()
+                .map(|path| path.trim().to_string()),
+            comment_template: env::var("CF_DNS_COMMENT_TEMPLATE")
+                .ok()
+                .map(|template| CommentTemplate::new(template.trim())),
+            managed_tag: env::var("CF_DNS_MANAGED_TAG")
+                .ok()
+                .map(|tag| tag.trim().to_string())
+                .filter(|tag| !tag.is_empty()),
+            audit_file: env::var("CF_DNS_AUDIT_FILE")
+                .ok()
+                .map(|path| path.trim().to_string()),
+            report_file: env::var("CF_DNS_REPORT_FILE")
+                .ok()
+                .map(|path| path.trim().to_string())
+                .filter(|path| !path.is_empty()),
+            metrics_addr: env::var("CF_DNS_METRICS_ADDR")
+                .ok()
+                .map(|addr| addr.trim().to_string()),
+            control_socket: env::var("CF_DNS_CONTROL_SOCKET")
+                .ok()
+                .map(|path| path.trim().to_string()),
+            normalize_name_case: env_bool("CF_DNS_NORMALIZE_NAME_CASE", false)?,
+            manage_auto_added: env_bool("CF_DNS_MANAGE_AUTO_ADDED", false)?,
+            max_consecutive_failures: env_parse("CF_DNS_MAX_CONSECUTIVE_FAILURES", 0).map_err(|_| {
+                error!("Could not parse `CF_DNS_MAX_CONSECUTIVE_FAILURES` which should be an unsigned value");
+            })?,
+            align_to_clock: env_bool("CF_DNS_ALIGN_TO_CLOCK", false)?,
+            record_cache_cycles: env_parse("CF_DNS_RECORD_CACHE_CYCLES", 0).map_err(|_| {
+                error!("Could not parse `CF_DNS_RECORD_CACHE_CYCLES` which should be an unsigned value");
+            })?,
+            record_cache_ttl: match env_parse("CF_DNS_RECORD_CACHE_TTL_SECONDS", 0).map_err(|_| {
+                error!("Could not parse `CF_DNS_RECORD_CACHE_TTL_SECONDS` which should be an unsigned value");
+            })? {
+                0 => None,
+                seconds => Some(Duration::from_secs(seconds)),
+            },
+            quiet: env_bool("CF_DNS_QUIET", false)?,
+            run_timeout: match env::var("CF_DNS_RUN_TIMEOUT_SECONDS") {
+                Ok(timeout) => Some(Duration::from_secs(timeout.trim().parse().map_err(|_| {
+                    error!(
+                        "Could not parse the value of `CF_DNS_RUN_TIMEOUT_SECONDS`. Make sure it is an unsigned value in the form `CF_DNS_RUN_TIMEOUT_SECONDS=300`"
+                    );
+                })?)),
+                Err(_) => None,
+            },
+            max_runtime: match env_parse("CF_DNS_MAX_RUNTIME_SECONDS", 0).map_err(|_| {
+                error!("Could not parse `CF_DNS_MAX_RUNTIME_SECONDS` which should be an unsigned value");
+            })? {
+                0 => None,
+                seconds => Some(Duration::from_secs(seconds)),
+            },
+            webhook_url: env::var("CF_DNS_WEBHOOK_URL")
+                .ok()
+                .map(|url| url.trim().to_string()),
+            notify_lifecycle: env_bool("CF_DNS_NOTIFY_LIFECYCLE", false)?,
+            #[cfg(feature = "event-sink")]
+            event_sink: sink::event_sink()?,
+        })
+    }
+
+    /// Whether a created record of type `rtype` is proxied, unless overridden by the zone or host
+    pub fn proxied_for(&self, rtype: RecordType) -> bool {
+        self.proxied_by_type
+            .get(&rtype)
+            .copied()
+            .unwrap_or(self.proxied_default)
+    }
+
+    /// TTL of a created record of type `rtype`
+    pub fn ttl_for(&self, rtype: RecordType) -> Ttl {
+        self.ttl_by_type.get(&rtype).copied().unwrap_or_default()
+    }
+
+    /// Whether records of type `rtype` may be deleted when pruning duplicates, replacing the type which is no longer
+    /// monitored or deleting the records which are no longer desired
+    pub fn prunes(&self, rtype: RecordType) -> bool {
+        self.prune_types
+            .as_ref()
+            .is_none_or(|types| types.contains(&rtype))
+    }
+
+    /// Log the differences between a previous configuration and this one
+    pub fn log_changes(&self, old: &Config) {
+        if self.repeat_interval != old.repeat_interval {
+            info!(
+                "Repeat interval changed from <{}> to <{}> seconds",
+                old.repeat_interval, self.repeat_interval
+            );
+        }
+
+        for rtype in [RecordType::A, RecordType::AAAA] {
+            let old_endpoint = old.endpoints.get(&rtype).map(|e| e.to_string());
+            let new_endpoint = self.endpoints.get(&rtype).map(|e| e.to_string());
+            if old_endpoint != new_endpoint {
+                info!("'{rtype}' IP source changed from '{old_endpoint:?}' to '{new_endpoint:?}'");
+            }
+        }
+
+        for zone in &self.zones {
+            match old.zones.iter().find(|z| z.zone_ref == zone.zone_ref) {
+                Some(old_zone) => {
+                    for host in &zone.hosts {
+                        match old_zone.hosts.iter().find(|h| h.name == host.name) {
+                            Some(old_host) if old_host != host => {
+                                info!("Host '{}' in zone '{}' changed", host.name, zone.zone_ref)
+                            }
+                            Some(_) => {}
+                            None => info!("Host '{}' added to zone '{}'", host.name, zone.zone_ref),
+                        }
+                    }
+                    for host in &old_zone.hosts {
+                        if !zone.hosts.iter().any(|h| h.name == host.name) {
+                            info!("Host '{}' removed from zone '{}'", host.name, zone.zone_ref);
+                        }
+                    }
+                    if zone.records != old_zone.records {
+                        info!(
+                            "Desired records of zone '{}' changed, <{}> records are now desired",
+                            zone.zone_ref,
+                            zone.records.as_ref().map_or(0, Vec::len)
+                        );
+                    }
+                    if zone.proxied_default != old_zone.proxied_default {
+                        info!(
+                            "Zone '{}' proxied default changed from '{:?}' to '{:?}'",
+                            zone.zone_ref, old_zone.proxied_default, zone.proxied_default
+                        );
+                    }
+                }
+                None => info!(
+                    "Zone '{}' added with <{}> hosts",
+                    zone.zone_ref,
+                    zone.hosts.len()
+                ),
+            }
+        }
+        for zone in &old.zones {
+            if !self.zones.iter().any(|z| z.zone_ref == zone.zone_ref) {
+                info!("Zone '{}' removed", zone.zone_ref);
+            }
+        }
+    }
+}
+
+/// Watches the config files for changes, by checking their modification times
+#[derive(Debug)]
+pub struct ConfigWatcher {
+    /// Config files, with their modification times
+    files: Vec<(String, Option<SystemTime>)>,
+}
+
+impl ConfigWatcher {
+    /// Watch the file at `CF_DNS_CONFIG_FILE`, or the files in `CF_DNS_CONFIG_DIR`. Returns `None` if no config file
+    /// is used.
+    pub fn new() -> Option<Self> {
+        config_file_paths().ok().flatten().map(|paths| Self {
+            files: modified_times(paths),
+        })
+    }
+
+    /// Whether a config file was modified, added or removed since the last call
+    pub fn changed(&mut self) -> bool {
+        self.changed_files(config_file_paths().ok().flatten().unwrap_or_default())
+    }
+
+    /// Whether one of the config files at `paths` was modified, or a file was added or removed, since the last call
+    fn changed_files(&mut self, paths: Vec<String>) -> bool {
+        let files = modified_times(paths);
+        if files != self.files {
+            self.files = files;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn modified_times(paths: Vec<String>) -> Vec<(String, Option<SystemTime>)> {
+    paths
+        .into_iter()
+        .map(|path| {
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+            (path, modified)
+        })
+        .collect()
+}
+
+/// Paths of the config files: either the file at `CF_DNS_CONFIG_FILE`, or the `*.toml` files in `CF_DNS_CONFIG_DIR`
+/// sorted by name. Returns `None` if no config file is used.
+fn config_file_paths() -> Result<Option<Vec<String>>, ()> {
+    let file = env::var("CF_DNS_CONFIG_FILE").ok();
+    let dir = env::var("CF_DNS_CONFIG_DIR").ok();
+
+    match (file, dir) {
+        (Some(file), None) => Ok(Some(vec![file.trim().to_string()])),
+        (None, Some(dir)) => {
+            let dir = dir.trim();
+            let entries = fs::read_dir(dir).map_err(|e| {
+                error!("Could not read config directory '{dir}': {e}");
+            })?;
+            let mut paths = entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "toml"))
+                .map(|path| path.to_string_lossy().to_string())
+                .collect::<Vec<_>>();
+            if paths.is_empty() {
+                error!("No `*.toml` config files found in '{dir}'");
+                return Err(());
+            }
+            paths.sort();
+            Ok(Some(paths))
+        }
+        (None, None) => Ok(None),
+        (Some(_), Some(_)) => {
+            error!("Only one of `CF_DNS_CONFIG_FILE` and `CF_DNS_CONFIG_DIR` can be set");
+            Err(())
+        }
+    }
+}
+
+/// Make sure that some host or desired record is configured in the `zones`, unless `allow_no_hosts`
+///
+/// e.g. `CF_DNS_HOSTS=;` leaves no hosts after parsing.
+fn check_some_hosts(zones: &[ZoneConfig], allow_no_hosts: bool) -> Result<(), ()> {
+    if zones
+        .iter()
+        .any(|zone| !zone.hosts.is_empty() || zone.records.is_some())
+    {
+        return Ok(());
+    }
+    if allow_no_hosts {
+        warn!("No hosts are configured, nothing will be updated");
+        Ok(())
+    } else {
+        error!(
+            "No hosts are configured. Check the `;` separators in `CF_DNS_HOSTS`, or set `CF_DNS_ALLOW_NO_HOSTS=true` to run anyway"
+        );
+        Err(())
+    }
+}
+
+/// Keep only the `endpoints` of the record types of the `,` separated `types` of `CF_DNS_RECORD_TYPES`, each of which
+/// must have an endpoint
+fn select_record_types<T>(types: &str, endpoints: &mut BTreeMap<RecordType, T>) -> Result<(), ()> {
+    let types = types
+        .split(',')
+        .map(|rtype| {
+            RecordType::try_from(rtype.trim()).map_err(|_| {
+                error!(
+                    "Could not parse `CF_DNS_RECORD_TYPES` which should be a `,` separated list of `A` and `AAAA`"
+                );
+            })
+        })
+        .collect::<Result<Vec<_>, ()>>()?;
+    if let Some(rtype) = types.iter().find(|rtype| !endpoints.contains_key(rtype)) {
+        error!(
+            "'{rtype}' records are selected in `CF_DNS_RECORD_TYPES`, but no IP endpoint is defined for them"
+        );
+        return Err(());
+    }
+    endpoints.retain(|rtype, _| types.contains(rtype));
+    Ok(())
+}
+
+/// Parse an environment variable, or use the `default` value if it is not set
+fn env_parse<T: FromStr>(name: &str, default: T) -> Result<T, ()> {
+    match env::var(name) {
+        Ok(value) => value.trim().parse().map_err(|_| ()),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Read the proxied status of the created records of each type from `CF_DNS_DEFAULT_PROXIED_A` and
+/// `CF_DNS_DEFAULT_PROXIED_AAAA`
+fn proxied_by_type() -> Result<BTreeMap<RecordType, bool>, ()> {
+    let mut proxied = BTreeMap::new();
+    for rtype in [RecordType::A, RecordType::AAAA] {
+        let name = format!("CF_DNS_DEFAULT_PROXIED_{rtype}");
+        if env::var(&name).is_ok() {
+            proxied.insert(rtype, env_bool(&name, false)?);
+        }
+    }
+    Ok(proxied)
+}
+
+/// Read the TTL of the created records of each type from `CF_DNS_DEFAULT_TTL_A` and `CF_DNS_DEFAULT_TTL_AAAA`
+fn ttl_by_type() -> Result<BTreeMap<RecordType, Ttl>, ()> {
+    let mut ttls = BTreeMap::new();
+    for rtype in [RecordType::A, RecordType::AAAA] {
+        let name = format!("CF_DNS_DEFAULT_TTL_{rtype}");
+        if let Ok(ttl) = env::var(&name) {
+            let ttl = ttl.trim().parse::<u32>().map(Ttl::try_from);
+            let Ok(Ok(ttl)) = ttl else {
+                error!(
+                    "Could not parse `{name}` which should be `1` for automatic, or a TTL from `30` to `86400` seconds"
+                );
+                return Err(());
+            };
+            ttls.insert(rtype, ttl);
+        }
+    }
+    Ok(ttls)
+}
+
+/// Read the TTL bounds from `CF_DNS_TTL_MIN` and `CF_DNS_TTL_MAX`
+fn ttl_limits() -> Result<TtlLimits, ()> {
+    let bound = |name: &str| match env::var(name) {
+        Ok(ttl) => match ttl.trim().parse::<u32>().map(Ttl::try_from) {
+            Ok(Ok(Ttl::Seconds(seconds))) => Ok(Some(seconds)),
+            _ => {
+                error!(
+                    "Could not parse `{name}` which should be a TTL from `30` to `86400` seconds"
+                );
+                Err(())
+            }
+        },
+        Err(_) => Ok(None),
+    };
+
+    let limits = TtlLimits {
+        min: bound("CF_DNS_TTL_MIN")?,
+        max: bound("CF_DNS_TTL_MAX")?,
+    };
+    if let (Some(min), Some(max)) = (limits.min, limits.max)
+        && min > max
+    {
+        error!("`CF_DNS_TTL_MIN` ({min}) is larger than `CF_DNS_TTL_MAX` ({max})");
+        return Err(());
+    }
+    Ok(limits)
+}
+
+/// Read the propagation check from `CF_DNS_PROPAGATION_RESOLVERS`, `CF_DNS_PROPAGATION_QUORUM` and
+/// `CF_DNS_PROPAGATION_GRACE_SECONDS`
+fn propagation_check() -> Result<Option<PropagationCheck>, ()> {
+    let resolvers = env::var("CF_DNS_PROPAGATION_RESOLVERS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|resolver| resolver.trim().to_string())
+        .filter(|resolver| !resolver.is_empty())
+        .collect::<Vec<_>>();
+    if resolvers.is_empty() {
+        return Ok(None);
+    }
+
+    let quorum = env_parse("CF_DNS_PROPAGATION_QUORUM", resolvers.len()).map_err(|_| {
+        error!("Could not parse `CF_DNS_PROPAGATION_QUORUM` which should be an unsigned value");
+    })?;
+    if quorum == 0 || quorum > resolvers.len() {
+        error!(
+            "`CF_DNS_PROPAGATION_QUORUM` ({quorum}) should be from `1` to the number of resolvers ({})",
+            resolvers.len()
+        );
+        return Err(());
+    }
+    let grace = env_parse("CF_DNS_PROPAGATION_GRACE_SECONDS", 60).map_err(|_| {
+        error!(
+            "Could not parse `CF_DNS_PROPAGATION_GRACE_SECONDS` which should be an unsigned value"
+        );
+    })?;
+
+    Ok(Some(PropagationCheck {
+        resolvers,
+        quorum,
+        grace: Duration::from_secs(grace),
+    }))
+}
+
+/// Read the IP churn limit from `CF_DNS_IP_CHURN_THRESHOLD` and `CF_DNS_IP_CHURN_WINDOW_SECONDS`
+fn ip_churn() -> Result<Option<ChurnLimit>, ()> {
+    let changes = env_parse("CF_DNS_IP_CHURN_THRESHOLD", 0).map_err(|_| {
+        error!("Could not parse `CF_DNS_IP_CHURN_THRESHOLD` which should be an unsigned value");
+    })?;
+    let window = env_parse("CF_DNS_IP_CHURN_WINDOW_SECONDS", 86400).map_err(|_| {
+        error!(
+            "Could not parse `CF_DNS_IP_CHURN_WINDOW_SECONDS` which should be an unsigned value"
+        );
+    })?;
+
+    Ok((changes > 0).then(|| ChurnLimit {
+        changes,
+        window: Duration::from_secs(window),
+    }))
+}
+
+/// Parse a prefix length environment variable in the form `/24`, which can't exceed `max`
+fn env_prefix(name: &str, max: u8) -> Result<Option<u8>, ()> {
+    match env::var(name) {
+        Ok(value) => {
+            let value = value.trim();
+            match value.strip_prefix('/').unwrap_or(value).parse::<u8>() {
+                Ok(len) if len <= max => Ok(Some(len)),
+                _ => {
+                    error!(
+                        "Could not parse `{name}` which should be a prefix length from `/0` to `/{max}`"
+                    );
+                    Err(())
+                }
+            }
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+/// Parse a `true`/`false` environment variable, or use the `default` value if it is not set
+fn env_bool(name: &str, default: bool) -> Result<bool, ()> {
+    env_parse(name, default).map_err(|_| {
+        error!("Could not read `{name}` which should be either `true` or `false`");
+    })
+}
+
+/// Load the environment variables of the env file into the environment
+///
+/// The file is the one at `CF_DNS_ENV_FILE`, which must exist, or else the first `.env` file in the current directory
+/// or its parents, if any. Since the log settings can be given in the env file, it is loaded before logging is set up,
+/// and the error is returned to be logged afterwards. The malformed line itself is not part of the error, in case it
+/// holds a secret.
+pub fn load_env_file() -> Result<(), String> {
+    let path = match env::var("CF_DNS_ENV_FILE") {
+        Ok(path) => PathBuf::from(path.trim()),
+        Err(_) => match env::current_dir().ok().and_then(|dir| find_env_file(&dir)) {
+            Some(path) => path,
+            None => return Ok(()),
+        },
+    };
+    read_env_file(&path)
+}
+
+/// First `.env` file in `dir` or its parents
+fn find_env_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(".env"))
+        .find(|path| path.is_file())
+}
+
+/// Load the environment variables of the env file at `path`, see [`load_env_file`]
+fn read_env_file(path: &Path) -> Result<(), String> {
+    dotenv::from_path(path).map_err(|e| match e {
+        dotenv::Error::LineParse(line, index) => {
+            let number = fs::read_to_string(path)
+                .ok()
+                .and_then(|text| text.lines().position(|l| l.contains(line.as_str())))
+                .map(|position| format!("line <{}>, ", position + 1))
+                .unwrap_or_default();
+            format!(
+                "Could not parse env file '{}' at {number}character <{}>. Lines should be `NAME=value`, with \
+                values containing spaces or `#` in quotes",
+                path.display(),
+                index + 1
+            )
+        }
+        e => format!("Could not read env file '{}': {e}", path.display()),
+    })
+}
+
+/// Read the headers of `CF_DNS_EXTRA_HEADERS`, see [`parse_extra_headers`]
+fn extra_headers() -> Result<Vec<(String, String)>, ()> {
+    match env::var("CF_DNS_EXTRA_HEADERS") {
+        Ok(headers) => parse_extra_headers(&headers),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+/// Parse the newline separated `Name: Value` `headers`, and make sure they are valid and don't replace the headers set
+/// by the app
+fn parse_extra_headers(headers: &str) -> Result<Vec<(String, String)>, ()> {
+    let mut parsed = Vec::new();
+    for line in headers
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        let Some((name, value)) = line.split_once(':') else {
+            error!("Header '{line}' of `CF_DNS_EXTRA_HEADERS` should be given as `Name: Value`");
+            return Err(());
+        };
+        let (name, value) = (name.trim(), value.trim());
+        if http::HeaderName::from_bytes(name.as_bytes()).is_err() {
+            error!("'{name}' of `CF_DNS_EXTRA_HEADERS` is not a valid header name");
+            return Err(());
+        }
+        if http::HeaderValue::from_str(value).is_err() {
+            error!(
+                "The value of header '{name}' of `CF_DNS_EXTRA_HEADERS` is not a valid header value"
+            );
+            return Err(());
+        }
+        if let Some(reserved) = RESERVED_HEADERS
+            .iter()
+            .find(|reserved| reserved.eq_ignore_ascii_case(name))
+        {
+            error!(
+                "Header '{reserved}' is set by the app, it can't be given in `CF_DNS_EXTRA_HEADERS`"
+            );
+            return Err(());
+        }
+        parsed.push((name.to_string(), value.to_string()));
+    }
+    Ok(parsed)
+}
+
+/// Read the proxy URL from `CF_DNS_IP_DETECT_PROXY`, see [`parse_ip_detect_proxy`]
+fn ip_detect_proxy() -> Result<Option<String>, ()> {
+    match env::var("CF_DNS_IP_DETECT_PROXY") {
+        Ok(proxy) => parse_ip_detect_proxy(&proxy).map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Make sure that the IP detection `proxy` URL has a supported scheme
+fn parse_ip_detect_proxy(proxy: &str) -> Result<String, ()> {
+    const SCHEMES: [&str; 4] = ["http://", "https://", "socks5://", "socks5h://"];
+
+    let proxy = proxy.trim();
+    if SCHEMES.iter().any(|scheme| proxy.starts_with(scheme)) {
+        Ok(proxy.to_string())
+    } else {
+        error!(
+            "`CF_DNS_IP_DETECT_PROXY` must start with one of {}",
+            SCHEMES.join(", ")
+        );
+        Err(())
+    }
+}
+
+/// Parse a list of `;` separated host names
+///
+/// Each entry is trimmed, and anything after a `#` is a comment, so that entries which are empty or only a comment are
+/// ignored. Names with placeholders are checked once they are rendered, and `@` is the zone's apex.
+fn parse_hosts(hosts: &str) -> Result<Vec<HostConfig>, ()> {
+    let mut parsed = Vec::new();
+    for (index, entry) in hosts.split(';').enumerate() {
+        let name = entry.split('#').next().unwrap_or_default().trim();
+        if name.is_empty() {
+            continue;
+        }
+        if !name.contains('{') && name != "@" {
+            validate_host_name(name).map_err(|e| {
+                error!("Invalid entry <{}> in `CF_DNS_HOSTS`: {e}", index + 1);
+            })?;
+        }
+        parsed.push(HostConfig {
+            name: name.to_string(),
+            proxied: None,
+            webhook: None,
+            enabled: true,
+            manage: Manage::Full,
+        });
+    }
+    Ok(parsed)
+}
+
+/// Index of the zone which `host` belongs to, among zones with the given names (`None` if unknown)
+///
+/// A host belongs to a zone if it is the zone's apex or one of its subdomains. When several zones contain the host,
+/// `selection` decides which one is used, and a warning is logged.
+pub fn zone_for_host(
+    host: &str,
+    zones: &[Option<&str>],
+    selection: ZoneSelection,
+) -> Option<usize> {
+    let host = host.trim_end_matches('.');
+    let matches = zones
+        .iter()
+        .enumerate()
+        .filter_map(|(index, zone)| Some((index, zone.as_ref()?.trim_end_matches('.'))))
+        .filter(|(_, zone)| {
+            host.eq_ignore_ascii_case(zone)
+                || host.len() > zone.len()
+                    && host.as_bytes()[host.len() - zone.len() - 1] == b'.'
+                    && host[host.len() - zone.len()..].eq_ignore_ascii_case(zone)
+        })
+        .collect::<Vec<_>>();
+
+    let chosen = match selection {
+        // The first of the longest, so that the configured order breaks ties
+        ZoneSelection::LongestSuffixMatch => matches
+            .iter()
+            .rev()
+            .max_by_key(|(_, zone)| zone.len())
+            .copied(),
+        ZoneSelection::FirstMatch => matches.first().copied(),
+    };
+
+    if let Some((_, zone)) = chosen
+        && matches.len() > 1
+    {
+        warn!(
+            "'{host}' is in <{}> configured zones ({}), using zone '{zone}' ({selection})",
+            matches.len(),
+            matches
+                .iter()
+                .map(|(_, zone)| format!("'{zone}'"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    chosen.map(|(index, _)| index)
+}
+
+/// Check that `name` is a valid DNS name
+pub fn validate_host_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > 253 {
+        return Err(format!("'{name}' is not a valid host name"));
+    }
+    for label in name.split('.') {
+        let valid = !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid && label != "*" {
+            return Err(format!("'{name}' is not a valid host name"));
+        }
+    }
+    Ok(())
+}
+
+/// Replace the placeholders in the name of `host`: `{hostname}` with the machine's host name, and `{env:VAR}` with the
+/// value of the environment variable `VAR`
+///
+/// The resulting name must be a valid DNS name.
+fn render_host_name(mut host: HostConfig) -> Result<HostConfig, ()> {
+    if !host.name.contains('{') {
+        return Ok(host);
+    }
+
+    let template = host.name;
+    let mut name = String::new();
+    let mut rest = template.as_str();
+    while let Some(start) = rest.find('{') {
+        name.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            error!("Host '{template}' has an unclosed placeholder");
+            return Err(());
+        };
+        let placeholder = &rest[start + 1..start + end];
+        let value = match placeholder.strip_prefix("env:") {
+            Some(var) => env::var(var).map_err(|_| {
+                error!("Host '{template}' uses the environment variable `{var}`, which is not set");
+            })?,
+            None if placeholder == "hostname" => machine_hostname().ok_or_else(|| {
+                error!("Host '{template}' uses the machine's host name, which could not be read");
+            })?,
+            None => {
+                error!(
+                    "Host '{template}' has an unknown placeholder '{{{placeholder}}}', only `{{hostname}}` and `{{env:VAR}}` are supported"
+                );
+                return Err(());
+            }
+        };
+        name.push_str(value.trim());
+        rest = &rest[start + end + 1..];
+    }
+    name.push_str(rest);
+
+    let name = name.to_lowercase();
+    validate_host_name(&name).map_err(|e| {
+        error!("Host '{template}' resolves to an invalid name: {e}");
+    })?;
+    info!("Host '{template}' resolves to '{name}'");
+    host.name = name;
+    Ok(host)
+}
+
+/// Host name of the machine, from the kernel or else from the `HOSTNAME` environment variable
+fn machine_hostname() -> Option<String> {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| env::var("HOSTNAME").ok())
+        .map(|hostname| hostname.trim().to_string())
+        .filter(|hostname| !hostname.is_empty())
+}
+
+/// Resolve the placeholders in the host names, and remove the duplicate entries of the same host, keeping the order in
+/// which hosts are first configured
+///
+/// Identical duplicates are merged. Duplicates with different settings are handled according to `policy`.
+fn dedup_hosts(
+    hosts: impl Iterator<Item = HostConfig>,
+    policy: DuplicateHosts,
+) -> Result<Vec<HostConfig>, ()> {
+    let mut deduped: Vec<HostConfig> = Vec::new();
+
+    for host in hosts {
+        let host = render_host_name(host)?;
+        match deduped.iter_mut().find(|h| h.name == host.name) {
+            Some(existing) if *existing == host => {
+                debug!("Ignoring duplicate host '{}'", host.name);
+            }
+            Some(existing) => match policy {
+                DuplicateHosts::Error => {
+                    error!(
+                        "Host '{}' is configured more than once with different settings",
+                        host.name
+                    );
+                    return Err(());
+                }
+                DuplicateHosts::Last => {
+                    warn!(
+                        "Host '{}' is configured more than once with different settings, using the last one",
+                        host.name
+                    );
+                    *existing = host;
+                }
+            },
+            None => deduped.push(host),
+        }
+    }
+
+    Ok(deduped)
+}
+
+/// Read and merge the config files at `paths`
+///
+/// Each file contributes its zones. A setting given in more than one file, or a zone defined in more than one file, is
+/// an error.
+fn read_config_files(paths: &[String]) -> Result<ConfigFile, ()> {
+    let mut merged = ConfigFile::default();
+    // File in which each zone is defined, by id or name
+    let mut zone_files: BTreeMap<String, &str> = BTreeMap::new();
+
+    for path in paths {
+        let file = read_config_file(path)?;
+
+        merge_setting(
+            &mut merged.repeat_interval_seconds,
+            file.repeat_interval_seconds,
+            "repeat_interval_seconds",
+            path,
+        )?;
+        merge_setting(
+            &mut merged.ipv4_endpoint,
+            file.ipv4_endpoint,
+            "ipv4_endpoint",
+            path,
+        )?;
+        merge_setting(
+            &mut merged.ipv6_endpoint,
+            file.ipv6_endpoint,
+            "ipv6_endpoint",
+            path,
+        )?;
+
+        for zone in file.zones {
+            // Zones given one by id and the other by name are only found to be the same once the name is resolved
+            let key = zone
+                .id
+                .clone()
+                .or(zone.name.clone())
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase();
+            if let Some(other) = zone_files.insert(key.clone(), path) {
+                error!("Zone '{key}' is defined in both '{other}' and '{path}'");
+                return Err(());
+            }
+            merged.zones.push(zone);
+        }
+    }
+
+    Ok(merged)
+}
+
+/// Set a top level setting of the merged config files, unless it was already set by another file
+fn merge_setting<T>(
+    merged: &mut Option<T>,
+    value: Option<T>,
+    name: &str,
+    path: &str,
+) -> Result<(), ()> {
+    if value.is_some() {
+        if merged.is_some() {
+            error!("`{name}` in '{path}' is already set in another config file");
+            return Err(());
+        }
+        *merged = value;
+    }
+    Ok(())
+}
+
+/// Keys of the top level of the config file
+const FILE_KEYS: [&str; 4] = [
+    "repeat_interval_seconds",
+    "ipv4_endpoint",
+    "ipv6_endpoint",
+    "zones",
+];
+
+/// Keys of a zone of the config file
+const ZONE_KEYS: [&str; 5] = ["id", "name", "proxied_default", "hosts", "api_token"];
+
+/// Keys of a host of the config file which is given as a table
+const HOST_KEYS: [&str; 5] = ["name", "proxied", "webhook", "enabled", "manage"];
+
+/// Number of single character insertions, deletions or substitutions which turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Check that every key of `table` is one of the `known` keys
+///
+/// Serde ignores unknown keys, so a misspelled setting silently has no effect. Every unknown key is logged with the
+/// closest known key, as an error if `strict` (`CF_DNS_CONFIG_STRICT`), and as a warning otherwise.
+pub fn check_keys(
+    table: &toml::Table,
+    known: &[&str],
+    location: &str,
+    strict: bool,
+) -> Result<(), ()> {
+    let mut valid = true;
+    for key in table.keys().filter(|key| !known.contains(&key.as_str())) {
+        let suggestion = known
+            .iter()
+            .map(|candidate| (edit_distance(key, candidate), candidate))
+            .filter(|(distance, _)| *distance <= 2)
+            .min()
+            .map(|(_, candidate)| format!(", did you mean `{candidate}`?"))
+            .unwrap_or_default();
+        if strict {
+            error!("Unknown key `{key}` in {location}{suggestion}");
+            valid = false;
+        } else {
+            warn!("Unknown key `{key}` in {location} is ignored{suggestion}");
+        }
+    }
+    if valid { Ok(()) } else { Err(()) }
+}
+
+/// Check the keys of every table of a config file, see [`check_keys`]
+fn check_config_keys(file: &toml::Table, path: &str, strict: bool) -> Result<(), ()> {
+    let mut valid = check_keys(file, &FILE_KEYS, &format!("config file '{path}'"), strict).is_ok();
+    let zones = file.get("zones").and_then(|zones| zones.as_array());
+    for (z, zone) in zones.into_iter().flatten().enumerate() {
+        let Some(zone) = zone.as_table() else {
+            continue;
+        };
+        let location = format!("zone <{}> of config file '{path}'", z + 1);
+        valid &= check_keys(zone, &ZONE_KEYS, &location, strict).is_ok();
+
+        let hosts = zone.get("hosts").and_then(|hosts| hosts.as_array());
+        for (h, host) in hosts.into_iter().flatten().enumerate() {
+            if let Some(host) = host.as_table() {
+                let location = format!(
+                    "host <{}> of zone <{}> of config file '{path}'",
+                    h + 1,
+                    z + 1
+                );
+                valid &= check_keys(host, &HOST_KEYS, &location, strict).is_ok();
+            }
+        }
+    }
+    if valid { Ok(()) } else { Err(()) }
+}
+
+fn read_config_file(path: &str) -> Result<ConfigFile, ()> {
+    let text = fs::read_to_string(path).map_err(|e| {
+        error!("Could not read config file '{path}': {e}");
+    })?;
+
+    let table: toml::Table = toml::from_str(&text).map_err(|e| {
+        error!("Could not parse config file '{path}': {e}");
+    })?;
+    check_config_keys(&table, path, env_bool("CF_DNS_CONFIG_STRICT", false)?)?;
+
+    table.try_into().map_err(|e| {
+        error!("Could not parse config file '{path}': {e}");
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging;
+    use crate::zone::Zone;
+
+    /// Write each of `files` to a new temporary directory, and return their paths in order
+    fn write_files(files: &[&str]) -> Vec<String> {
+        let dir = env::temp_dir().join(format!("cf-dns-rs-config-{}", uuid::Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        files
+            .iter()
+            .enumerate()
+            .map(|(index, text)| {
+                let path = dir.join(format!("{index}.toml"));
+                fs::write(&path, text).unwrap();
+                path.to_string_lossy().to_string()
+            })
+            .collect()
+    }
+
+    fn host(name: &str) -> HostConfig {
+        HostConfig::from(HostEntry::Name(name.to_string()))
+    }
+
+    fn names(hosts: &[HostConfig]) -> Vec<&str> {
+        hosts.iter().map(|host| host.name.as_str()).collect()
+    }
+
+    #[test]
+    fn merges_identical_duplicate_hosts_in_order() {
+        let hosts = [
+            host("b.example.com"),
+            host("a.example.com"),
+            host("b.example.com"),
+        ];
+
+        let deduped = dedup_hosts(hosts.into_iter(), DuplicateHosts::Error).unwrap();
+
+        assert_eq!(names(&deduped), ["b.example.com", "a.example.com"]);
+    }
+
+    #[test]
+    fn handles_conflicting_duplicate_hosts_according_to_the_policy() {
+        let proxied = HostConfig {
+            proxied: Some(true),
+            ..host("www.example.com")
+        };
+        let hosts = [host("www.example.com"), host("api.example.com"), proxied];
+
+        assert!(dedup_hosts(hosts.clone().into_iter(), DuplicateHosts::Error).is_err());
+        let deduped = dedup_hosts(hosts.into_iter(), DuplicateHosts::Last).unwrap();
+        assert_eq!(names(&deduped), ["www.example.com", "api.example.com"]);
+        assert_eq!(deduped[0].proxied, Some(true));
+    }
+
+    #[test]
+    fn picks_up_the_hosts_of_a_changed_config_file() {
+        let paths = write_files(&[
+            "[[zones]]\nid = \"abc\"\nhosts = [\"a.example.com\", \"b.example.com\"]\n",
+        ]);
+        let mut watcher = ConfigWatcher {
+            files: modified_times(paths.clone()),
+        };
+        assert!(!watcher.changed_files(paths.clone()));
+
+        fs::write(
+            &paths[0],
+            "[[zones]]\nid = \"abc\"\nhosts = [\"a.example.com\", \"c.example.com\"]\n",
+        )
+        .unwrap();
+        // The modification time may not have changed yet at the resolution of the file system
+        let modified = SystemTime::now() + Duration::from_secs(1);
+        fs::File::options()
+            .write(true)
+            .open(&paths[0])
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+
+        assert!(watcher.changed_files(paths.clone()));
+        assert!(!watcher.changed_files(paths.clone()));
+        let zone = read_config_files(&paths).unwrap().zones.remove(0);
+        let hosts = zone
+            .hosts
+            .into_iter()
+            .map(|h| HostConfig::from(h).name)
+            .collect::<Vec<_>>();
+        assert_eq!(hosts, ["a.example.com", "c.example.com"]);
+        assert!(watcher.changed_files(Vec::new()));
+    }
+
+    #[test]
+    fn merges_the_zones_of_the_config_files() {
+        let paths = write_files(&[
+            "[[zones]]\nid = \"abc\"\nhosts = [\"www.example.com\"]\n",
+            "repeat_interval_seconds = 60\n[[zones]]\nname = \"example.org\"\nhosts = []\n",
+        ]);
+
+        let merged = read_config_files(&paths).unwrap();
+
+        assert_eq!(merged.repeat_interval_seconds, Some(60));
+        assert_eq!(merged.zones.len(), 2);
+    }
+
+    #[test]
+    fn rejects_a_zone_defined_in_two_config_files() {
+        let paths = write_files(&[
+            "[[zones]]\nid = \"abc\"\nhosts = []\n",
+            "[[zones]]\nid = \"abc\"\nhosts = []\n",
+        ]);
+        assert!(read_config_files(&paths).is_err());
+
+        let paths = write_files(&[
+            "[[zones]]\nname = \"example.com\"\nhosts = []\n",
+            "[[zones]]\nname = \"Example.com\"\nhosts = []\n",
+        ]);
+        assert!(read_config_files(&paths).is_err());
+    }
+
+    #[test]
+    fn restricts_the_managed_record_types() {
+        let mut endpoints = BTreeMap::from([(RecordType::A, "v4"), (RecordType::AAAA, "v6")]);
+        select_record_types(" A ", &mut endpoints).unwrap();
+        assert_eq!(endpoints, BTreeMap::from([(RecordType::A, "v4")]));
+
+        let mut endpoints = BTreeMap::from([(RecordType::A, "v4"), (RecordType::AAAA, "v6")]);
+        select_record_types("A,AAAA", &mut endpoints).unwrap();
+        assert_eq!(endpoints.len(), 2);
+    }
+
+    #[test]
+    fn rejects_a_record_type_without_an_endpoint() {
+        logging::init_for_tests();
+        let mut endpoints = BTreeMap::from([(RecordType::A, "v4")]);
+
+        let (selected, errors) =
+            logging::capture_errors(|| select_record_types("A,AAAA", &mut endpoints));
+
+        assert!(selected.is_err());
+        assert_eq!(
+            errors,
+            [
+                "'AAAA' records are selected in `CF_DNS_RECORD_TYPES`, but no IP endpoint is defined for them"
+            ]
+        );
+        assert!(select_record_types("A,CNAME", &mut endpoints).is_err());
+    }
+
+    #[test]
+    fn detects_that_no_hosts_are_left_after_parsing() {
+        let zone = |hosts: &str| ZoneConfig {
+            zone_ref: ZoneRef::Id("abc".to_string()),
+            proxied_default: None,
+            hosts: parse_hosts(hosts).unwrap(),
+            auth: None,
+            records: None,
+        };
+
+        assert!(check_some_hosts(&[zone(" ; ;;")], false).is_err());
+        assert!(check_some_hosts(&[zone(" ; ;;")], true).is_ok());
+        assert!(check_some_hosts(&[zone(";"), zone("www.example.com;")], false).is_ok());
+        let desired_only = ZoneConfig {
+            records: Some(Vec::new()),
+            ..zone("")
+        };
+        assert!(check_some_hosts(&[desired_only], false).is_ok());
+    }
+
+    #[test]
+    fn renders_the_placeholders_of_a_host_name() {
+        // Set by cargo when running the tests
+        let package = env::var("CARGO_PKG_NAME").unwrap();
+        let rendered = render_host_name(host("{env:CARGO_PKG_NAME}.dyn.example.com")).unwrap();
+        assert_eq!(rendered.name, format!("{package}.dyn.example.com"));
+
+        if let Some(hostname) = machine_hostname() {
+            let rendered = render_host_name(host("{hostname}.example.com"));
+            let expected = format!("{}.example.com", hostname.to_lowercase());
+            match validate_host_name(&expected) {
+                Ok(()) => assert_eq!(rendered.unwrap().name, expected),
+                Err(_) => assert!(rendered.is_err()),
+            }
+        }
+
+        let plain = render_host_name(host("www.example.com")).unwrap();
+        assert_eq!(plain.name, "www.example.com");
+    }
+
+    #[test]
+    fn rejects_a_host_name_which_renders_to_an_invalid_name() {
+        logging::init_for_tests();
+
+        // `PATH` holds `/` and `:`, which can't be in a DNS name
+        let (rendered, errors) =
+            logging::capture_errors(|| render_host_name(host("{env:PATH}.example.com")));
+        assert!(rendered.is_err());
+        assert!(
+            errors[0].starts_with("Host '{env:PATH}.example.com' resolves to an invalid name"),
+            "{errors:?}"
+        );
+
+        assert!(render_host_name(host("{env:CF_DNS_TEST_UNSET}.example.com")).is_err());
+        assert!(render_host_name(host("{user}.example.com")).is_err());
+        assert!(render_host_name(host("{hostname.example.com")).is_err());
+    }
+
+    #[test]
+    fn trims_the_host_entries_and_ignores_the_comments() {
+        let hosts = parse_hosts(
+            " www.example.com \t; # disabled.example.com;\tapi.example.com # the API ;;@",
+        )
+        .unwrap();
+        assert_eq!(names(&hosts), ["www.example.com", "api.example.com", "@"]);
+    }
+
+    #[test]
+    fn rejects_an_invalid_host_entry() {
+        logging::init_for_tests();
+
+        let (hosts, errors) =
+            logging::capture_errors(|| parse_hosts("www.example.com;bad host.example.com"));
+
+        assert!(hosts.is_err());
+        assert_eq!(
+            errors,
+            [
+                "Invalid entry <2> in `CF_DNS_HOSTS`: 'bad host.example.com' is not a valid host name"
+            ]
+        );
+        assert!(parse_hosts("www..example.com").is_err());
+        assert!(parse_hosts("-www.example.com").is_err());
+        // Placeholders are checked once rendered
+        assert!(parse_hosts("{hostname}.example.com").is_ok());
+    }
+
+    #[test]
+    fn counts_the_edits_between_two_keys() {
+        assert_eq!(edit_distance("proxied", "proxied"), 0);
+        assert_eq!(edit_distance("proxyed", "proxied"), 1);
+        assert_eq!(edit_distance("hosts", "host"), 1);
+        assert_eq!(edit_distance("", "name"), 4);
+        assert_eq!(edit_distance("enabled", "webhook"), 7);
+    }
+
+    #[test]
+    fn rejects_a_misspelled_key_only_when_strict() {
+        logging::init_for_tests();
+        let file: toml::Table =
+            toml::from_str("[[zones]]\nid = \"abc\"\nproxyed_default = true\nhosts = []\n")
+                .unwrap();
+
+        let (checked, errors) =
+            logging::capture_errors(|| check_config_keys(&file, "cf.toml", true));
+        assert!(checked.is_err());
+        assert_eq!(
+            errors,
+            [
+                "Unknown key `proxyed_default` in zone <1> of config file 'cf.toml', did you mean `proxied_default`?"
+            ]
+        );
+
+        let (checked, errors) =
+            logging::capture_errors(|| check_config_keys(&file, "cf.toml", false));
+        assert!(checked.is_ok());
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn parses_the_extra_headers() {
+        let headers =
+            parse_extra_headers("X-Audit: cf-dns-rs\n\n  X-Purpose :  dns updates \n").unwrap();
+        assert_eq!(
+            headers,
+            [
+                ("X-Audit".to_string(), "cf-dns-rs".to_string()),
+                ("X-Purpose".to_string(), "dns updates".to_string())
+            ]
+        );
+        assert!(parse_extra_headers("X-Audit cf-dns-rs").is_err());
+        assert!(parse_extra_headers("X Audit: cf-dns-rs").is_err());
+    }
+
+    #[test]
+    fn rejects_an_extra_header_which_is_set_by_the_app() {
+        logging::init_for_tests();
+
+        let (headers, errors) = logging::capture_errors(|| {
+            parse_extra_headers("X-Audit: 1\nauthorization: Bearer other")
+        });
+
+        assert!(headers.is_err());
+        assert_eq!(
+            errors,
+            [
+                "Header 'Authorization' is set by the app, it can't be given in `CF_DNS_EXTRA_HEADERS`"
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_the_creation_defaults_of_each_record_type() {
+        let mut config = Config::for_tests(&[RecordType::A, RecordType::AAAA]);
+        config.proxied_default = false;
+        config.proxied_by_type = BTreeMap::from([(RecordType::A, true)]);
+        config.ttl_by_type = BTreeMap::from([(RecordType::AAAA, Ttl::Seconds(300))]);
+
+        assert!(config.proxied_for(RecordType::A));
+        assert!(!config.proxied_for(RecordType::AAAA));
+        assert_eq!(config.ttl_for(RecordType::A), Ttl::Auto);
+        assert_eq!(config.ttl_for(RecordType::AAAA), Ttl::Seconds(300));
+
+        // The host overrides the default of the type
+        let zone = Zone {
+            id: "abc".to_string(),
+            name: None,
+            status: None,
+            proxied_default: None,
+        };
+        let unproxied = HostConfig {
+            proxied: Some(false),
+            ..host("www.example.com")
+        };
+        assert!(zone.proxied(&host("www.example.com"), config.proxied_for(RecordType::A)));
+        assert!(!zone.proxied(&unproxied, config.proxied_for(RecordType::A)));
+    }
+
+    #[test]
+    fn explains_why_an_env_file_is_malformed() {
+        let path = write_files(&["# Cloudflare\nCF_DNS_TEST ONE=1\n"]).remove(0);
+
+        let error = read_env_file(Path::new(&path)).unwrap_err();
+
+        assert_eq!(
+            error,
+            format!(
+                "Could not parse env file '{path}' at line <2>, character <13>. Lines should be `NAME=value`, with \
+                values containing spaces or `#` in quotes"
+            )
+        );
+        assert!(env::var("CF_DNS_TEST").is_err());
+    }
+
+    #[test]
+    fn tolerates_an_absent_env_file() {
+        let dir = Path::new(&write_files(&[""])[0])
+            .parent()
+            .unwrap()
+            .to_path_buf();
+        assert_eq!(find_env_file(&dir), None);
+
+        fs::write(dir.join(".env"), "").unwrap();
+        let nested = dir.join("nested");
+        fs::create_dir(&nested).unwrap();
+        assert_eq!(find_env_file(&nested), Some(dir.join(".env")));
+        assert!(read_env_file(&dir.join("missing.env")).is_err());
+    }
+
+    #[test]
+    fn accepts_only_the_supported_proxy_schemes() {
+        assert_eq!(
+            parse_ip_detect_proxy(" socks5://127.0.0.1:1080 "),
+            Ok("socks5://127.0.0.1:1080".to_string())
+        );
+        assert!(parse_ip_detect_proxy("socks5h://proxy.example:1080").is_ok());
+        assert!(parse_ip_detect_proxy("http://proxy.example:3128").is_ok());
+        assert!(parse_ip_detect_proxy("socks4://127.0.0.1:1080").is_err());
+        assert!(parse_ip_detect_proxy("127.0.0.1:1080").is_err());
+    }
+
+    #[test]
+    fn chooses_the_zone_of_a_host_among_overlapping_zones() {
+        let zones = [
+            Some("example.com"),
+            None,
+            Some("dev.example.com"),
+            Some("example.org"),
+        ];
+        let zone = |host, selection| zone_for_host(host, &zones, selection);
+
+        assert_eq!(
+            zone("api.dev.example.com", ZoneSelection::LongestSuffixMatch),
+            Some(2)
+        );
+        assert_eq!(
+            zone("api.dev.example.com", ZoneSelection::FirstMatch),
+            Some(0)
+        );
+        assert_eq!(
+            zone("Dev.Example.com.", ZoneSelection::LongestSuffixMatch),
+            Some(2)
+        );
+        assert_eq!(
+            zone("www.example.com", ZoneSelection::LongestSuffixMatch),
+            Some(0)
+        );
+        assert_eq!(
+            zone("example.org", ZoneSelection::LongestSuffixMatch),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn finds_no_zone_for_a_host_outside_of_every_zone() {
+        let zones = [Some("example.com"), Some("dev.example.com")];
+
+        for host in ["www.example.net", "notexample.com", "com"] {
+            assert_eq!(
+                zone_for_host(host, &zones, ZoneSelection::LongestSuffixMatch),
+                None,
+                "{host}"
+            );
+        }
+    }
+
+    #[test]
+    fn reads_how_much_of_a_host_is_managed() {
+        let manage = |entry: &str| {
+            let entry = toml::from_str::<HostEntry>(entry).unwrap();
+            HostConfig::from(entry).manage
+        };
+
+        assert_eq!(manage("name = \"www.example.com\""), Manage::Full);
+        assert_eq!(
+            manage("name = \"www.example.com\"\nmanage = \"create-only\""),
+            Manage::CreateOnly
+        );
+    }
+}
@@ -0,0 +1,410 @@
+//! Control socket.
+//!
+//! When `CF_DNS_CONTROL_SOCKET` is set, a Unix socket is created at that path, through which monitored hosts can be
+//! added and removed without a restart. Each line sent on a connection is one JSON command:
+//!
+//! ```json
+//! {"add_host": {"name": "www.example.com", "proxied": true, "zone": "example.com"}}
+//! {"remove_host": "www.example.com"}
+//! "status"
+//! ```
+//!
+//! and is answered with one JSON line, either `{"ok": true}` or `{"ok": false, "error": "<reason>"}`. `proxied`,
+//! `webhook` and `zone` are optional, the zone defaults to the configured zone which contains the host, chosen with
+//! `CF_DNS_ZONE_SELECTION`. The answer to `status` also lists the last error of every failing record in `last_errors`.
+//!
+//! Accepted changes are applied from the next cycle. They are not persisted, so they are lost when the config file is
+//! reloaded or the app restarts.
+
+use crate::config::{HostConfig, Manage, ZoneSelection, validate_host_name, zone_for_host};
+use crate::status;
+use crate::zone::ZoneClient;
+use log::{error, info, warn};
+use serde::Deserialize;
+use serde_json::json;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+static HOSTS: Mutex<HostSet> = Mutex::new(HostSet::new());
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum Command {
+    AddHost(AddHost),
+    RemoveHost(String),
+    Status,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AddHost {
+    name: String,
+    proxied: Option<bool>,
+    webhook: Option<String>,
+    /// Zone id or name
+    zone: Option<String>,
+}
+
+/// Monitored hosts of a single zone
+#[derive(Debug)]
+struct ZoneHosts {
+    id: String,
+    name: Option<String>,
+    hosts: Vec<HostConfig>,
+    /// Names of the zone's desired records, which can't also be monitored hosts
+    desired: Vec<String>,
+}
+
+impl ZoneHosts {
+    /// Whether the zone is the one referred to by `zone`, which is either its id or its name
+    fn is(&self, zone: &str) -> bool {
+        self.id == zone || self.name.as_deref() == Some(zone)
+    }
+}
+
+/// Monitored hosts of every zone, as changed through the control socket
+#[derive(Debug)]
+struct HostSet {
+    zones: Vec<ZoneHosts>,
+    /// How the zone of an added host is chosen when several zones contain it
+    selection: ZoneSelection,
+    /// Whether hosts were added or removed since the zones last took their hosts from the set
+    changed: bool,
+}
+
+impl HostSet {
+    const fn new() -> Self {
+        Self {
+            zones: Vec::new(),
+            selection: ZoneSelection::LongestSuffixMatch,
+            changed: false,
+        }
+    }
+
+    fn handle(&mut self, command: Command) -> Result<String, String> {
+        match command {
+            Command::Status => Err("`status` can't change the hosts".to_string()),
+            Command::AddHost(add) => {
+                let name = add.name.trim().to_string();
+                validate_host_name(&name)?;
+
+                if self
+                    .zones
+                    .iter()
+                    .any(|zone| zone.hosts.iter().any(|host| host.name == name))
+                {
+                    return Err(format!("'{name}' is already monitored"));
+                }
+
+                let index = match &add.zone {
+                    Some(zone) => self
+                        .zones
+                        .iter()
+                        .position(|z| z.is(zone.trim()))
+                        .ok_or_else(|| format!("Zone '{zone}' is not configured"))?,
+                    None => match zone_for_host(
+                        &name,
+                        &self
+                            .zones
+                            .iter()
+                            .map(|zone| zone.name.as_deref())
+                            .collect::<Vec<_>>(),
+                        self.selection,
+                    ) {
+                        Some(index) => index,
+                        None if self.zones.len() == 1 => 0,
+                        None => {
+                            return Err(format!(
+                                "No configured zone contains '{name}', set `zone` to choose one"
+                            ));
+                        }
+                    },
+                };
+
+                let zone = &mut self.zones[index];
+                if zone.desired.contains(&name) {
+                    return Err(format!("'{name}' is a desired record"));
+                }
+                zone.hosts.push(HostConfig {
+                    name: name.clone(),
+                    proxied: add.proxied,
+                    webhook: add.webhook,
+                    enabled: true,
+                    manage: Manage::Full,
+                });
+                self.changed = true;
+
+                Ok(format!(
+                    "Added host '{name}' to zone '{}'",
+                    zone.name.as_deref().unwrap_or(&zone.id)
+                ))
+            }
+            Command::RemoveHost(name) => {
+                let name = name.trim();
+                for zone in &mut self.zones {
+                    if let Some(index) = zone.hosts.iter().position(|host| host.name == name) {
+                        zone.hosts.remove(index);
+                        self.changed = true;
+                        return Ok(format!("Removed host '{name}'"));
+                    }
+                }
+                Err(format!("'{name}' is not monitored"))
+            }
+        }
+    }
+}
+
+/// Reset the host set to the hosts of `zones`, discarding changes which were not applied yet
+pub fn sync(zones: &[ZoneClient], selection: ZoneSelection) {
+    let mut set = HOSTS.lock().unwrap_or_else(|e| e.into_inner());
+    set.selection = selection;
+    set.zones = zones
+        .iter()
+        .map(|zone| ZoneHosts {
+            id: zone.zone.id.clone(),
+            name: zone.zone.name.clone(),
+            hosts: zone.hosts.clone(),
+            desired: zone
+                .desired
+                .iter()
+                .flatten()
+                .map(|record| record.name.clone())
+                .collect(),
+        })
+        .collect();
+    set.changed = false;
+}
+
+/// Give `zones` the hosts added and removed through the control socket since the last call
+pub fn apply(zones: &mut [ZoneClient]) {
+    let mut set = HOSTS.lock().unwrap_or_else(|e| e.into_inner());
+    if !set.changed {
+        return;
+    }
+    for (zone, hosts) in zones.iter_mut().zip(&set.zones) {
+        zone.hosts = hosts.hosts.clone();
+    }
+    set.changed = false;
+    info!("Applied host changes from the control socket");
+}
+
+/// Listen for commands on the Unix socket at `path` from a background thread
+///
+/// A socket left over at `path` by a previous run is replaced.
+pub fn serve(path: &str) -> Result<(), ()> {
+    if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        fs::remove_file(path).map_err(|e| {
+            error!("Could not remove the old control socket '{path}': {e}");
+        })?;
+    }
+    let listener = UnixListener::bind(path).map_err(|e| {
+        error!("Could not listen for commands on control socket '{path}': {e}");
+    })?;
+
+    info!("Listening for commands on control socket '{path}'");
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = handle_connection(stream) {
+                        warn!("Could not answer control socket command: {e}");
+                    }
+                }
+                Err(e) => warn!("Could not accept control socket connection: {e}"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Reply to the `status` command: the last error of every failing record
+fn status() -> serde_json::Value {
+    let last_errors = status::last_errors()
+        .into_iter()
+        .map(|((host, rtype), last_error)| {
+            json!({
+                "host": host,
+                "type": rtype.to_string(),
+                "message": last_error.message,
+                "timestamp": jiff::Timestamp::try_from(last_error.timestamp)
+                    .map(|timestamp| timestamp.to_string())
+                    .unwrap_or_default(),
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({ "ok": true, "last_errors": last_errors })
+}
+
+fn handle_connection(mut stream: UnixStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+
+    for line in BufReader::new(stream.try_clone()?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let result = match serde_json::from_str::<Command>(&line) {
+            // The status only reads, so it is answered without logging
+            Ok(Command::Status) => {
+                writeln!(stream, "{}", status())?;
+                continue;
+            }
+            Ok(command) => HOSTS
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .handle(command),
+            Err(e) => Err(format!("Invalid command: {e}")),
+        };
+
+        let reply = match result {
+            Ok(message) => {
+                info!("{message} through the control socket, applied from the next cycle");
+                json!({ "ok": true })
+            }
+            Err(e) => {
+                warn!("Rejected control socket command: {e}");
+                json!({ "ok": false, "error": e })
+            }
+        };
+        writeln!(stream, "{reply}")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zone(id: &str, name: &str, hosts: &[&str], desired: &[&str]) -> ZoneHosts {
+        ZoneHosts {
+            id: id.to_string(),
+            name: Some(name.to_string()),
+            hosts: hosts
+                .iter()
+                .map(|name| HostConfig {
+                    name: name.to_string(),
+                    proxied: None,
+                    webhook: None,
+                    enabled: true,
+                    manage: Manage::Full,
+                })
+                .collect(),
+            desired: desired.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    fn host_set() -> HostSet {
+        HostSet {
+            zones: vec![
+                zone(
+                    "1",
+                    "example.com",
+                    &["www.example.com"],
+                    &["api.example.com"],
+                ),
+                zone("2", "example.org", &[], &[]),
+            ],
+            ..HostSet::new()
+        }
+    }
+
+    fn command(line: &str) -> Command {
+        serde_json::from_str(line).unwrap()
+    }
+
+    fn names(set: &HostSet, zone: usize) -> Vec<&str> {
+        set.zones[zone]
+            .hosts
+            .iter()
+            .map(|h| h.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn adds_a_host_to_the_zone_which_contains_it_or_the_given_one() {
+        let mut set = host_set();
+
+        let added = set.handle(command(
+            r#"{"add_host": {"name": " blog.example.org ", "proxied": true}}"#,
+        ));
+        assert_eq!(
+            added,
+            Ok("Added host 'blog.example.org' to zone 'example.org'".to_string())
+        );
+        assert!(set.changed);
+        assert_eq!(names(&set, 1), ["blog.example.org"]);
+        assert_eq!(set.zones[1].hosts[0].proxied, Some(true));
+
+        let added = set.handle(command(
+            r#"{"add_host": {"name": "cdn.example.net", "zone": "1"}}"#,
+        ));
+        assert!(added.is_ok(), "{added:?}");
+        assert_eq!(names(&set, 0), ["www.example.com", "cdn.example.net"]);
+    }
+
+    #[test]
+    fn rejects_an_invalid_host_to_add() {
+        let mut set = host_set();
+        let mut add = |line| set.handle(command(line)).unwrap_err();
+
+        assert_eq!(
+            add(r#"{"add_host": {"name": "www.example.com"}}"#),
+            "'www.example.com' is already monitored"
+        );
+        assert_eq!(
+            add(r#"{"add_host": {"name": "api.example.com"}}"#),
+            "'api.example.com' is a desired record"
+        );
+        assert_eq!(
+            add(r#"{"add_host": {"name": "-bad.example.com"}}"#),
+            "'-bad.example.com' is not a valid host name"
+        );
+        assert_eq!(
+            add(r#"{"add_host": {"name": "www.example.net"}}"#),
+            "No configured zone contains 'www.example.net', set `zone` to choose one"
+        );
+        assert_eq!(
+            add(r#"{"add_host": {"name": "www.example.net", "zone": "example.net"}}"#),
+            "Zone 'example.net' is not configured"
+        );
+        assert!(!set.changed);
+    }
+
+    #[test]
+    fn removes_a_monitored_host_only() {
+        let mut set = host_set();
+
+        assert_eq!(
+            set.handle(command(r#"{"remove_host": "blog.example.com"}"#)),
+            Err("'blog.example.com' is not monitored".to_string())
+        );
+        assert!(!set.changed);
+        assert_eq!(
+            set.handle(command(r#"{"remove_host": "www.example.com"}"#)),
+            Ok("Removed host 'www.example.com'".to_string())
+        );
+        assert!(set.changed);
+        assert!(names(&set, 0).is_empty());
+    }
+
+    #[test]
+    fn rejects_the_malformed_commands() {
+        for line in [
+            r#"{"add_host": {"name": "www.example.com", "ttl": 60}}"#,
+            r#"{"rename_host": "www.example.com"}"#,
+            r#"{"remove_host": 1}"#,
+        ] {
+            assert!(serde_json::from_str::<Command>(line).is_err(), "{line}");
+        }
+        assert!(matches!(command(r#""status""#), Command::Status));
+    }
+}
@@ -0,0 +1,747 @@
+//! Desired state of DNS records.
+//!
+//! Instead of (or in addition to) monitoring hosts, every record which should exist can be listed in a TOML file,
+//! whose path is given in `CF_DNS_RECORDS_FILE`:
+//!
+//! ```toml
+//! [[records]]
+//! name = "example.com"
+//! type = "A"
+//! # Either "dynamic", to track the current IP, a fixed IP, or "@ref:<name>" for the same content as another desired
+//! # record of the same type or a monitored host
+//! content = "dynamic"
+//! ttl = 300
+//! proxied = true
+//! # Zone id or name, only needed when several zones are configured and the name is not in a zone configured by name
+//! zone = "example.com"
+//! ```
+//!
+//! A file whose name ends in `.zone` is read as BIND zone file lines instead, see [`crate::bind`].
+//!
+//! Each cycle, the zone's records are made to match the desired state: missing records are created, and records which
+//! differ are updated. Records created or updated this way are marked as managed in their comment, and managed records
+//! which are no longer in the desired state are deleted. Records which are not managed are never deleted.
+
+use crate::bind;
+use crate::comment;
+use crate::config::{ZoneRef, ZoneSelection, check_keys, zone_for_host};
+use crate::{CfRecord, Record, RecordType, Ttl};
+use log::{error, warn};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::fs;
+use std::net::IpAddr;
+
+/// Desired content of a record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Content {
+    /// The current IP of the record's type
+    Dynamic,
+    /// A fixed IP
+    Static(IpAddr),
+}
+
+impl Display for Content {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Content::Dynamic => write!(f, "dynamic"),
+            Content::Static(ip) => write!(f, "{ip}"),
+        }
+    }
+}
+
+/// A record which should exist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DesiredRecord {
+    pub name: String,
+    pub rtype: RecordType,
+    pub content: Content,
+    pub ttl: Ttl,
+    /// Whether the record is proxied. Defaults to the zone's and then the global default.
+    pub proxied: Option<bool>,
+    /// Name of the desired record of the same type which this record refers to, and which is written before it
+    pub depends_on: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecordEntry {
+    name: String,
+    #[serde(rename = "type")]
+    rtype: String,
+    content: String,
+    ttl: Option<u32>,
+    proxied: Option<bool>,
+    zone: Option<String>,
+    /// Desired record referred to by the content, set when references are resolved
+    #[serde(skip)]
+    depends_on: Option<String>,
+}
+
+/// Prefix of a content which refers to the content of another record
+const REF_PREFIX: &str = "@ref:";
+
+/// Keys of a record of the records file
+const RECORD_KEYS: [&str; 6] = ["name", "type", "content", "ttl", "proxied", "zone"];
+
+#[derive(Debug, Deserialize)]
+struct RecordsFile {
+    records: Vec<RecordEntry>,
+}
+
+impl RecordEntry {
+    fn parse(self) -> Result<DesiredRecord, ()> {
+        let name = self.name.trim().to_string();
+
+        let rtype = RecordType::try_from(self.rtype.trim()).map_err(|_| {
+            error!(
+                "Desired record '{name}' has type '{}', only `A` and `AAAA` are supported",
+                self.rtype
+            );
+        })?;
+
+        let content = match self.content.trim() {
+            "dynamic" => Content::Dynamic,
+            content => {
+                let ip = content.parse::<IpAddr>().map_err(|e| {
+                    error!("Desired record '{name}' has invalid content '{content}': {e}");
+                })?;
+                if RecordType::from_ip(&ip) != rtype {
+                    error!(
+                        "Desired '{rtype}' record '{name}' has content '{ip}' of the wrong IP version"
+                    );
+                    return Err(());
+                }
+                Content::Static(ip)
+            }
+        };
+
+        let ttl = match self.ttl {
+            Some(ttl) => Ttl::try_from(ttl).map_err(|_| {
+                error!("Desired record '{name}' has invalid TTL '{ttl}'");
+            })?,
+            None => Ttl::default(),
+        };
+
+        Ok(DesiredRecord {
+            name,
+            rtype,
+            content,
+            ttl,
+            proxied: self.proxied,
+            depends_on: self.depends_on,
+        })
+    }
+}
+
+/// Replace every `@ref:<name>` content of the `entries` by the content of the record it refers to, following chains of
+/// references
+///
+/// A reference is to the desired record of the same type with that name, or else to one of the monitored `hosts`,
+/// whose content is the current IP. The desired record which an entry refers to directly is kept as its dependency.
+fn resolve_references(entries: &mut [RecordEntry], hosts: &[&str]) -> Result<(), ()> {
+    let resolved = entries
+        .iter()
+        .map(|entry| {
+            let name = entry.name.trim();
+            let mut chain = vec![name.to_string()];
+            let mut content = entry.content.trim().to_string();
+            let mut depends_on = None;
+            while let Some(target) = content.strip_prefix(REF_PREFIX).map(|t| t.trim().to_string()) {
+                let is_cycle = chain.iter().any(|n| n.eq_ignore_ascii_case(&target));
+                chain.push(target.clone());
+                if is_cycle {
+                    error!(
+                        "Desired record '{name}' has a cycle of references: {}",
+                        chain.join(" -> ")
+                    );
+                    return Err(());
+                }
+
+                content = match entries.iter().find(|e| {
+                    e.name.trim().eq_ignore_ascii_case(&target) && e.rtype.trim() == entry.rtype.trim()
+                }) {
+                    Some(target) => {
+                        depends_on.get_or_insert_with(|| target.name.trim().to_string());
+                        target.content.trim().to_string()
+                    }
+                    None if hosts.iter().any(|h| h.eq_ignore_ascii_case(&target)) => {
+                        "dynamic".to_string()
+                    }
+                    None => {
+                        error!(
+                            "Desired record '{name}' refers to '{target}', which is neither a desired '{}' record nor a \
+                            monitored host",
+                            entry.rtype.trim()
+                        );
+                        return Err(());
+                    }
+                };
+            }
+            Ok((content, depends_on))
+        })
+        .collect::<Result<Vec<_>, ()>>()?;
+
+    for (entry, (content, depends_on)) in entries.iter_mut().zip(resolved) {
+        entry.content = content;
+        entry.depends_on = depends_on;
+    }
+    Ok(())
+}
+
+/// Parse the TOML records file `text`, read from `path`. Unknown keys are an error if `strict`.
+fn read_toml_records(text: &str, path: &str, strict: bool) -> Result<RecordsFile, ()> {
+    let table: toml::Table = toml::from_str(text).map_err(|e| {
+        error!("Could not parse records file '{path}': {e}");
+    })?;
+    let mut valid = check_keys(
+        &table,
+        &["records"],
+        &format!("records file '{path}'"),
+        strict,
+    )
+    .is_ok();
+    let entries = table.get("records").and_then(|records| records.as_array());
+    for (index, entry) in entries.into_iter().flatten().enumerate() {
+        if let Some(entry) = entry.as_table() {
+            let location = format!("record <{}> of records file '{path}'", index + 1);
+            valid &= check_keys(entry, &RECORD_KEYS, &location, strict).is_ok();
+        }
+    }
+    if !valid {
+        return Err(());
+    }
+    table.try_into().map_err(|e| {
+        error!("Could not parse records file '{path}': {e}");
+    })
+}
+
+/// Read the desired records from the file at `path`, and assign each of them to one of the given `zones`
+///
+/// Returns the desired records of each zone, in the same order as `zones`.
+///
+/// A record without a `zone` is assigned to the zone configured by name which contains it, chosen with `selection`.
+/// References to other records are resolved against the records of the file, and the monitored `hosts`. Unknown keys
+/// are an error if `strict`.
+pub fn read_records_file(
+    path: &str,
+    zones: &[ZoneRef],
+    selection: ZoneSelection,
+    hosts: &[&str],
+    strict: bool,
+) -> Result<Vec<Vec<DesiredRecord>>, ()> {
+    let text = fs::read_to_string(path).map_err(|e| {
+        error!("Could not read records file '{path}': {e}");
+    })?;
+    let mut file = if path.ends_with(".zone") {
+        let records = bind::parse(&text, path)?
+            .into_iter()
+            .map(|record| RecordEntry {
+                name: record.name,
+                rtype: record.rtype,
+                content: record.content,
+                ttl: record.ttl,
+                proxied: record.proxied,
+                zone: None,
+                depends_on: None,
+            })
+            .collect();
+        RecordsFile { records }
+    } else {
+        read_toml_records(&text, path, strict)?
+    };
+    resolve_references(&mut file.records, hosts)?;
+
+    let zone_names = zones
+        .iter()
+        .map(|zone| match zone {
+            ZoneRef::Name(name) => Some(name.as_str()),
+            ZoneRef::Id(_) => None,
+        })
+        .collect::<Vec<_>>();
+    let mut records = vec![Vec::new(); zones.len()];
+    let mut seen = BTreeSet::new();
+
+    for entry in file.records {
+        let index = match (&entry.zone, zones) {
+            (Some(zone), _) => zones
+                .iter()
+                .position(|z| z.to_string() == zone.trim())
+                .ok_or_else(|| {
+                    error!(
+                        "Desired record '{}' is in zone '{zone}', which is not configured",
+                        entry.name
+                    );
+                })?,
+            (None, [_]) => 0,
+            (None, _) => zone_for_host(&entry.name, &zone_names, selection).ok_or_else(|| {
+                error!(
+                    "Desired record '{}' must have a `zone`, since several zones are configured and none of those \
+                    configured by name contains it",
+                    entry.name
+                );
+            })?,
+        };
+
+        let record = entry.parse()?;
+        if !seen.insert((index, record.name.clone(), record.rtype)) {
+            error!(
+                "Desired '{}' record '{}' is listed more than once",
+                record.rtype, record.name
+            );
+            return Err(());
+        }
+        records[index].push(record);
+    }
+
+    Ok(records)
+}
+
+/// Operation which makes a zone's records match the desired state
+#[derive(Debug, Clone)]
+pub enum Op {
+    /// The record already matches the desired state
+    Keep(CfRecord),
+    Create(Record),
+    Update {
+        current: CfRecord,
+        desired: Record,
+    },
+    /// The record is managed, but no longer desired
+    Delete(CfRecord),
+}
+
+impl Op {
+    /// Name and type of the record the operation applies to
+    pub fn target(&self) -> (&str, RecordType) {
+        match self {
+            Op::Keep(r) | Op::Update { current: r, .. } | Op::Delete(r) => {
+                (&r.record.name, r.record.rtype())
+            }
+            Op::Create(r) => (&r.name, r.rtype()),
+        }
+    }
+}
+
+/// Desired and live state of the desired records of a zone, as hashes which are equal when the zone is in sync
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Drift {
+    pub desired: u64,
+    pub live: u64,
+    /// Number of records which must be created, updated or deleted to be in sync
+    pub differences: usize,
+}
+
+impl Drift {
+    /// Drift of the records which the `ops` apply to
+    pub fn of(ops: &[Op]) -> Self {
+        let desired = ops.iter().filter_map(|op| match op {
+            Op::Keep(current) => Some(&current.record),
+            Op::Create(desired) | Op::Update { desired, .. } => Some(desired),
+            Op::Delete(_) => None,
+        });
+        let live = ops.iter().filter_map(|op| match op {
+            Op::Keep(current) | Op::Update { current, .. } | Op::Delete(current) => {
+                Some(&current.record)
+            }
+            Op::Create(_) => None,
+        });
+        Self {
+            desired: state_hash(desired),
+            live: state_hash(live),
+            differences: ops.iter().filter(|op| !matches!(op, Op::Keep(_))).count(),
+        }
+    }
+
+    pub fn in_sync(&self) -> bool {
+        self.differences == 0
+    }
+}
+
+/// Hash of `records` which is the same whatever their order, and across runs and builds
+///
+/// Names are compared in any case, and the TTL of proxied records is left out since it is not used.
+pub fn state_hash<'a>(records: impl Iterator<Item = &'a Record>) -> u64 {
+    let mut lines = records
+        .map(|record| {
+            let ttl = match record.proxied {
+                true => "-".to_string(),
+                false => u32::from(record.ttl).to_string(),
+            };
+            format!(
+                "{} {} {} {ttl} {}\n",
+                record.name.to_lowercase(),
+                record.rtype(),
+                record.content,
+                record.proxied
+            )
+        })
+        .collect::<Vec<_>>();
+    lines.sort();
+
+    // FNV-1a, which unlike the standard library's hasher is specified to stay the same
+    lines
+        .iter()
+        .flat_map(|line| line.bytes())
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+        })
+}
+
+/// Order the `desired` records so that every record comes after the record it depends on
+///
+/// Records are otherwise kept in their order. A cycle of dependencies, which cannot be ordered, is logged and broken at
+/// the first record of the cycle.
+pub fn dependency_order(desired: &[DesiredRecord]) -> Vec<&DesiredRecord> {
+    fn visit<'a>(
+        index: usize,
+        desired: &'a [DesiredRecord],
+        visiting: &mut Vec<usize>,
+        ordered: &mut Vec<&'a DesiredRecord>,
+        done: &mut BTreeSet<usize>,
+    ) {
+        if done.contains(&index) {
+            return;
+        }
+        let record = &desired[index];
+        if let Some(start) = visiting.iter().position(|&i| i == index) {
+            let cycle = visiting[start..]
+                .iter()
+                .chain([&index])
+                .map(|&i| desired[i].name.as_str())
+                .collect::<Vec<_>>();
+            warn!(
+                "Desired '{}' records have a cycle of dependencies, which is ignored: {}",
+                record.rtype,
+                cycle.join(" -> ")
+            );
+            return;
+        }
+
+        visiting.push(index);
+        let target = record.depends_on.as_deref().and_then(|target| {
+            desired
+                .iter()
+                .position(|r| r.name.eq_ignore_ascii_case(target) && r.rtype == record.rtype)
+        });
+        if let Some(target) = target {
+            visit(target, desired, visiting, ordered, done);
+        }
+        visiting.pop();
+
+        if done.insert(index) {
+            ordered.push(record);
+        }
+    }
+
+    let mut ordered = Vec::with_capacity(desired.len());
+    let mut done = BTreeSet::new();
+    for index in 0..desired.len() {
+        visit(index, desired, &mut Vec::new(), &mut ordered, &mut done);
+    }
+    ordered
+}
+
+/// Compute the operations which make the `current` records of a zone match the `desired` ones
+///
+/// The operations on the desired records are in [`dependency_order`], followed by the deletions. Dynamic records for
+/// which there is no current IP are left as they are. Names are matched in any case, and a record whose name differs
+/// only in case is renamed if `normalize_case`.
+pub fn diff(
+    desired: &[DesiredRecord],
+    current: &[CfRecord],
+    cur_ips: &BTreeMap<RecordType, IpAddr>,
+    proxied_default: bool,
+    normalize_case: bool,
+) -> Vec<Op> {
+    let mut ops = Vec::new();
+    // Ids of the records which match a desired record
+    let mut matched = BTreeSet::new();
+
+    for want in dependency_order(desired) {
+        let existing = current.iter().find(|r| {
+            r.record.name.eq_ignore_ascii_case(&want.name) && r.record.rtype() == want.rtype
+        });
+        if let Some(existing) = existing {
+            matched.insert(existing.id.as_str());
+        }
+
+        let content = match want.content {
+            Content::Static(ip) => ip,
+            Content::Dynamic => match cur_ips.get(&want.rtype) {
+                Some(ip) => *ip,
+                None => continue,
+            },
+        };
+        let name = match existing {
+            Some(existing) if !normalize_case => existing.record.name.clone(),
+            _ => want.name.clone(),
+        };
+        let record = Record {
+            name,
+            ttl: want.ttl,
+            content,
+            proxied: want.proxied.unwrap_or(proxied_default),
+        };
+
+        ops.push(match existing {
+            Some(existing)
+                if existing.record.matches(&record) && existing.record.name == record.name =>
+            {
+                Op::Keep(existing.clone())
+            }
+            Some(existing) => Op::Update {
+                current: existing.clone(),
+                desired: record,
+            },
+            None => Op::Create(record),
+        });
+    }
+
+    for record in current {
+        if !matched.contains(record.id.as_str())
+            && !record.locked
+            && comment::is_managed(record.comment.as_deref())
+        {
+            ops.push(Op::Delete(record.clone()));
+        }
+    }
+
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::fake_record;
+
+    const CUR_IP: &str = "203.0.113.7";
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn desired(name: &str, content: Content) -> DesiredRecord {
+        DesiredRecord {
+            name: name.to_string(),
+            rtype: RecordType::A,
+            content,
+            ttl: Ttl::Auto,
+            proxied: None,
+            depends_on: None,
+        }
+    }
+
+    fn diff(desired: &[DesiredRecord], current: &[CfRecord]) -> Vec<Op> {
+        let cur_ips = BTreeMap::from([(RecordType::A, ip(CUR_IP))]);
+        super::diff(desired, current, &cur_ips, false, false)
+    }
+
+    fn entry(name: &str, content: &str) -> RecordEntry {
+        RecordEntry {
+            name: name.to_string(),
+            rtype: "A".to_string(),
+            content: content.to_string(),
+            ttl: None,
+            proxied: None,
+            zone: None,
+            depends_on: None,
+        }
+    }
+
+    #[test]
+    fn creates_a_missing_record() {
+        let ops = diff(&[desired("www.example.com", Content::Dynamic)], &[]);
+        let [Op::Create(record)] = &ops[..] else {
+            panic!("unexpected operations {ops:?}");
+        };
+        assert_eq!(record.name, "www.example.com");
+        assert_eq!(record.content, ip(CUR_IP));
+    }
+
+    #[test]
+    fn updates_a_record_which_differs() {
+        let current = [fake_record(
+            "1",
+            "www.example.com",
+            ip("198.51.100.1"),
+            None,
+        )];
+        let ops = diff(&[desired("www.example.com", Content::Dynamic)], &current);
+        let [Op::Update { current, desired }] = &ops[..] else {
+            panic!("unexpected operations {ops:?}");
+        };
+        assert_eq!(current.id, "1");
+        assert_eq!(desired.content, ip(CUR_IP));
+    }
+
+    #[test]
+    fn deletes_only_the_managed_records_which_are_no_longer_desired() {
+        let current = [
+            fake_record("1", "old.example.com", ip(CUR_IP), Some("[cf-dns-rs]")),
+            fake_record("2", "manual.example.com", ip(CUR_IP), Some("by hand")),
+            fake_record("3", "bare.example.com", ip(CUR_IP), None),
+        ];
+        let ops = diff(&[], &current);
+        let [Op::Delete(deleted)] = &ops[..] else {
+            panic!("unexpected operations {ops:?}");
+        };
+        assert_eq!(deleted.id, "1");
+    }
+
+    #[test]
+    fn keeps_a_record_which_is_in_sync() {
+        let static_ip = ip("192.0.2.1");
+        let current = [
+            fake_record("1", "www.example.com", ip(CUR_IP), Some("[cf-dns-rs]")),
+            fake_record("2", "static.example.com", static_ip, Some("[cf-dns-rs]")),
+        ];
+        let ops = diff(
+            &[
+                desired("WWW.example.com", Content::Dynamic),
+                desired("static.example.com", Content::Static(static_ip)),
+            ],
+            &current,
+        );
+        assert_eq!(ops.len(), 2);
+        assert!(ops.iter().all(|op| matches!(op, Op::Keep(_))));
+        assert!(Drift::of(&ops).in_sync());
+    }
+
+    #[test]
+    fn leaves_a_dynamic_record_alone_without_a_current_ip() {
+        let current = [fake_record("1", "www.example.com", ip(CUR_IP), None)];
+        let desired = [DesiredRecord {
+            rtype: RecordType::AAAA,
+            ..desired("www.example.com", Content::Dynamic)
+        }];
+        assert!(diff(&desired, &current).is_empty());
+    }
+
+    #[test]
+    fn resolves_chains_of_references() {
+        let mut entries = [
+            entry("a.example.com", "@ref:b.example.com"),
+            entry("b.example.com", "@ref:www.example.com"),
+            entry("c.example.com", "192.0.2.1"),
+            entry("d.example.com", "@ref:c.example.com"),
+        ];
+        resolve_references(&mut entries, &["www.example.com"]).unwrap();
+        let contents = entries
+            .iter()
+            .map(|e| e.content.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(contents, ["dynamic", "dynamic", "192.0.2.1", "192.0.2.1"]);
+    }
+
+    #[test]
+    fn rejects_a_cycle_of_references() {
+        let mut entries = [
+            entry("a.example.com", "@ref:b.example.com"),
+            entry("b.example.com", "@ref:c.example.com"),
+            entry("c.example.com", "@ref:A.example.com"),
+        ];
+        assert!(resolve_references(&mut entries, &[]).is_err());
+
+        let mut entries = [entry("a.example.com", "@ref:a.example.com")];
+        assert!(resolve_references(&mut entries, &[]).is_err());
+    }
+
+    #[test]
+    fn keeps_the_record_referred_to_as_a_dependency() {
+        let mut entries = [
+            entry("a.example.com", "@ref:B.example.com"),
+            entry("b.example.com", "@ref:www.example.com"),
+        ];
+        resolve_references(&mut entries, &["www.example.com"]).unwrap();
+        assert_eq!(entries[0].depends_on.as_deref(), Some("b.example.com"));
+        assert_eq!(entries[1].depends_on, None);
+    }
+
+    #[test]
+    fn writes_the_record_referred_to_first() {
+        let static_ip = ip("192.0.2.1");
+        let alias = DesiredRecord {
+            depends_on: Some("target.example.com".to_string()),
+            ..desired("alias.example.com", Content::Static(static_ip))
+        };
+        let target = desired("target.example.com", Content::Static(static_ip));
+        let ops = diff(&[alias, target], &[]);
+        let names = ops.iter().map(|op| op.target().0).collect::<Vec<_>>();
+        assert_eq!(names, ["target.example.com", "alias.example.com"]);
+    }
+
+    #[test]
+    fn keeps_the_order_of_a_cycle_of_dependencies() {
+        let depending = |name: &str, on: &str| DesiredRecord {
+            depends_on: Some(on.to_string()),
+            ..desired(name, Content::Dynamic)
+        };
+        let records = [
+            depending("a.example.com", "b.example.com"),
+            depending("b.example.com", "a.example.com"),
+            desired("c.example.com", Content::Dynamic),
+        ];
+        let names = dependency_order(&records)
+            .iter()
+            .map(|record| record.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["b.example.com", "a.example.com", "c.example.com"]);
+    }
+
+    #[test]
+    fn rejects_a_reference_to_an_unknown_record() {
+        let mut entries = [entry("a.example.com", "@ref:missing.example.com")];
+        assert!(resolve_references(&mut entries, &["www.example.com"]).is_err());
+    }
+
+    fn record(name: &str, content: &str, ttl: Ttl, proxied: bool) -> Record {
+        Record {
+            name: name.to_string(),
+            ttl,
+            content: ip(content),
+            proxied,
+        }
+    }
+
+    #[test]
+    fn hashes_equivalent_states_equally() {
+        let state = [
+            record("www.example.com", CUR_IP, Ttl::Seconds(300), false),
+            record("api.example.com", CUR_IP, Ttl::Seconds(60), true),
+        ];
+        let equivalent = [
+            record("API.example.com", CUR_IP, Ttl::Auto, true),
+            record("www.example.com", CUR_IP, Ttl::Seconds(300), false),
+        ];
+
+        assert_eq!(state_hash(state.iter()), state_hash(equivalent.iter()));
+    }
+
+    #[test]
+    fn changes_the_live_hash_with_a_single_field() {
+        let desired = [desired("www.example.com", Content::Dynamic)];
+        let in_sync = diff(
+            &desired,
+            &[fake_record("1", "www.example.com", ip(CUR_IP), None)],
+        );
+        let drifted = diff(
+            &desired,
+            &[fake_record(
+                "1",
+                "www.example.com",
+                ip("198.51.100.1"),
+                None,
+            )],
+        );
+
+        let (in_sync, drifted) = (Drift::of(&in_sync), Drift::of(&drifted));
+
+        assert!(in_sync.in_sync());
+        assert_eq!(in_sync.desired, in_sync.live);
+        assert!(!drifted.in_sync());
+        assert_eq!(drifted.differences, 1);
+        assert_eq!(drifted.desired, in_sync.desired);
+        assert_ne!(drifted.live, in_sync.live);
+    }
+}
@@ -0,0 +1,351 @@
+//! `diagnose` command: write a bundle with everything needed to reproduce a bug report.
+//!
+//! The bundle holds the versions, the effective config, the relevant environment variables, the monitored hosts, the
+//! detected IPs and a sample of the DNS records of every zone, as returned by Cloudflare. Secrets are redacted before
+//! anything is written: the API token (or the global API key and its email), zone ids and names, webhook URLs, the IP
+//! detection proxy, record ids, contents and comments, and the host part of the detected IPs. The applied redactions
+//! are listed in the bundle.
+
+use crate::clock::{Deadline, SystemClock};
+use crate::cloudflare::{Auth, CfApi, cf_get_records_raw, cf_resolve_zone_id};
+use crate::config::{Config, ZoneRef};
+use crate::{detect_ips, ip_detect_client};
+use log::{error, info};
+use serde_json::Value;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::net::IpAddr;
+
+/// Number of records fetched from each zone as a sample response
+const SAMPLE_RECORDS: u32 = 5;
+
+/// Environment variables read by the app which don't have the `CF_DNS_` prefix
+const UNPREFIXED_VARS: [&str; 3] = ["IPV4_ENDPOINT", "IPV6_ENDPOINT", "REPEAT_INTERVAL_SECONDS"];
+
+/// Parts of environment variable names whose values are always fully redacted
+const SECRET_VAR_PARTS: [&str; 5] = ["TOKEN", "KEY", "SECRET", "PASSWORD", "EMAIL"];
+
+/// Fields of the sample records whose values are redacted
+const REDACTED_RECORD_FIELDS: [&str; 4] = ["id", "zone_id", "content", "comment"];
+
+/// Secret values, each replaced by a placeholder wherever it appears in the bundle
+#[derive(Debug, Default)]
+struct Redactor {
+    secrets: Vec<(String, String)>,
+    /// Description of every redaction which was applied
+    applied: Vec<String>,
+}
+
+impl Redactor {
+    fn secret(&mut self, value: &str, placeholder: String) {
+        if !value.is_empty() && !self.secrets.iter().any(|(secret, _)| secret == value) {
+            self.secrets.push((value.to_string(), placeholder));
+        }
+    }
+
+    fn note(&mut self, redaction: String) {
+        if !self.applied.contains(&redaction) {
+            self.applied.push(redaction);
+        }
+    }
+
+    /// Replace every secret in `text` by its placeholder
+    fn redact(&mut self, text: &str) -> String {
+        // Longest secrets first, in case one contains another
+        let mut secrets = self.secrets.clone();
+        secrets.sort_by_key(|(secret, _)| std::cmp::Reverse(secret.len()));
+
+        let mut text = text.to_string();
+        for (secret, placeholder) in secrets {
+            if text.contains(&secret) {
+                text = text.replace(&secret, &placeholder);
+                self.note(format!("{placeholder} replaced every occurrence"));
+            }
+        }
+        text
+    }
+}
+
+/// Keep only the network part of `ip`
+fn mask_ip(ip: &IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            format!("{a}.{b}.x.x")
+        }
+        IpAddr::V6(ip) => {
+            let [a, b, ..] = ip.segments();
+            format!("{a:x}:{b:x}:x:x:x:x:x:x")
+        }
+    }
+}
+
+/// Redact the record ids, contents and comments of a records list response
+fn redact_records(json: &mut Value, redactor: &mut Redactor) {
+    let Some(records) = json["result"].as_array_mut() else {
+        return;
+    };
+    for record in records {
+        for field in REDACTED_RECORD_FIELDS {
+            if let Some(value) = record.get_mut(field)
+                && !value.is_null()
+            {
+                *value = Value::String(format!("<record {field}>"));
+                redactor.note(format!(
+                    "<record {field}> replaced the {field} of the sample records"
+                ));
+            }
+        }
+    }
+}
+
+/// Build the bundle for `config`
+fn bundle(config: &Config) -> String {
+    let mut redactor = Redactor::default();
+    redactor.secret(config.auth.secret(), "<credentials>".to_string());
+    if let Auth::GlobalKey { email, .. } = &config.auth {
+        redactor.secret(email, "<auth email>".to_string());
+    }
+    for (index, zone) in config.zones.iter().enumerate() {
+        if let Some(auth) = &zone.auth {
+            redactor.secret(auth.secret(), "<credentials>".to_string());
+        }
+        match &zone.zone_ref {
+            ZoneRef::Id(id) => redactor.secret(id, format!("<zone {} id>", index + 1)),
+            ZoneRef::Name(name) => redactor.secret(name, format!("<zone {} name>", index + 1)),
+        }
+        for host in &zone.hosts {
+            if let Some(webhook) = &host.webhook {
+                redactor.secret(webhook, "<webhook url>".to_string());
+            }
+        }
+    }
+    if let Some(url) = &config.webhook_url {
+        redactor.secret(url, "<webhook url>".to_string());
+    }
+    for (name, value) in &config.extra_headers {
+        redactor.secret(value, format!("<{name} header value>"));
+    }
+    #[cfg(feature = "event-sink")]
+    if let Some(sink) = &config.event_sink {
+        redactor.secret(sink.url(), "<event sink url>".to_string());
+    }
+    if let Some(proxy) = &config.ip_detect_proxy {
+        redactor.secret(proxy, "<ip detect proxy>".to_string());
+    }
+
+    let mut out = String::new();
+
+    let _ = writeln!(out, "== versions ==");
+    let _ = writeln!(
+        out,
+        "{} {}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    );
+    let _ = writeln!(out, "os: {} {}", env::consts::OS, env::consts::ARCH);
+    let _ = writeln!(out, "keyring feature: {}", cfg!(feature = "keyring"));
+    let _ = writeln!(out, "event-sink feature: {}", cfg!(feature = "event-sink"));
+
+    let _ = writeln!(out, "\n== config ==");
+    let _ = writeln!(out, "{config:#?}");
+
+    let _ = writeln!(out, "\n== environment ==");
+    let mut vars = env::vars()
+        .filter(|(name, _)| name.starts_with("CF_DNS_") || UNPREFIXED_VARS.contains(&name.as_str()))
+        .collect::<Vec<_>>();
+    vars.sort();
+    for (name, value) in vars {
+        if SECRET_VAR_PARTS.iter().any(|part| name.contains(part)) {
+            redactor.note(format!("<redacted> replaced the value of `{name}`"));
+            let _ = writeln!(out, "{name}=<redacted>");
+        } else {
+            let _ = writeln!(out, "{name}={value}");
+        }
+    }
+
+    let _ = writeln!(out, "\n== hosts ==");
+    for (index, zone) in config.zones.iter().enumerate() {
+        let _ = writeln!(out, "zone {} ({}):", index + 1, zone.zone_ref);
+        for host in &zone.hosts {
+            let _ = writeln!(out, "\t{} (proxied: {:?})", host.name, host.proxied);
+        }
+    }
+
+    let _ = writeln!(out, "\n== detected IPs ==");
+    match ip_detect_client(config) {
+        Ok(client) => {
+            let ips = detect_ips(
+                config,
+                &client,
+                &SystemClock,
+                Deadline::after(&SystemClock, None),
+            );
+            for rtype in config.endpoints.keys() {
+                match ips.get(rtype) {
+                    Some(ip) => {
+                        redactor.note("x replaced the host part of the detected IPs".to_string());
+                        let _ = writeln!(out, "{rtype}: {}", mask_ip(ip));
+                    }
+                    None => {
+                        let _ = writeln!(out, "{rtype}: failed, see the log");
+                    }
+                }
+            }
+        }
+        Err(_) => {
+            let _ = writeln!(out, "failed, see the log");
+        }
+    }
+
+    let _ = writeln!(out, "\n== sample records ==");
+    for (index, zone) in config.zones.iter().enumerate() {
+        let _ = writeln!(out, "zone {}:", index + 1);
+        let api = CfApi::with_config(zone.auth(config), config);
+        let zone_id = match &zone.zone_ref {
+            ZoneRef::Id(id) => Ok(id.clone()),
+            ZoneRef::Name(name) => cf_resolve_zone_id(name, &api, &SystemClock),
+        };
+        let sample = zone_id.and_then(|zone_id| {
+            redactor.secret(&zone_id, format!("<zone {} id>", index + 1));
+            cf_get_records_raw(&zone_id, SAMPLE_RECORDS, &api, &SystemClock)
+        });
+        match sample {
+            Ok(mut json) => {
+                if let Some(name) = json["result"]
+                    .as_array()
+                    .and_then(|records| records.first())
+                    .and_then(|record| record["zone_name"].as_str())
+                {
+                    redactor.secret(name, format!("<zone {} name>", index + 1));
+                }
+                redact_records(&mut json, &mut redactor);
+                let _ = writeln!(
+                    out,
+                    "{}",
+                    serde_json::to_string_pretty(&json).unwrap_or_default()
+                );
+            }
+            Err(_) => {
+                let _ = writeln!(out, "failed, see the log");
+            }
+        }
+    }
+
+    let out = redactor.redact(&out);
+
+    let mut header = String::from("== redactions ==\n");
+    for redaction in &redactor.applied {
+        let _ = writeln!(header, "{redaction}");
+    }
+    format!("{header}\n{out}")
+}
+
+/// Write the diagnostic bundle to the file at `path`, or to stdout if there is none
+pub fn run(config: &Config, path: Option<&str>) -> Result<(), ()> {
+    let bundle = bundle(config);
+    match path {
+        Some(path) => {
+            fs::write(path, bundle).map_err(|e| {
+                error!("Could not write the diagnostic bundle to '{path}': {e}");
+            })?;
+            info!("Wrote the diagnostic bundle to '{path}'");
+        }
+        None => print!("{bundle}"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging;
+    use crate::{IpSource, RecordType};
+    use serde_json::json;
+
+    #[test]
+    fn writes_every_section_without_the_secrets() {
+        logging::init_for_tests();
+        let mut config = Config::for_tests(&[RecordType::A]);
+        config.auth = Auth::GlobalKey {
+            email: "me@example.com".to_string(),
+            key: "s3cr3t-key".to_string(),
+        };
+        config.webhook_url = Some("https://hooks.example/s3cr3t-hook".to_string());
+        config.extra_headers = vec![("X-Audit".to_string(), "s3cr3t-header".to_string())];
+        // Nothing listens on the discard port, so that IP detection fails right away
+        config.endpoints = [(
+            RecordType::A,
+            IpSource::Http("http://127.0.0.1:9/".to_string()),
+        )]
+        .into();
+
+        let (bundle, _) = logging::capture_errors(|| bundle(&config));
+
+        let sections = bundle
+            .lines()
+            .filter(|line| line.starts_with("== "))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sections,
+            [
+                "== redactions ==",
+                "== versions ==",
+                "== config ==",
+                "== environment ==",
+                "== hosts ==",
+                "== detected IPs ==",
+                "== sample records ==",
+            ]
+        );
+        assert!(!bundle.contains("s3cr3t"), "{bundle}");
+        assert!(!bundle.contains("me@example.com"), "{bundle}");
+        for redaction in [
+            "<auth email> replaced every occurrence",
+            "<webhook url> replaced every occurrence",
+            "<X-Audit header value> replaced every occurrence",
+        ] {
+            assert!(
+                bundle.lines().any(|line| line == redaction),
+                "{redaction} not in:\n{bundle}"
+            );
+        }
+        assert!(bundle.contains("A: failed, see the log"), "{bundle}");
+    }
+
+    #[test]
+    fn redacts_the_ids_contents_and_comments_of_the_sample_records() {
+        let mut redactor = Redactor::default();
+        let mut json = json!({ "result": [{
+            "id": "record-id",
+            "zone_id": "zone-id",
+            "name": "www.example.com",
+            "content": "203.0.113.7",
+            "comment": null,
+        }] });
+
+        redact_records(&mut json, &mut redactor);
+
+        assert_eq!(
+            json["result"][0],
+            json!({
+                "id": "<record id>",
+                "zone_id": "<record zone_id>",
+                "name": "www.example.com",
+                "content": "<record content>",
+                "comment": null,
+            })
+        );
+        assert_eq!(redactor.applied.len(), 3);
+    }
+
+    #[test]
+    fn masks_the_host_part_of_the_ips() {
+        assert_eq!(mask_ip(&"203.0.113.7".parse().unwrap()), "203.0.x.x");
+        assert_eq!(
+            mask_ip(&"2001:db8::7".parse().unwrap()),
+            "2001:db8:x:x:x:x:x:x"
+        );
+    }
+}
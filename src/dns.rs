@@ -0,0 +1,246 @@
+//! IP detection by querying a DNS resolver.
+//!
+//! Some resolvers answer a special name with the address the query came from, e.g. `myip.opendns.com` on
+//! `resolver1.opendns.com`. Such a source is configured as `dns:<name>@<server>`, where the server is a host name or an
+//! IP, optionally followed by a port (`53` by default). The query is sent over UDP to an address of the server of the
+//! same IP version as the requested record type, so that the answer is the IP of that version.
+//!
+//! The same queries check that changed records are visible from public resolvers, see [`crate::propagation`].
+
+use crate::RecordType;
+use log::error;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+/// Time to wait for the answer of the resolver
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Get the IP of the given type which the DNS `server` answers for `name`
+pub fn get_dns_ip(rtype: &RecordType, name: &str, server: &str) -> Result<IpAddr, ()> {
+    query(rtype, name, server).map_err(|e| error!("{e}"))
+}
+
+/// Query the DNS `server` for the IP of the given type of `name`, returning the reason of a failure
+pub fn query(rtype: &RecordType, name: &str, server: &str) -> Result<IpAddr, String> {
+    let addr = server_addr(rtype, server)?;
+    let local: SocketAddr = match addr {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+
+    let query_id = uuid::Uuid::new_v4().as_u128() as u16;
+    let query = build_query(query_id, name, rtype)?;
+
+    let socket = UdpSocket::bind(local)
+        .and_then(|socket| {
+            socket.set_read_timeout(Some(TIMEOUT))?;
+            socket.connect(addr)?;
+            Ok(socket)
+        })
+        .map_err(|e| format!("Could not open a socket to DNS server '{server}' ({addr}): {e}"))?;
+    socket
+        .send(&query)
+        .map_err(|e| format!("Could not query DNS server '{server}' ({addr}) for '{name}': {e}"))?;
+
+    let mut buf = [0; 1232];
+    let len = socket
+        .recv(&mut buf)
+        .map_err(|e| format!("No answer from DNS server '{server}' ({addr}) for '{name}': {e}"))?;
+
+    parse_answer(&buf[..len], query_id, rtype).map_err(|e| {
+        format!("Invalid answer from DNS server '{server}' ({addr}) for '{name}': {e}")
+    })
+}
+
+/// Resolve `server` to an address of the IP version of `rtype`
+fn server_addr(rtype: &RecordType, server: &str) -> Result<SocketAddr, String> {
+    let has_port = server
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.ends_with(':') && port.parse::<u16>().is_ok());
+    let addrs = match server.parse::<IpAddr>() {
+        Ok(ip) => Ok(vec![SocketAddr::new(ip, 53)]),
+        Err(_) if has_port => server.to_socket_addrs().map(|addrs| addrs.collect()),
+        Err(_) => (server, 53).to_socket_addrs().map(|addrs| addrs.collect()),
+    }
+    .map_err(|e| format!("Could not resolve DNS server '{server}': {e}"))?;
+
+    addrs
+        .into_iter()
+        .find(|addr| RecordType::from_ip(&addr.ip()) == *rtype)
+        .ok_or_else(|| {
+            format!("DNS server '{server}' has no address to send a '{rtype}' query from")
+        })
+}
+
+fn qtype(rtype: &RecordType) -> u16 {
+    match rtype {
+        RecordType::A => 1,
+        RecordType::AAAA => 28,
+    }
+}
+
+/// Build a recursive query for the record of type `rtype` of `name`
+fn build_query(id: u16, name: &str, rtype: &RecordType) -> Result<Vec<u8>, String> {
+    // Header: id, flags (recursion desired), 1 question, no answer, authority or additional records
+    let mut query = Vec::with_capacity(512);
+    query.extend_from_slice(&id.to_be_bytes());
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("'{name}' is not a valid DNS name"));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+
+    query.extend_from_slice(&qtype(rtype).to_be_bytes());
+    // Class IN
+    query.extend_from_slice(&[0, 1]);
+    Ok(query)
+}
+
+/// Skip the (possibly compressed) name starting at `pos`, and return the position right after it
+fn skip_name(msg: &[u8], mut pos: usize) -> Result<usize, String> {
+    loop {
+        let len = *msg.get(pos).ok_or("truncated name")? as usize;
+        match len {
+            0 => return Ok(pos + 1),
+            // Compression pointer, which always ends the name
+            len if len & 0xc0 == 0xc0 => return Ok(pos + 2),
+            len => pos += 1 + len,
+        }
+    }
+}
+
+fn read_u16(msg: &[u8], pos: usize) -> Result<u16, String> {
+    msg.get(pos..pos + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(|| "truncated message".to_string())
+}
+
+/// Get the address of the first answer of type `rtype` in the response `msg` to the query with id `query_id`
+fn parse_answer(msg: &[u8], query_id: u16, rtype: &RecordType) -> Result<IpAddr, String> {
+    if read_u16(msg, 0)? != query_id {
+        return Err("answer to another query".to_string());
+    }
+    let flags = read_u16(msg, 2)?;
+    if flags & 0x8000 == 0 {
+        return Err("not a response".to_string());
+    }
+    match flags & 0x000f {
+        0 => {}
+        3 => return Err("the name does not exist".to_string()),
+        rcode => return Err(format!("error code {rcode}")),
+    }
+
+    let questions = read_u16(msg, 4)?;
+    let answers = read_u16(msg, 6)?;
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        // Name, type and class
+        pos = skip_name(msg, pos)? + 4;
+    }
+
+    for _ in 0..answers {
+        pos = skip_name(msg, pos)?;
+        let answer_type = read_u16(msg, pos)?;
+        let len = read_u16(msg, pos + 8)? as usize;
+        pos += 10;
+        let data = msg.get(pos..pos + len).ok_or("truncated answer")?;
+        pos += len;
+
+        if answer_type != qtype(rtype) {
+            continue;
+        }
+        return match (rtype, <[u8; 4]>::try_from(data), <[u8; 16]>::try_from(data)) {
+            (RecordType::A, Ok(octets), _) => Ok(IpAddr::V4(Ipv4Addr::from(octets))),
+            (RecordType::AAAA, _, Ok(octets)) => Ok(IpAddr::V6(Ipv6Addr::from(octets))),
+            _ => Err(format!("'{rtype}' answer of invalid length {len}")),
+        };
+    }
+
+    Err(format!("no '{rtype}' answer"))
+}
+
+/// DNS server on localhost which answers every query with `ip`, returning its address as `<ip>:<port>`
+#[cfg(test)]
+pub fn stub_resolver(ip: IpAddr) -> String {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap().to_string();
+    std::thread::spawn(move || {
+        let mut buf = [0; 512];
+        while let Ok((len, peer)) = socket.recv_from(&mut buf) {
+            // The query with the response flags and a single answer, pointing to the name of the question
+            let mut response = buf[..len].to_vec();
+            response[2..4].copy_from_slice(&[0x81, 0x80]);
+            response[6..8].copy_from_slice(&1u16.to_be_bytes());
+            let (rtype, data) = match ip {
+                IpAddr::V4(ip) => (RecordType::A, ip.octets().to_vec()),
+                IpAddr::V6(ip) => (RecordType::AAAA, ip.octets().to_vec()),
+            };
+            response.extend_from_slice(&[0xc0, 12]);
+            response.extend_from_slice(&qtype(&rtype).to_be_bytes());
+            response.extend_from_slice(&[0, 1, 0, 0, 0, 0]);
+            response.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            response.extend_from_slice(&data);
+            let _ = socket.send_to(&response, peer);
+        }
+    });
+    addr
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gets_the_ip_answered_by_the_resolver() {
+        let server = stub_resolver("203.0.113.7".parse().unwrap());
+
+        assert_eq!(
+            query(&RecordType::A, "myip.opendns.com", &server),
+            Ok("203.0.113.7".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn rejects_an_answer_of_another_type() {
+        let server = stub_resolver("2001:db8::7".parse().unwrap());
+
+        let answer = query(&RecordType::A, "myip.opendns.com", &server);
+
+        assert_eq!(
+            answer,
+            Err(format!(
+                "Invalid answer from DNS server '{server}' ({server}) for 'myip.opendns.com': no 'A' answer"
+            ))
+        );
+    }
+
+    #[test]
+    fn parses_the_error_code_and_the_id_of_an_answer() {
+        let mut msg = build_query(7, "myip.opendns.com", &RecordType::A).unwrap();
+        assert_eq!(
+            parse_answer(&msg, 7, &RecordType::A),
+            Err("not a response".to_string())
+        );
+        msg[2..4].copy_from_slice(&[0x81, 0x83]);
+        assert_eq!(
+            parse_answer(&msg, 7, &RecordType::A),
+            Err("the name does not exist".to_string())
+        );
+        assert_eq!(
+            parse_answer(&msg, 8, &RecordType::A),
+            Err("answer to another query".to_string())
+        );
+    }
+
+    #[test]
+    fn refuses_an_invalid_name() {
+        assert!(build_query(1, "a..example.com", &RecordType::A).is_err());
+        assert!(build_query(1, &"a".repeat(64), &RecordType::A).is_err());
+    }
+}
@@ -0,0 +1,145 @@
+//! `endpoints-test` command: query every configured IP endpoint, and a few well known ones, and report the IP each
+//! one returns, its latency and whether it agrees with the majority.
+
+use crate::config::Config;
+use crate::timing::{Phase, Timer};
+use crate::{RecordType, get_external_ip, ip_detect_client};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Well known endpoints which return the caller's IP as plain text
+const DEFAULT_ENDPOINTS: [(RecordType, &str); 6] = [
+    (RecordType::A, "https://api.ipify.org"),
+    (RecordType::A, "https://ipv4.icanhazip.com"),
+    (RecordType::A, "https://v4.ident.me"),
+    (RecordType::AAAA, "https://api6.ipify.org"),
+    (RecordType::AAAA, "https://ipv6.icanhazip.com"),
+    (RecordType::AAAA, "https://v6.ident.me"),
+];
+
+/// Result of querying a single endpoint
+#[derive(Debug, Clone)]
+struct Probe {
+    endpoint: String,
+    ip: Result<IpAddr, ()>,
+    latency: Duration,
+}
+
+/// Most common IP among the successful probes, if any
+fn majority(probes: &[Probe]) -> Option<IpAddr> {
+    let mut counts: BTreeMap<IpAddr, usize> = BTreeMap::new();
+    for ip in probes.iter().filter_map(|p| p.ip.ok()) {
+        *counts.entry(ip).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .map(|(ip, _)| ip)
+}
+
+/// Query the endpoints of both record types and print a report to stdout
+pub fn run(config: &Config) -> Result<(), ()> {
+    let client = ip_detect_client(config)?;
+
+    for rtype in [RecordType::A, RecordType::AAAA] {
+        let mut endpoints = config
+            .endpoints
+            .get(&rtype)
+            .map(|source| source.sources())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|source| source.to_string())
+            .filter(|endpoint| endpoint.starts_with("http"))
+            .collect::<Vec<_>>();
+        for (_, endpoint) in DEFAULT_ENDPOINTS.iter().filter(|(t, _)| *t == rtype) {
+            if !endpoints.iter().any(|e| e == endpoint) {
+                endpoints.push(endpoint.to_string());
+            }
+        }
+
+        let probes = endpoints
+            .into_iter()
+            .map(|endpoint| {
+                let timer = Timer::start(Phase::IpDetection, format!("Querying '{endpoint}'"));
+                let ip = get_external_ip(
+                    &rtype,
+                    &endpoint,
+                    client.get(&rtype),
+                    config.ip_max_response_bytes,
+                );
+                Probe {
+                    latency: timer.elapsed(),
+                    endpoint,
+                    ip,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        println!("'{rtype}' endpoints:");
+        for line in report_lines(&probes) {
+            println!("\t{line}");
+        }
+    }
+
+    Ok(())
+}
+
+/// One line for each of the `probes`, with its IP, its latency and whether it agrees with the majority
+fn report_lines(probes: &[Probe]) -> Vec<String> {
+    let majority = majority(probes);
+
+    probes
+        .iter()
+        .map(|probe| {
+            let (ip, agreement) = match probe.ip {
+                Ok(ip) if Some(ip) == majority => (ip.to_string(), "agrees"),
+                Ok(ip) => (ip.to_string(), "DISAGREES with the majority"),
+                Err(_) => ("-".to_string(), "failed"),
+            };
+            format!(
+                "{:<40} {:<40} {:>6}ms  {agreement}",
+                probe.endpoint,
+                ip,
+                probe.latency.as_millis()
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probe(endpoint: &str, ip: Result<&str, ()>, latency_ms: u64) -> Probe {
+        Probe {
+            endpoint: endpoint.to_string(),
+            ip: ip.map(|ip| ip.parse().unwrap()),
+            latency: Duration::from_millis(latency_ms),
+        }
+    }
+
+    #[test]
+    fn flags_the_endpoint_which_disagrees_with_the_majority() {
+        let probes = [
+            probe("https://a.example", Ok("203.0.113.7"), 40),
+            probe("https://b.example", Ok("198.51.100.1"), 25),
+            probe("https://c.example", Ok("203.0.113.7"), 120),
+            probe("https://d.example", Err(()), 3000),
+        ];
+
+        let lines = report_lines(&probes);
+
+        let agreement = lines
+            .iter()
+            .map(|line| line.split("ms  ").nth(1).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            agreement,
+            ["agrees", "DISAGREES with the majority", "agrees", "failed"]
+        );
+        assert!(lines[1].starts_with("https://b.example"), "{lines:?}");
+        assert!(lines[1].contains(" 198.51.100.1 "), "{lines:?}");
+        assert!(lines[2].contains("   120ms"), "{lines:?}");
+    }
+}
@@ -0,0 +1,137 @@
+//! Periodic "alive" log.
+//!
+//! On a stable connection nothing is logged for days. When `CF_DNS_HEARTBEAT_EVERY_SECONDS` is set, a line with the
+//! current IPs is logged at that period, whether or not the records changed in the meantime. The period is independent
+//! of the repeat interval: the sleep between cycles is interrupted to log it.
+
+use crate::RecordType;
+use crate::clock::Clock;
+use crate::shutdown;
+use log::info;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Heartbeat {
+    pub every: Duration,
+    next: Instant,
+    /// Whether the records changed since the last heartbeat
+    changed: bool,
+}
+
+impl Heartbeat {
+    /// Heartbeat logged every `every`, starting `every` from now
+    pub fn new(clock: &impl Clock, every: Duration) -> Self {
+        Self {
+            every,
+            next: clock.now() + every,
+            changed: false,
+        }
+    }
+
+    /// Record that the records changed
+    pub fn note_change(&mut self) {
+        self.changed = true;
+    }
+
+    /// Log the heartbeat if it is due, and return it
+    pub fn tick(
+        &mut self,
+        clock: &impl Clock,
+        ips: &BTreeMap<RecordType, IpAddr>,
+    ) -> Option<String> {
+        let now = clock.now();
+        if now < self.next {
+            return None;
+        }
+
+        let ips = ips
+            .iter()
+            .map(|(rtype, ip)| format!("'{rtype}' '{ip}'"))
+            .collect::<Vec<_>>();
+        let ips = if ips.is_empty() {
+            "none".to_string()
+        } else {
+            ips.join(", ")
+        };
+        let line = if self.changed {
+            format!("Alive, current IPs: {ips}")
+        } else {
+            format!("Alive, no changes, current IPs: {ips}")
+        };
+        info!("{line}");
+
+        self.changed = false;
+        // Skip the heartbeats which were missed, e.g. during a long cycle
+        while self.next <= now {
+            self.next += self.every;
+        }
+        Some(line)
+    }
+
+    /// Sleep for `duration`, waking up to log the heartbeats which become due meanwhile, and return them
+    pub fn sleep(
+        &mut self,
+        clock: &impl Clock,
+        duration: Duration,
+        ips: &BTreeMap<RecordType, IpAddr>,
+    ) -> Vec<String> {
+        let mut logged = Vec::new();
+        let end = clock.now() + duration;
+        loop {
+            let now = clock.now();
+            if now >= end || shutdown::requested() {
+                break;
+            }
+            clock.sleep(end.min(self.next).saturating_duration_since(now));
+            logged.extend(self.tick(clock, ips));
+        }
+        logged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn logs_the_heartbeat_on_schedule_during_the_sleep_between_cycles() {
+        let clock = MockClock::new(Duration::ZERO);
+        let start = clock.now();
+        let ips = BTreeMap::from([(RecordType::A, "203.0.113.7".parse().unwrap())]);
+        let mut heartbeat = Heartbeat::new(&clock, Duration::from_secs(60));
+
+        // A cycle every 150s, the records changing during the first one only
+        heartbeat.note_change();
+        let first = heartbeat.sleep(&clock, Duration::from_secs(150), &ips);
+        let second = heartbeat.sleep(&clock, Duration::from_secs(150), &ips);
+
+        assert_eq!(
+            first,
+            [
+                "Alive, current IPs: 'A' '203.0.113.7'",
+                "Alive, no changes, current IPs: 'A' '203.0.113.7'"
+            ]
+        );
+        assert_eq!(second.len(), 3);
+        assert_eq!(clock.now() - start, Duration::from_secs(300));
+    }
+
+    #[test]
+    fn skips_the_heartbeats_missed_during_a_long_cycle() {
+        let clock = MockClock::new(Duration::ZERO);
+        let mut heartbeat = Heartbeat::new(&clock, Duration::from_secs(60));
+
+        assert_eq!(heartbeat.tick(&clock, &BTreeMap::new()), None);
+        clock.advance(Duration::from_secs(250));
+        assert_eq!(
+            heartbeat.tick(&clock, &BTreeMap::new()).as_deref(),
+            Some("Alive, no changes, current IPs: none")
+        );
+        assert_eq!(heartbeat.tick(&clock, &BTreeMap::new()), None);
+        clock.advance(Duration::from_secs(50));
+        assert!(heartbeat.tick(&clock, &BTreeMap::new()).is_some());
+    }
+}
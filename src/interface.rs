@@ -0,0 +1,317 @@
+//! IP detection from a local network interface.
+//!
+//! Addresses are read by running `ip -o addr show dev <iface> scope global`, which means this source is only
+//! available on Linux hosts with `iproute2` installed. The address flags (`temporary`, `deprecated`, `tentative`) and
+//! the preferred lifetime reported by the kernel are used to pick the most appropriate IPv6 address.
+
+use crate::RecordType;
+use log::{error, warn};
+use std::fmt::Display;
+use std::net::{IpAddr, Ipv6Addr};
+use std::process::Command;
+use std::str::FromStr;
+
+/// Policy used to pick one IPv6 address when an interface has several global addresses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Ipv6Select {
+    /// Stable (EUI-64 or stable-privacy) public address
+    #[default]
+    Stable,
+    /// Temporary (RFC 4941 privacy extension) public address
+    Temporary,
+    /// Public address with the largest preferred lifetime, regardless of its flags
+    LargestLifetime,
+}
+
+impl TryFrom<&str> for Ipv6Select {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "stable" => Ok(Self::Stable),
+            "temporary" => Ok(Self::Temporary),
+            "largest-lifetime" => Ok(Self::LargestLifetime),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for Ipv6Select {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Ipv6Select::Stable => write!(f, "stable"),
+            Ipv6Select::Temporary => write!(f, "temporary"),
+            Ipv6Select::LargestLifetime => write!(f, "largest-lifetime"),
+        }
+    }
+}
+
+/// Address assigned to an interface, together with the flags relevant for selecting it
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Candidate {
+    addr: IpAddr,
+    temporary: bool,
+    deprecated: bool,
+    tentative: bool,
+    /// Preferred lifetime in seconds. `None` means `forever`.
+    preferred_lft: Option<u64>,
+}
+
+impl Candidate {
+    /// Whether the address can be published in a public DNS record
+    fn is_usable(&self) -> bool {
+        if self.deprecated || self.tentative {
+            return false;
+        }
+
+        match self.addr {
+            IpAddr::V4(ip) => {
+                !(ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified())
+            }
+            IpAddr::V6(ip) => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || ip.is_unicast_link_local()
+                    || ip.is_unique_local())
+            }
+        }
+    }
+}
+
+/// Get the current IP of the given type assigned to the interface `name`
+pub fn get_interface_ip(rtype: &RecordType, name: &str, select: Ipv6Select) -> Result<IpAddr, ()> {
+    let family = match rtype {
+        RecordType::A => "-4",
+        RecordType::AAAA => "-6",
+    };
+
+    let output = Command::new("ip")
+        .args(["-o", family, "addr", "show", "dev", name, "scope", "global"])
+        .output()
+        .map_err(|e| {
+            error!("Could not run `ip` to list the addresses of interface '{name}': {e}");
+        })?;
+
+    if !output.status.success() {
+        error!(
+            "Could not list the addresses of interface '{name}': {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return Err(());
+    }
+
+    let candidates = parse_ip_addr_output(&String::from_utf8_lossy(&output.stdout));
+    let addr = match rtype {
+        RecordType::A => candidates.iter().find(|c| c.is_usable()).map(|c| c.addr),
+        RecordType::AAAA => select_ipv6(&candidates, select).map(IpAddr::V6),
+    };
+
+    addr.ok_or_else(|| {
+        error!("Interface '{name}' has no usable public '{rtype}' address");
+    })
+}
+
+/// Parse the output of `ip -o addr show` into a list of candidate addresses
+///
+/// Lines which cannot be parsed are ignored.
+fn parse_ip_addr_output(output: &str) -> Vec<Candidate> {
+    output.lines().filter_map(parse_ip_addr_line).collect()
+}
+
+/// Parse a single line of `ip -o addr show`, e.g.:
+///
+/// `2: eth0    inet6 2001:db8::1/64 scope global temporary dynamic \       valid_lft 86000sec preferred_lft 14000sec`
+fn parse_ip_addr_line(line: &str) -> Option<Candidate> {
+    let mut tokens = line.split_whitespace();
+    tokens.find(|t| *t == "inet" || *t == "inet6")?;
+    let addr = tokens.next()?.split('/').next()?;
+    let addr = IpAddr::from_str(addr).ok()?;
+
+    let mut candidate = Candidate {
+        addr,
+        temporary: false,
+        deprecated: false,
+        tentative: false,
+        preferred_lft: None,
+    };
+
+    while let Some(token) = tokens.next() {
+        match token {
+            "temporary" => candidate.temporary = true,
+            "deprecated" => candidate.deprecated = true,
+            "tentative" => candidate.tentative = true,
+            "preferred_lft" => {
+                candidate.preferred_lft = tokens
+                    .next()
+                    .and_then(|lft| lft.strip_suffix("sec"))
+                    .and_then(|lft| lft.parse().ok());
+            }
+            _ => {}
+        }
+    }
+
+    Some(candidate)
+}
+
+/// Pick an IPv6 address from the candidates according to the selection policy
+///
+/// If the policy asks for a stable or temporary address and none is available, the other kind is used instead.
+fn select_ipv6(candidates: &[Candidate], select: Ipv6Select) -> Option<Ipv6Addr> {
+    let usable = candidates
+        .iter()
+        .filter(|c| c.is_usable())
+        .filter_map(|c| match c.addr {
+            IpAddr::V6(ip) => Some((ip, c)),
+            IpAddr::V4(_) => None,
+        })
+        .collect::<Vec<_>>();
+
+    // `None` means `forever`, which beats any finite lifetime
+    let longest_lived = |(_, c): &&(Ipv6Addr, &Candidate)| c.preferred_lft.unwrap_or(u64::MAX);
+
+    let wanted_temporary = match select {
+        Ipv6Select::Stable => false,
+        Ipv6Select::Temporary => true,
+        Ipv6Select::LargestLifetime => {
+            return usable.iter().max_by_key(longest_lived).map(|(ip, _)| *ip);
+        }
+    };
+
+    match usable
+        .iter()
+        .filter(|(_, c)| c.temporary == wanted_temporary)
+        .max_by_key(longest_lived)
+    {
+        Some((ip, _)) => Some(*ip),
+        None => {
+            let fallback = usable.iter().max_by_key(longest_lived).map(|(ip, _)| *ip);
+            if let Some(ip) = fallback {
+                warn!("No {select} IPv6 address available, falling back to '{ip}'");
+            }
+            fallback
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `ip -o -6 addr show dev eth0` of a host with a temporary, a stable, a deprecated temporary, a tentative, a ULA
+    /// and a link-local address
+    const IP_ADDR_OUTPUT: &str = "\
+2: eth0    inet6 2001:db8::a1b2/64 scope global temporary dynamic \\       valid_lft 86000sec preferred_lft 14000sec
+2: eth0    inet6 2001:db8::211:22ff:fe33:4455/64 scope global dynamic mngtmpaddr noprefixroute \\       valid_lft 86000sec preferred_lft 86000sec
+2: eth0    inet6 2001:db8::dead/64 scope global temporary deprecated dynamic \\       valid_lft 40000sec preferred_lft 0sec
+2: eth0    inet6 2001:db8::beef/64 scope global tentative \\       valid_lft forever preferred_lft forever
+2: eth0    inet6 fd00::1/64 scope global \\       valid_lft forever preferred_lft forever
+2: eth0    inet6 fe80::211:22ff:fe33:4455/64 scope link \\       valid_lft forever preferred_lft forever
+";
+
+    fn ip(addr: &str) -> Ipv6Addr {
+        Ipv6Addr::from_str(addr).unwrap()
+    }
+
+    fn candidate(addr: &str, temporary: bool, preferred_lft: Option<u64>) -> Candidate {
+        Candidate {
+            addr: IpAddr::V6(ip(addr)),
+            temporary,
+            deprecated: false,
+            tentative: false,
+            preferred_lft,
+        }
+    }
+
+    #[test]
+    fn parses_the_addresses_and_their_flags() {
+        let candidates = parse_ip_addr_output(IP_ADDR_OUTPUT);
+        assert_eq!(candidates.len(), 6);
+        assert_eq!(
+            candidates[0],
+            candidate("2001:db8::a1b2", true, Some(14000))
+        );
+        assert_eq!(
+            candidates[1],
+            candidate("2001:db8::211:22ff:fe33:4455", false, Some(86000))
+        );
+        assert!(candidates[2].temporary && candidates[2].deprecated);
+        assert!(candidates[3].tentative);
+        assert_eq!(candidates[3].preferred_lft, None);
+    }
+
+    #[test]
+    fn ignores_unparseable_lines() {
+        let output = "garbage\n2: eth0    inet6 not-an-ip/64 scope global\n\n";
+        assert!(parse_ip_addr_output(output).is_empty());
+    }
+
+    #[test]
+    fn selects_the_stable_address_by_default() {
+        let candidates = parse_ip_addr_output(IP_ADDR_OUTPUT);
+        assert_eq!(
+            select_ipv6(&candidates, Ipv6Select::default()),
+            Some(ip("2001:db8::211:22ff:fe33:4455"))
+        );
+    }
+
+    #[test]
+    fn selects_the_temporary_address_which_is_not_deprecated() {
+        let candidates = parse_ip_addr_output(IP_ADDR_OUTPUT);
+        assert_eq!(
+            select_ipv6(&candidates, Ipv6Select::Temporary),
+            Some(ip("2001:db8::a1b2"))
+        );
+    }
+
+    #[test]
+    fn selects_the_largest_lifetime_with_forever_first() {
+        let candidates = [
+            candidate("2001:db8::1", false, Some(100)),
+            candidate("2001:db8::2", true, None),
+            candidate("2001:db8::3", false, Some(200)),
+        ];
+        assert_eq!(
+            select_ipv6(&candidates, Ipv6Select::LargestLifetime),
+            Some(ip("2001:db8::2"))
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_other_kind_of_address() {
+        let candidates = [candidate("2001:db8::1", true, Some(100))];
+        assert_eq!(
+            select_ipv6(&candidates, Ipv6Select::Stable),
+            Some(ip("2001:db8::1"))
+        );
+    }
+
+    #[test]
+    fn never_selects_ula_link_local_deprecated_or_tentative_addresses() {
+        let output = IP_ADDR_OUTPUT
+            .lines()
+            .filter(|line| !line.contains("2001:db8::a1b2") && !line.contains("2001:db8::211"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let candidates = parse_ip_addr_output(&output);
+        assert_eq!(candidates.len(), 4);
+        for select in [
+            Ipv6Select::Stable,
+            Ipv6Select::Temporary,
+            Ipv6Select::LargestLifetime,
+        ] {
+            assert_eq!(select_ipv6(&candidates, select), None);
+        }
+    }
+
+    #[test]
+    fn parses_the_selection_policy() {
+        assert_eq!(Ipv6Select::try_from("stable"), Ok(Ipv6Select::Stable));
+        assert_eq!(Ipv6Select::try_from("temporary"), Ok(Ipv6Select::Temporary));
+        assert_eq!(
+            Ipv6Select::try_from("largest-lifetime"),
+            Ok(Ipv6Select::LargestLifetime)
+        );
+        assert!(Ipv6Select::try_from("newest").is_err());
+    }
+}
@@ -0,0 +1,138 @@
+//! `--list` command: print the managed records with their live and desired TTL.
+//!
+//! The desired TTL of a desired record is the one of the records file, and the one of a monitored host's record is the
+//! TTL its records are created with (`CF_DNS_DEFAULT_TTL_A` and `CF_DNS_DEFAULT_TTL_AAAA`). A record whose live TTL
+//! differs is marked as drifting, unless it is proxied, since the TTL of proxied records is always automatic.
+
+use crate::clock::SystemClock;
+use crate::config::Config;
+use crate::zone::ZoneClient;
+use crate::{Record, Ttl};
+
+/// TTL as shown in the list
+fn ttl(ttl: Ttl) -> String {
+    match ttl {
+        Ttl::Auto => "auto".to_string(),
+        Ttl::Seconds(seconds) => seconds.to_string(),
+    }
+}
+
+/// Desired TTL of the managed `record` of `zone`, `None` if it is not known
+fn desired_ttl(zone: &ZoneClient, record: &Record, config: &Config) -> Option<Ttl> {
+    let desired = zone.desired.iter().flatten().find(|desired| {
+        desired.name.eq_ignore_ascii_case(&record.name) && desired.rtype == record.rtype()
+    });
+    match desired {
+        Some(desired) => Some(desired.ttl),
+        None => zone
+            .hosts
+            .iter()
+            .any(|host| host.name.eq_ignore_ascii_case(&record.name))
+            .then(|| config.ttl_for(record.rtype())),
+    }
+}
+
+/// Line of the list for the managed `record` of `zone`, marked if its TTL drifts
+fn row(zone: &ZoneClient, record: &Record, config: &Config) -> String {
+    let desired = desired_ttl(zone, record, config);
+    let drift = !record.proxied && desired.is_some_and(|desired| desired != record.ttl);
+    let desired = match (desired, record.proxied) {
+        (Some(_), true) => "auto".to_string(),
+        (Some(desired), false) => ttl(desired),
+        (None, _) => "-".to_string(),
+    };
+    format!(
+        "  {:<32} {:<4} {:<39} {:>8} {:>8}{}",
+        record.name,
+        record.rtype().to_string(),
+        record.content.to_string(),
+        ttl(record.ttl),
+        desired,
+        if drift { "  TTL drift" } else { "" }
+    )
+}
+
+/// Print the managed records of every configured zone
+pub fn run(config: &Config) -> Result<(), ()> {
+    let zones = config
+        .zones
+        .iter()
+        .map(|zone| ZoneClient::connect(zone, config, &[]))
+        .collect::<Result<Vec<_>, ()>>()?;
+
+    for mut zone in zones {
+        let records = zone.managed_records(&SystemClock)?;
+        println!(
+            "Zone '{}' ({}):",
+            zone.zone.name.as_deref().unwrap_or("unknown"),
+            zone.zone.id
+        );
+        println!(
+            "  {:<32} {:<4} {:<39} {:>8} {:>8}",
+            "NAME", "TYPE", "CONTENT", "LIVE TTL", "WANT TTL"
+        );
+        for record in records {
+            println!("{}", row(&zone, &record, config));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RecordType;
+    use crate::provider::FakeProvider;
+    use std::collections::BTreeMap;
+
+    fn record(name: &str, ttl: Ttl, proxied: bool) -> Record {
+        Record {
+            name: name.to_string(),
+            ttl,
+            content: "203.0.113.7".parse().unwrap(),
+            proxied,
+        }
+    }
+
+    #[test]
+    fn flags_a_live_ttl_which_differs_from_the_desired_one() {
+        let mut config = Config::for_tests(&[RecordType::A]);
+        config.ttl_by_type = BTreeMap::from([(RecordType::A, Ttl::Seconds(300))]);
+        let zone = ZoneClient::for_tests(
+            &["www.example.com", "api.example.com", "cdn.example.com"],
+            &config,
+            &FakeProvider::new(Vec::new()),
+        );
+        let columns = |record| {
+            let row = row(&zone, &record, &config);
+            row.split_whitespace()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            columns(record("www.example.com", Ttl::Seconds(300), false)),
+            ["www.example.com", "A", "203.0.113.7", "300", "300"]
+        );
+        assert_eq!(
+            columns(record("api.example.com", Ttl::Auto, false)),
+            [
+                "api.example.com",
+                "A",
+                "203.0.113.7",
+                "auto",
+                "300",
+                "TTL",
+                "drift"
+            ]
+        );
+        assert_eq!(
+            columns(record("cdn.example.com", Ttl::Auto, true)),
+            ["cdn.example.com", "A", "203.0.113.7", "auto", "auto"]
+        );
+        assert_eq!(
+            columns(record("mail.example.com", Ttl::Seconds(60), false)),
+            ["mail.example.com", "A", "203.0.113.7", "60", "-"]
+        );
+    }
+}
@@ -0,0 +1,321 @@
+//! Log output.
+//!
+//! Log messages are written to the console (stdout, or stderr for the commands which write their output to stdout)
+//! and, if `CF_DNS_LOG_FILE` is set, appended to a file. Each output has its own minimum level and format, so that e.g.
+//! only warnings and errors are kept in the file.
+
+use log::{Level, LevelFilter, Log, Metadata, Record, error};
+use serde_json::json;
+use std::cell::RefCell;
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+thread_local! {
+    /// Error messages logged by this thread during [`capture_errors`], `None` outside of it
+    static CAPTURED_ERRORS: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+/// Format of the log lines of an output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Format {
+    /// `[<timestamp> <level> <target>] <message>`
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+impl TryFrom<&str> for Format {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, ()> {
+        match value {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Output {
+    /// stdout, or stderr if it is set (when stdout is the output of a command)
+    Console(AtomicBool),
+    File(Mutex<File>),
+    /// Lines kept in memory, for the tests
+    #[cfg(test)]
+    Memory(Mutex<Vec<String>>),
+}
+
+/// A log output, with its own minimum level and format
+#[derive(Debug)]
+struct Sink {
+    /// Minimum [`LevelFilter`], stored as `usize` so that it can be changed after the logger is set up
+    level: AtomicUsize,
+    format: Format,
+    output: Output,
+}
+
+impl Sink {
+    fn new(level: LevelFilter, format: Format, output: Output) -> Self {
+        Self {
+            level: AtomicUsize::new(level as usize),
+            format,
+            output,
+        }
+    }
+
+    fn level(&self) -> LevelFilter {
+        match self.level.load(Ordering::Relaxed) {
+            0 => LevelFilter::Off,
+            1 => LevelFilter::Error,
+            2 => LevelFilter::Warn,
+            3 => LevelFilter::Info,
+            4 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    }
+
+    fn write(&self, record: &Record) {
+        let timestamp = jiff::Timestamp::now().strftime("%Y-%m-%dT%H:%M:%SZ");
+        let line = match self.format {
+            Format::Text => format!(
+                "[{timestamp} {:<5} {}] {}",
+                record.level(),
+                record.target(),
+                record.args()
+            ),
+            Format::Json => json!({
+                "timestamp": timestamp.to_string(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            })
+            .to_string(),
+        };
+
+        // A log line which can't be written can't be logged either, so errors are ignored
+        let _ = match &self.output {
+            Output::Console(to_stderr) if to_stderr.load(Ordering::Relaxed) => {
+                writeln!(io::stderr().lock(), "{line}")
+            }
+            Output::Console(_) => writeln!(io::stdout().lock(), "{line}"),
+            Output::File(file) => match file.lock() {
+                Ok(mut file) => writeln!(file, "{line}"),
+                Err(_) => Ok(()),
+            },
+            #[cfg(test)]
+            Output::Memory(lines) => {
+                lines.lock().unwrap().push(line);
+                Ok(())
+            }
+        };
+    }
+}
+
+#[derive(Debug)]
+struct Logger {
+    sinks: Vec<Sink>,
+}
+
+impl Logger {
+    /// Only log the messages enabled by at least one sink
+    fn update_max_level(&self) {
+        let max_level = self
+            .sinks
+            .iter()
+            .map(|sink| sink.level())
+            .max()
+            .unwrap_or(LevelFilter::Off);
+        log::set_max_level(max_level);
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.sinks
+            .iter()
+            .any(|sink| metadata.level() <= sink.level())
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() == Level::Error {
+            CAPTURED_ERRORS.with_borrow_mut(|captured| {
+                if let Some(captured) = captured {
+                    captured.push(record.args().to_string());
+                }
+            });
+        }
+        for sink in self.sinks.iter().filter(|s| record.level() <= s.level()) {
+            sink.write(record);
+        }
+    }
+
+    fn flush(&self) {
+        for sink in &self.sinks {
+            let _ = match &sink.output {
+                Output::Console(_) => io::stdout().flush().and(io::stderr().flush()),
+                Output::File(file) => match file.lock() {
+                    Ok(mut file) => file.flush(),
+                    Err(_) => Ok(()),
+                },
+                #[cfg(test)]
+                Output::Memory(_) => Ok(()),
+            };
+        }
+    }
+}
+
+/// Set up the log outputs from the environment
+///
+/// Invalid settings are logged after the outputs which could be set up are in place, and cause an error.
+pub fn init() -> Result<(), ()> {
+    let mut errors = Vec::new();
+
+    let mut sinks = vec![Sink::new(
+        env_level("CF_DNS_LOG_LEVEL", LevelFilter::Info, &mut errors),
+        env_format("CF_DNS_LOG_FORMAT", &mut errors),
+        Output::Console(AtomicBool::new(false)),
+    )];
+
+    if let Ok(path) = env::var("CF_DNS_LOG_FILE") {
+        let path = path.trim();
+        match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => sinks.push(Sink::new(
+                env_level("CF_DNS_LOG_FILE_LEVEL", LevelFilter::Warn, &mut errors),
+                env_format("CF_DNS_LOG_FILE_FORMAT", &mut errors),
+                Output::File(Mutex::new(file)),
+            )),
+            Err(e) => errors.push(format!("Could not open log file '{path}': {e}")),
+        }
+    }
+
+    let logger = LOGGER.get_or_init(|| Logger { sinks });
+    log::set_logger(logger).map_err(|e| {
+        eprintln!("Could not set up logging: {e}");
+    })?;
+    logger.update_max_level();
+
+    for e in &errors {
+        error!("{e}");
+    }
+    if errors.is_empty() { Ok(()) } else { Err(()) }
+}
+
+/// Raise the minimum level of the console output to `level`, if it is lower
+pub fn restrict_console(level: LevelFilter) {
+    if let Some(logger) = LOGGER.get() {
+        for sink in &logger.sinks {
+            if matches!(sink.output, Output::Console(_)) && sink.level() > level {
+                sink.level.store(level as usize, Ordering::Relaxed);
+            }
+        }
+        logger.update_max_level();
+    }
+}
+
+/// Log to stderr instead of stdout on the console, so that the output of a command which is written to stdout is kept
+/// apart
+pub fn console_to_stderr() {
+    if let Some(logger) = LOGGER.get() {
+        for sink in &logger.sinks {
+            if let Output::Console(to_stderr) = &sink.output {
+                to_stderr.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Log the errors of the tests nowhere, so that they can be captured by [`capture_errors`]
+#[cfg(test)]
+pub fn init_for_tests() {
+    let logger = LOGGER.get_or_init(|| Logger { sinks: Vec::new() });
+    let _ = log::set_logger(logger);
+    log::set_max_level(LevelFilter::Error);
+}
+
+/// Run `f`, and return the error messages it logged along with its result
+pub fn capture_errors<T>(f: impl FnOnce() -> T) -> (T, Vec<String>) {
+    let outer = CAPTURED_ERRORS.replace(Some(Vec::new()));
+    let result = f();
+    let captured = CAPTURED_ERRORS.replace(outer).unwrap_or_default();
+    (result, captured)
+}
+
+fn env_level(name: &str, default: LevelFilter, errors: &mut Vec<String>) -> LevelFilter {
+    match env::var(name) {
+        Ok(level) => LevelFilter::from_str(level.trim()).unwrap_or_else(|_| {
+            errors.push(format!(
+                "Could not parse `{name}` which should be one of `off`, `error`, `warn`, `info`, `debug` or `trace`"
+            ));
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+fn env_format(name: &str, errors: &mut Vec<String>) -> Format {
+    match env::var(name) {
+        Ok(format) => Format::try_from(format.trim()).unwrap_or_else(|_| {
+            errors.push(format!(
+                "Could not parse `{name}` which should be either `text` or `json`"
+            ));
+            Format::default()
+        }),
+        Err(_) => Format::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(sink: &Sink) -> Vec<String> {
+        match &sink.output {
+            Output::Memory(lines) => lines.lock().unwrap().clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn logs_to_the_sinks_which_enable_the_level() {
+        let memory = || Output::Memory(Mutex::new(Vec::new()));
+        let logger = Logger {
+            sinks: vec![
+                Sink::new(LevelFilter::Info, Format::Text, memory()),
+                Sink::new(LevelFilter::Warn, Format::Json, memory()),
+            ],
+        };
+
+        logger.log(
+            &Record::builder()
+                .level(Level::Warn)
+                .target("cf_dns_rs")
+                .args(format_args!("warned"))
+                .build(),
+        );
+        logger.log(
+            &Record::builder()
+                .level(Level::Info)
+                .target("cf_dns_rs")
+                .args(format_args!("informed"))
+                .build(),
+        );
+
+        let console = lines(&logger.sinks[0]);
+        assert_eq!(console.len(), 2);
+        assert!(console[0].ends_with("WARN  cf_dns_rs] warned"));
+        assert!(console[1].ends_with("INFO  cf_dns_rs] informed"));
+        let file = lines(&logger.sinks[1]);
+        assert_eq!(file.len(), 1);
+        let line: serde_json::Value = serde_json::from_str(&file[0]).unwrap();
+        assert_eq!(line["level"], "WARN");
+        assert_eq!(line["message"], "warned");
+    }
+}
@@ -0,0 +1,287 @@
+//! Prometheus metrics.
+//!
+//! Metrics are collected in a global registry, and served in the OpenMetrics text format over HTTP at `/metrics` when
+//! `CF_DNS_METRICS_ADDR` is set. Every metric name has the `cf_dns_` prefix.
+
+use crate::status;
+use log::{error, info, warn};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Upper bounds (in seconds) of the IP detection latency histogram buckets
+const LATENCY_BUCKETS: [f64; 9] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics::new());
+
+/// IP detection statistics of a single IP source
+#[derive(Debug, Clone, Default)]
+struct EndpointStats {
+    successes: u64,
+    failures: u64,
+    /// Number of requests in each of the [`LATENCY_BUCKETS`]
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    latency_sum: f64,
+}
+
+#[derive(Debug)]
+struct Metrics {
+    /// Stats for each IP source, by the source's display name
+    endpoints: BTreeMap<String, EndpointStats>,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            endpoints: BTreeMap::new(),
+        }
+    }
+
+    fn record_ip_detection(&mut self, endpoint: &str, success: bool, latency: Duration) {
+        let stats = self.endpoints.entry(endpoint.to_string()).or_default();
+        if success {
+            stats.successes += 1;
+        } else {
+            stats.failures += 1;
+        }
+
+        let seconds = latency.as_secs_f64();
+        stats.latency_sum += seconds;
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            stats.buckets[bucket] += 1;
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# HELP cf_dns_build_info Version of the running app");
+        let _ = writeln!(out, "# TYPE cf_dns_build_info gauge");
+        let _ = writeln!(
+            out,
+            "cf_dns_build_info{{version=\"{}\"}} 1",
+            escape_label(env!("CARGO_PKG_VERSION"))
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP cf_dns_ip_detection_requests IP detection requests, by IP source and result"
+        );
+        let _ = writeln!(out, "# TYPE cf_dns_ip_detection_requests counter");
+        for (endpoint, stats) in &self.endpoints {
+            let endpoint = escape_label(endpoint);
+            let _ = writeln!(
+                out,
+                "cf_dns_ip_detection_requests_total{{endpoint=\"{endpoint}\",result=\"success\"}} {}",
+                stats.successes
+            );
+            let _ = writeln!(
+                out,
+                "cf_dns_ip_detection_requests_total{{endpoint=\"{endpoint}\",result=\"failure\"}} {}",
+                stats.failures
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP cf_dns_ip_detection_duration_seconds IP detection request latency, by IP source"
+        );
+        let _ = writeln!(out, "# TYPE cf_dns_ip_detection_duration_seconds histogram");
+        let _ = writeln!(out, "# UNIT cf_dns_ip_detection_duration_seconds seconds");
+        for (endpoint, stats) in &self.endpoints {
+            let endpoint = escape_label(endpoint);
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(stats.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "cf_dns_ip_detection_duration_seconds_bucket{{endpoint=\"{endpoint}\",le=\"{bound:?}\"}} {cumulative}"
+                );
+            }
+            let count = stats.successes + stats.failures;
+            let _ = writeln!(
+                out,
+                "cf_dns_ip_detection_duration_seconds_bucket{{endpoint=\"{endpoint}\",le=\"+Inf\"}} {count}"
+            );
+            let _ = writeln!(
+                out,
+                "cf_dns_ip_detection_duration_seconds_sum{{endpoint=\"{endpoint}\"}} {}",
+                stats.latency_sum
+            );
+            let _ = writeln!(
+                out,
+                "cf_dns_ip_detection_duration_seconds_count{{endpoint=\"{endpoint}\"}} {count}"
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP cf_dns_record_last_error_timestamp_seconds Time of the last failure of each failing record, with its reason"
+        );
+        let _ = writeln!(
+            out,
+            "# TYPE cf_dns_record_last_error_timestamp_seconds gauge"
+        );
+        let _ = writeln!(
+            out,
+            "# UNIT cf_dns_record_last_error_timestamp_seconds seconds"
+        );
+        for ((host, rtype), last_error) in status::last_errors() {
+            let timestamp = last_error
+                .timestamp
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64();
+            let _ = writeln!(
+                out,
+                "cf_dns_record_last_error_timestamp_seconds{{host=\"{}\",type=\"{rtype}\",message=\"{}\"}} {timestamp:?}",
+                escape_label(&host),
+                escape_label(&last_error.message)
+            );
+        }
+
+        out.push_str("# EOF\n");
+        out
+    }
+}
+
+/// Escape a Prometheus label value
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Record the outcome and latency of an IP detection request
+pub fn record_ip_detection(endpoint: &str, success: bool, latency: Duration) {
+    if let Ok(mut metrics) = METRICS.lock() {
+        metrics.record_ip_detection(endpoint, success, latency);
+    }
+}
+
+/// Serve the metrics at `http://<addr>/metrics` from a background thread
+pub fn serve(addr: &str) -> Result<(), ()> {
+    let listener = TcpListener::bind(addr).map_err(|e| {
+        error!("Could not listen for metrics requests on '{addr}': {e}");
+    })?;
+
+    info!("Serving metrics on 'http://{addr}/metrics'");
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = handle_request(stream) {
+                        warn!("Could not answer metrics request: {e}");
+                    }
+                }
+                Err(e) => warn!("Could not accept metrics connection: {e}"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_request(mut stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+
+    let mut parts = request_line.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = METRICS
+                .lock()
+                .map(|metrics| metrics.render())
+                .unwrap_or_default();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/openmetrics-text; version=1.0.0; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+
+    stream.write_all(response.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_the_latency_and_the_failures_of_each_endpoint() {
+        let mut metrics = Metrics::new();
+        metrics.record_ip_detection("https://slow.example", true, Duration::from_millis(300));
+        metrics.record_ip_detection("https://slow.example", false, Duration::from_secs(60));
+        metrics.record_ip_detection("https://fast.example", true, Duration::from_millis(20));
+
+        let rendered = metrics.render();
+        let lines = rendered.lines().collect::<Vec<_>>();
+        for expected in [
+            r#"cf_dns_ip_detection_requests_total{endpoint="https://slow.example",result="success"} 1"#,
+            r#"cf_dns_ip_detection_requests_total{endpoint="https://slow.example",result="failure"} 1"#,
+            r#"cf_dns_ip_detection_duration_seconds_bucket{endpoint="https://slow.example",le="0.25"} 0"#,
+            r#"cf_dns_ip_detection_duration_seconds_bucket{endpoint="https://slow.example",le="0.5"} 1"#,
+            r#"cf_dns_ip_detection_duration_seconds_bucket{endpoint="https://slow.example",le="30.0"} 1"#,
+            r#"cf_dns_ip_detection_duration_seconds_bucket{endpoint="https://slow.example",le="+Inf"} 2"#,
+            r#"cf_dns_ip_detection_duration_seconds_sum{endpoint="https://slow.example"} 60.3"#,
+            r#"cf_dns_ip_detection_duration_seconds_count{endpoint="https://slow.example"} 2"#,
+            r#"cf_dns_ip_detection_duration_seconds_bucket{endpoint="https://fast.example",le="0.05"} 1"#,
+        ] {
+            assert!(lines.contains(&expected), "{expected} not in:\n{rendered}");
+        }
+        assert!(rendered.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn escapes_the_label_values() {
+        assert_eq!(escape_label("a\"b\\c\nd"), r#"a\"b\\c\nd"#);
+    }
+
+    #[test]
+    fn renders_every_metric_family_in_the_openmetrics_format() {
+        let mut metrics = Metrics::new();
+        metrics.record_ip_detection("https://ip.example", true, Duration::from_millis(20));
+
+        let rendered = metrics.render();
+        let lines = rendered.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.last(), Some(&"# EOF"));
+        assert_eq!(rendered.matches("# EOF").count(), 1);
+        assert!(lines.contains(&concat!(
+            r#"cf_dns_build_info{version=""#,
+            env!("CARGO_PKG_VERSION"),
+            r#""} 1"#
+        )));
+
+        // Every sample belongs to the family of the last HELP and TYPE lines, whose names have the prefix
+        let mut family = None;
+        for line in &lines[..lines.len() - 1] {
+            if let Some(help) = line.strip_prefix("# HELP ") {
+                let name = help.split(' ').next().unwrap();
+                assert!(name.starts_with("cf_dns_"), "{line}");
+                family = Some(name);
+            } else if let Some(rest) = line
+                .strip_prefix("# TYPE ")
+                .or(line.strip_prefix("# UNIT "))
+            {
+                assert_eq!(rest.split(' ').next(), family, "{line}");
+            } else {
+                let family = family.expect("sample before any HELP line");
+                assert!(line.starts_with(family), "{line} is not in family {family}");
+                assert!(
+                    line.rsplit(' ').next().unwrap().parse::<f64>().is_ok(),
+                    "{line}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,632 @@
+//! Async API, for embedding the updater in an async application. Only built with the `async` feature.
+//!
+//! [`reconcile`] makes the `A` and `AAAA` records of a zone match a desired state, like the binary does for the records
+//! of `CF_DNS_RECORDS_FILE`, through any async [`DnsProvider`]. [`CloudflareClient`] is the provider of the Cloudflare
+//! API, which sends its requests with the async `reqwest` client and needs a `tokio` runtime. Only the I/O differs from
+//! the blocking binary: the changes are worked out, and the requests are built, retried and their responses parsed, by
+//! the same code.
+
+use crate::clock::{Clock, SystemClock};
+use crate::cloudflare::{self, CfApi, CreateTransport, RequestIds, Sent};
+use crate::comment;
+use crate::desired::{self, Op};
+use log::{debug, error, info, warn};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::net::IpAddr;
+
+pub use crate::cloudflare::Auth;
+pub use crate::desired::{Content, DesiredRecord};
+pub use crate::report::OpResult;
+pub use crate::{CfRecord, Record, RecordType, Ttl};
+
+/// Async operations on the DNS records of a zone, like those through which the binary changes them
+pub trait DnsProvider {
+    /// Get all `A` and `AAAA` records of the zone
+    fn list_records(&self, zone_id: &str)
+    -> impl Future<Output = Result<Vec<CfRecord>, ()>> + Send;
+
+    /// Create a record, with an optional comment. Returns the id of the created record.
+    fn create_record(
+        &self,
+        zone_id: &str,
+        record: &Record,
+        comment: Option<&str>,
+    ) -> impl Future<Output = Result<String, ()>> + Send;
+
+    /// Set the name, content, TTL, proxied status and comment of the `current` record
+    fn update_record(
+        &self,
+        zone_id: &str,
+        current: &CfRecord,
+        record: &Record,
+        comment: &str,
+    ) -> impl Future<Output = Result<(), ()>> + Send;
+
+    /// Delete a record
+    fn delete_record(
+        &self,
+        zone_id: &str,
+        record_id: &str,
+    ) -> impl Future<Output = Result<(), ()>> + Send;
+}
+
+/// Result of reconciling a single record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordOutcome {
+    pub name: String,
+    pub rtype: RecordType,
+    pub result: OpResult,
+}
+
+/// Make the `A` and `AAAA` records of the zone with id `zone_id` match `desired`
+///
+/// Dynamic records get the IP of their type in `cur_ips`, and are left as they are when there is none. Created and
+/// updated records are marked as managed in their comment, and only the managed records which are no longer desired are
+/// deleted. Records which are locked or were added automatically by Cloudflare are never changed.
+///
+/// Fails only if the records can't be listed. The result of each change is in the returned outcomes.
+pub async fn reconcile(
+    provider: &impl DnsProvider,
+    zone_id: &str,
+    desired: &[DesiredRecord],
+    cur_ips: &BTreeMap<RecordType, IpAddr>,
+    proxied_default: bool,
+) -> Result<Vec<RecordOutcome>, ()> {
+    let records = provider.list_records(zone_id).await?;
+
+    let mut outcomes = Vec::new();
+    for op in desired::diff(desired, &records, cur_ips, proxied_default, false) {
+        let (name, rtype) = op.target();
+        let name = name.to_string();
+        let result = apply(provider, zone_id, op).await;
+        outcomes.push(RecordOutcome {
+            name,
+            rtype,
+            result,
+        });
+    }
+    Ok(outcomes)
+}
+
+/// Apply a single operation of [`reconcile`]
+async fn apply(provider: &impl DnsProvider, zone_id: &str, op: Op) -> OpResult {
+    match op {
+        Op::Keep(_) => OpResult::Unchanged,
+        Op::Update { current, .. } | Op::Delete(current) if current.locked => {
+            warn!(
+                "'{}' record '{}' is locked by Cloudflare and cannot be changed, skipping it",
+                current.record.rtype(),
+                current.record.name
+            );
+            OpResult::Skipped
+        }
+        Op::Update { current, .. } | Op::Delete(current) if current.auto_added => {
+            warn!(
+                "'{}' record '{}' was added automatically by Cloudflare, skipping it",
+                current.record.rtype(),
+                current.record.name
+            );
+            OpResult::SkippedAutoAdded
+        }
+        Op::Update { current, desired } => {
+            let comment = comment::managed(None, &desired.name, &desired.content);
+            match provider
+                .update_record(zone_id, &current, &desired, &comment)
+                .await
+            {
+                Ok(_) => {
+                    info!(
+                        "Updated '{}' record '{}' to IP '{}', TTL '{}', proxied '{}'",
+                        desired.rtype(),
+                        desired.name,
+                        desired.content,
+                        desired.ttl,
+                        desired.proxied
+                    );
+                    OpResult::Updated
+                }
+                Err(_) => {
+                    error!(
+                        "Failed to update '{}' record '{}' to IP '{}'",
+                        desired.rtype(),
+                        desired.name,
+                        desired.content
+                    );
+                    OpResult::Failed
+                }
+            }
+        }
+        Op::Create(record) => {
+            let comment = comment::managed(None, &record.name, &record.content);
+            let (name, rtype, ip) = (&record.name, record.rtype(), record.content);
+            match provider
+                .create_record(zone_id, &record, Some(&comment))
+                .await
+            {
+                Ok(_) => {
+                    info!("Created '{rtype}' record '{name}' with IP '{ip}'");
+                    OpResult::Created
+                }
+                Err(_) => {
+                    error!("Failed to create '{rtype}' record '{name}' with IP '{ip}'");
+                    OpResult::Failed
+                }
+            }
+        }
+        Op::Delete(current) => {
+            let (name, rtype, ip) = (
+                &current.record.name,
+                current.record.rtype(),
+                current.record.content,
+            );
+            match provider.delete_record(zone_id, &current.id).await {
+                Ok(_) => {
+                    info!(
+                        "Deleted '{rtype}' record '{name}' with IP '{ip}', which is no longer desired"
+                    );
+                    OpResult::Deleted
+                }
+                Err(_) => {
+                    error!(
+                        "Failed to delete '{rtype}' record '{name}' with IP '{ip}', which is no longer desired"
+                    );
+                    OpResult::Failed
+                }
+            }
+        }
+    }
+}
+
+/// [`DnsProvider`] of the Cloudflare API
+#[derive(Debug, Clone)]
+pub struct CloudflareClient {
+    client: Client,
+    api: CfApi,
+}
+
+impl CloudflareClient {
+    /// Client of the Cloudflare API, authenticated with `auth`
+    pub fn new(auth: Auth) -> Self {
+        Self::with_api_url(auth, cloudflare::API_URL)
+    }
+
+    /// Client sending its requests to `api_url` instead of the Cloudflare API, e.g. to a gateway in front of it
+    pub fn with_api_url(auth: Auth, api_url: &str) -> Self {
+        Self {
+            client: Client::new(),
+            api: CfApi {
+                api_url: api_url.trim_end_matches('/').to_string(),
+                ..CfApi::new(auth)
+            },
+        }
+    }
+
+    /// URL of the DNS records of a zone
+    fn records_url(&self, zone_id: &str) -> String {
+        format!("{}/zones/{zone_id}/dns_records", self.api.api_url)
+    }
+
+    /// Send a request, with the same headers and retries as the blocking requests
+    ///
+    /// A `429 Too Many Requests` response is retried after the wait its `Retry-After` header asks for. A network failure
+    /// is only retried for requests which can't create a duplicate.
+    async fn send(&self, mut request: RequestBuilder) -> (reqwest::Result<Response>, RequestIds) {
+        let mut ids = RequestIds::new();
+        for (name, value) in cloudflare::cf_headers(&self.api, &ids) {
+            request = request.header(name, value);
+        }
+        let retryable = request
+            .try_clone()
+            .and_then(|request| request.build().ok())
+            .is_some_and(|request| cloudflare::retries_network_failure(request.method()));
+        let (network_retries, network_retry_wait) =
+            (self.api.network_retries, self.api.network_retry_wait);
+        let mut attempt = 0;
+        let mut network_attempt = 0;
+        let res = loop {
+            let retry = request.try_clone();
+            let res = request.send().await;
+            match (&res, retry) {
+                (Ok(r), Some(retry))
+                    if r.status() == StatusCode::TOO_MANY_REQUESTS
+                        && attempt < cloudflare::RATE_LIMIT_RETRIES =>
+                {
+                    attempt += 1;
+                    let now = jiff::Timestamp::try_from(SystemClock.wall_now()).unwrap_or_default();
+                    let wait = cloudflare::retry_after(r.headers(), now, self.api.retry_after_max);
+                    warn!(
+                        "Rate limited by the Cloudflare API, retrying in <{}> seconds ({attempt}/{}) ({ids})",
+                        wait.as_secs(),
+                        cloudflare::RATE_LIMIT_RETRIES
+                    );
+                    tokio::time::sleep(wait).await;
+                    request = retry;
+                }
+                (Err(e), Some(retry)) if retryable && network_attempt < network_retries => {
+                    network_attempt += 1;
+                    let wait = network_retry_wait * network_attempt;
+                    warn!(
+                        "Cloudflare API request failed: {e}, retrying in <{}> ms ({network_attempt}/\
+                        {network_retries}) ({ids})",
+                        wait.as_millis()
+                    );
+                    tokio::time::sleep(wait).await;
+                    request = retry;
+                }
+                _ => break res,
+            }
+        };
+
+        match &res {
+            Ok(r) => {
+                ids.received(r.headers());
+                debug!(
+                    "Cloudflare API '{}' returned '{}' ({ids})",
+                    r.url(),
+                    r.status()
+                );
+            }
+            Err(e) => debug!("Cloudflare API request failed: {e} ({ids})"),
+        }
+        (res, ids)
+    }
+
+    /// Parse the response to a request made to `action`, logging why it failed if it did
+    async fn json(
+        &self,
+        res: reqwest::Result<Response>,
+        ids: RequestIds,
+        action: &str,
+    ) -> Result<Value, ()> {
+        let res = res.map_err(|e| {
+            error!("Could not {action}: {e} ({ids})");
+        })?;
+        let status = res.status();
+        let body = res.text().await.map_err(|e| {
+            error!("Could not {action}: {e} (status '{status}', {ids})");
+        })?;
+        if !status.is_success() {
+            error!(
+                "Failed to {action}: {} (status '{status}', {ids})",
+                cloudflare::cf_body_error_message(body, &self.api)
+            );
+            return Err(());
+        }
+
+        let json = serde_json::from_str::<Value>(&body).map_err(|e| {
+            error!("Could not parse the response when trying to {action}: {e} (status '{status}', {ids})");
+        })?;
+        if let Some(errors) = cloudflare::cf_failure(&json, &self.api) {
+            error!("Failed to {action}: {errors} (status '{status}', {ids})");
+            return Err(());
+        }
+        Ok(json)
+    }
+}
+
+impl CreateTransport for CloudflareClient {
+    async fn post(&self, zone_id: &str, body: &Value) -> Sent {
+        let (res, ids) = self
+            .send(self.client.post(self.records_url(zone_id)).json(body))
+            .await;
+        match res {
+            Err(e) => Sent::Lost {
+                error: e.to_string(),
+                ids,
+            },
+            res => {
+                let action = format!("create DNS record in zone '{zone_id}'");
+                Sent::Response(self.json(res, ids, &action).await)
+            }
+        }
+    }
+
+    async fn find(&self, zone_id: &str, query: &[(&'static str, String)]) -> Result<Value, ()> {
+        let (res, ids) = self
+            .send(self.client.get(self.records_url(zone_id)).query(query))
+            .await;
+        let action = format!("look up DNS record in zone '{zone_id}'");
+        self.json(res, ids, &action).await
+    }
+}
+
+impl DnsProvider for CloudflareClient {
+    /// Get all DNS records of type `A` and `AAAA` in the given zone
+    ///
+    /// Any record which cannot be parsed will be ignored. Like the blocking listing, the whole listing fails if any page
+    /// can't be fetched.
+    async fn list_records(&self, zone_id: &str) -> Result<Vec<CfRecord>, ()> {
+        let url = self.records_url(zone_id);
+        let mut records = Vec::new();
+        let mut page = 1;
+        loop {
+            let query = [("page", page), ("per_page", cloudflare::RECORDS_PER_PAGE)];
+            let (res, ids) = self.send(self.client.get(&url).query(&query)).await;
+            let action = format!("get page <{page}> of the DNS records of zone '{zone_id}'");
+            let json = self.json(res, ids, &action).await?;
+
+            let Some(json_records) = json["result"].as_array() else {
+                error!(
+                    "Could not parse array of DNS records of zone '{zone_id}':\n{}",
+                    cloudflare::cf_redacted(&json, &self.api)
+                );
+                return Err(());
+            };
+            records.extend(
+                json_records
+                    .iter()
+                    .filter_map(|record| cloudflare::cf_parse_record(record).ok())
+                    .inspect(|record| cloudflare::check_zone(record, zone_id)),
+            );
+
+            // Without pagination info, the response is taken to hold every record
+            let total_pages = json["result_info"]["total_pages"]
+                .as_u64()
+                .map(|pages| pages as u32)
+                .unwrap_or(page);
+            if page >= total_pages {
+                return Ok(records);
+            }
+            page += 1;
+        }
+    }
+
+    async fn create_record(
+        &self,
+        zone_id: &str,
+        record: &Record,
+        comment: Option<&str>,
+    ) -> Result<String, ()> {
+        cloudflare::cf_create_with(self, record, comment, zone_id, &self.api).await
+    }
+
+    async fn update_record(
+        &self,
+        zone_id: &str,
+        current: &CfRecord,
+        record: &Record,
+        comment: &str,
+    ) -> Result<(), ()> {
+        let url = format!("{}/{}", self.records_url(zone_id), current.id);
+        let body = cloudflare::cf_update_body(
+            record,
+            comment,
+            &current.extra,
+            self.api.managed_tag.as_deref(),
+        );
+
+        let (res, ids) = self.send(self.client.patch(&url).json(&body)).await;
+        let action = format!("update record with id '{}'", current.id);
+        self.json(res, ids, &action).await.map(|_| ())
+    }
+
+    async fn delete_record(&self, zone_id: &str, record_id: &str) -> Result<(), ()> {
+        let url = format!("{}/{record_id}", self.records_url(zone_id));
+
+        let (res, ids) = self.send(self.client.delete(&url)).await;
+        let action = format!("delete record with id '{record_id}'");
+        self.json(res, ids, &action).await.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    const CUR_IP: &str = "203.0.113.7";
+    const OLD_IP: &str = "198.51.100.1";
+
+    /// Status and body of the mock server's answer to a request, by method and target. `None` closes the connection
+    /// without answering, as if the response was lost.
+    type Handler = fn(&str, &str) -> Option<(u16, Value)>;
+
+    fn run<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    /// Read the method and target of an HTTP request, and skip its body
+    async fn read_request(stream: &mut TcpStream) -> (String, String) {
+        let mut data = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            let read = stream.read(&mut buf).await.unwrap();
+            data.extend_from_slice(&buf[..read]);
+            let text = String::from_utf8_lossy(&data);
+            let Some(end) = text.find("\r\n\r\n") else {
+                assert_ne!(read, 0, "incomplete request");
+                continue;
+            };
+            let length = text[..end]
+                .lines()
+                .find_map(|line| {
+                    line.to_ascii_lowercase()
+                        .strip_prefix("content-length:")
+                        .map(|length| length.trim().parse::<usize>().unwrap())
+                })
+                .unwrap_or(0);
+            if data.len() >= end + 4 + length {
+                let mut request_line = text.lines().next().unwrap().split(' ');
+                let method = request_line.next().unwrap().to_string();
+                let target = request_line.next().unwrap().to_string();
+                return (method, target);
+            }
+        }
+    }
+
+    /// Answer the requests sent to the returned API URL with `handler`, and keep them in the returned list, as method
+    /// and target
+    async fn mock_server(handler: Handler) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/client/v4", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let (method, target) = read_request(&mut stream).await;
+                let answer = handler(&method, &target);
+                received.lock().unwrap().push(format!("{method} {target}"));
+                if let Some((status, body)) = answer {
+                    let body = body.to_string();
+                    let response = format!(
+                        "HTTP/1.1 {status} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+                        Connection: close\r\n\r\n{body}",
+                        body.len()
+                    );
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                }
+            }
+        });
+        (url, requests)
+    }
+
+    fn record_json(id: &str, name: &str, ip: &str, comment: Option<&str>) -> Value {
+        json!({
+            "id": id,
+            "type": "A",
+            "name": name,
+            "content": ip,
+            "ttl": 1,
+            "proxied": false,
+            "comment": comment,
+            "zone_id": "zone",
+        })
+    }
+
+    fn success(result: Value) -> Option<(u16, Value)> {
+        Some((
+            200,
+            json!({ "success": true, "errors": [], "result": result }),
+        ))
+    }
+
+    fn desired(name: &str) -> DesiredRecord {
+        DesiredRecord {
+            name: name.to_string(),
+            rtype: RecordType::A,
+            content: Content::Dynamic,
+            ttl: Ttl::Auto,
+            proxied: None,
+            depends_on: None,
+        }
+    }
+
+    fn client(url: &str) -> CloudflareClient {
+        CloudflareClient::with_api_url(Auth::Token("token".to_string()), url)
+    }
+
+    #[test]
+    fn reconciles_a_zone_through_the_api() {
+        let handler: Handler = |method, target| match method {
+            "GET" => success(json!([
+                record_json("1", "www.example.com", OLD_IP, None),
+                record_json("2", "old.example.com", OLD_IP, Some("[cf-dns-rs]")),
+                record_json("3", "manual.example.com", OLD_IP, Some("by hand")),
+                record_json("4", "same.example.com", CUR_IP, None),
+            ])),
+            "POST" => success(json!({ "id": "5" })),
+            "PATCH" | "DELETE" => success(json!({ "id": target.rsplit('/').next() })),
+            _ => None,
+        };
+
+        let (outcomes, requests) = run(async {
+            let (url, requests) = mock_server(handler).await;
+            let cur_ips = BTreeMap::from([(RecordType::A, CUR_IP.parse().unwrap())]);
+            let desired = [
+                desired("www.example.com"),
+                desired("same.example.com"),
+                desired("api.example.com"),
+            ];
+            let outcomes = reconcile(&client(&url), "zone", &desired, &cur_ips, false).await;
+            (outcomes, requests)
+        });
+
+        let results = outcomes
+            .unwrap()
+            .into_iter()
+            .map(|outcome| (outcome.name, outcome.result))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            [
+                ("www.example.com".to_string(), OpResult::Updated),
+                ("same.example.com".to_string(), OpResult::Unchanged),
+                ("api.example.com".to_string(), OpResult::Created),
+                ("old.example.com".to_string(), OpResult::Deleted),
+            ]
+        );
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [
+                "GET /client/v4/zones/zone/dns_records?page=1&per_page=100",
+                "PATCH /client/v4/zones/zone/dns_records/1",
+                "POST /client/v4/zones/zone/dns_records",
+                "DELETE /client/v4/zones/zone/dns_records/2",
+            ]
+        );
+    }
+
+    #[test]
+    fn fails_when_the_records_cannot_be_listed() {
+        let handler: Handler = |_, _| {
+            Some((
+                400,
+                json!({ "success": false, "errors": [{ "code": 7003, "message": "Could not route" }] }),
+            ))
+        };
+
+        let outcomes = run(async {
+            let (url, _) = mock_server(handler).await;
+            let cur_ips = BTreeMap::from([(RecordType::A, CUR_IP.parse().unwrap())]);
+            reconcile(
+                &client(&url),
+                "zone",
+                &[desired("www.example.com")],
+                &cur_ips,
+                false,
+            )
+            .await
+        });
+
+        assert!(outcomes.is_err());
+    }
+
+    #[test]
+    fn finds_a_created_record_whose_response_was_lost() {
+        let handler: Handler = |method, _| match method {
+            "GET" => success(json!([record_json("7", "www.example.com", CUR_IP, None)])),
+            _ => None,
+        };
+        let record = Record {
+            name: "www.example.com".to_string(),
+            ttl: Ttl::Auto,
+            content: CUR_IP.parse().unwrap(),
+            proxied: false,
+        };
+
+        let (id, requests) = run(async {
+            let (url, requests) = mock_server(handler).await;
+            let id = client(&url).create_record("zone", &record, None).await;
+            (id, requests)
+        });
+
+        assert_eq!(id, Ok("7".to_string()));
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [
+                "POST /client/v4/zones/zone/dns_records",
+                "GET /client/v4/zones/zone/dns_records?type=A&name=www.example.com&content=203.0.113.7",
+            ]
+        );
+    }
+}
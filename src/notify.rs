@@ -0,0 +1,240 @@
+//! Notifications sent to the operator.
+//!
+//! Notifications are POSTed as JSON (`{"text": "..."}`) to the webhook in `CF_DNS_WEBHOOK_URL`, or for the changes of a
+//! host to the host's own `webhook` if it has one. Failing to send a notification is only logged, and never stops the
+//! app.
+
+use crate::config::HostConfig;
+use log::{debug, warn};
+use reqwest::blocking::Client;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+/// Destination of notifications
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    url: String,
+    client: Client,
+}
+
+impl Webhook {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            client: Client::new(),
+        }
+    }
+
+    /// Send a notification with the given text
+    pub fn send(&self, text: &str) {
+        let result = self
+            .client
+            .post(&self.url)
+            .json(&json!({ "text": text }))
+            .send()
+            .and_then(|res| res.error_for_status());
+
+        match result {
+            Ok(_) => debug!("Sent notification: {text}"),
+            // The URL is left out since it often contains a secret
+            Err(e) => warn!("Could not send notification '{text}': {}", e.without_url()),
+        }
+    }
+}
+
+/// Send a notification to all `webhooks`
+pub fn send_all(webhooks: &[Webhook], text: &str) {
+    for webhook in webhooks {
+        webhook.send(text);
+    }
+}
+
+/// Start or stop of the app, notified when `CF_DNS_NOTIFY_LIFECYCLE` is set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lifecycle {
+    /// The app started, monitoring this many hosts
+    Started(usize),
+    ShuttingDown,
+}
+
+impl Display for Lifecycle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Lifecycle::Started(hosts) => {
+                write!(
+                    f,
+                    "{} started, monitoring {hosts} hosts",
+                    env!("CARGO_PKG_NAME")
+                )
+            }
+            Lifecycle::ShuttingDown => write!(f, "{} shutting down", env!("CARGO_PKG_NAME")),
+        }
+    }
+}
+
+/// Changes to the records of a single host during a cycle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeEvent {
+    pub host: String,
+    /// Outcome of each record type, e.g. `A updated, AAAA unchanged (ok)`
+    pub summary: String,
+}
+
+/// Webhook URL of the changes of `host`: the `webhook` of its config among `hosts` if it has one, or else the `global`
+/// one
+pub fn webhook_for<'a>(
+    host: &str,
+    mut hosts: impl Iterator<Item = &'a HostConfig>,
+    global: Option<&str>,
+) -> Option<String> {
+    hosts
+        .find(|h| h.name == host)
+        .and_then(|h| h.webhook.as_deref())
+        .or(global)
+        .map(|url| url.to_string())
+}
+
+/// Send each of the `events` to the webhook URL which `route` gives for its host, or drop it if there is none
+///
+/// The events of each webhook are batched into a single notification.
+pub fn send_changes(events: &[ChangeEvent], route: impl Fn(&str) -> Option<String>) {
+    let mut batches: BTreeMap<String, Vec<&ChangeEvent>> = BTreeMap::new();
+    for event in events {
+        if let Some(url) = route(&event.host) {
+            batches.entry(url).or_default().push(event);
+        }
+    }
+
+    for (url, events) in batches {
+        let text = events
+            .iter()
+            .map(|event| format!("{}: {}", event.host, event.summary))
+            .collect::<Vec<_>>()
+            .join("\n");
+        Webhook::new(&url).send(&format!("DNS records changed\n{text}"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// Webhook which answers every notification with `status`, and keeps their texts in the returned list
+    fn fake_webhook(status: u16) -> (Webhook, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let webhook = Webhook::new(&format!("http://{}/hook", listener.local_addr().unwrap()));
+        let texts = Arc::new(Mutex::new(Vec::new()));
+        let received = texts.clone();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().map_while(Result::ok) {
+                let mut data = Vec::new();
+                let mut buf = [0; 4096];
+                // The body is the last JSON object of the request
+                while !data.ends_with(b"}") {
+                    let read = stream.read(&mut buf).unwrap();
+                    assert_ne!(read, 0, "incomplete request");
+                    data.extend_from_slice(&buf[..read]);
+                }
+                let text = String::from_utf8_lossy(&data);
+                let (_, body) = text.split_once("\r\n\r\n").unwrap();
+                let body: serde_json::Value = serde_json::from_str(body).unwrap();
+                received
+                    .lock()
+                    .unwrap()
+                    .push(body["text"].as_str().unwrap().to_string());
+                let response = format!(
+                    "HTTP/1.1 {status} Fake\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (webhook, texts)
+    }
+
+    #[test]
+    fn sends_the_start_and_stop_events_to_every_webhook() {
+        let (first, first_texts) = fake_webhook(200);
+        // A failing webhook is only logged
+        let (second, second_texts) = fake_webhook(500);
+        let webhooks = [first, second];
+
+        send_all(&webhooks, &Lifecycle::Started(3).to_string());
+        send_all(&webhooks, &Lifecycle::ShuttingDown.to_string());
+
+        let expected = [
+            "cf-dns-rs started, monitoring 3 hosts",
+            "cf-dns-rs shutting down",
+        ];
+        assert_eq!(first_texts.lock().unwrap()[..], expected);
+        assert_eq!(second_texts.lock().unwrap()[..], expected);
+    }
+
+    fn host(name: &str, webhook: Option<&str>) -> HostConfig {
+        HostConfig {
+            name: name.to_string(),
+            proxied: None,
+            webhook: webhook.map(|url| url.to_string()),
+            enabled: true,
+            manage: crate::config::Manage::Full,
+        }
+    }
+
+    #[test]
+    fn routes_the_changes_of_a_host_to_its_own_webhook_or_the_global_one() {
+        let hosts = [
+            host("www.example.com", Some("https://hooks.example/personal")),
+            host("blog.example.com", None),
+        ];
+        let route = |host, global| webhook_for(host, hosts.iter(), global);
+
+        let own = Some("https://hooks.example/personal".to_string());
+        assert_eq!(
+            route("www.example.com", Some("https://hooks.example/all")),
+            own
+        );
+        assert_eq!(route("www.example.com", None), own);
+        assert_eq!(
+            route("blog.example.com", Some("https://hooks.example/all")),
+            Some("https://hooks.example/all".to_string())
+        );
+        assert_eq!(route("blog.example.com", None), None);
+    }
+
+    #[test]
+    fn batches_the_changes_of_each_webhook() {
+        let (personal, personal_texts) = fake_webhook(200);
+        let (global, global_texts) = fake_webhook(200);
+        let hosts = [
+            host("www.example.com", Some(&personal.url)),
+            host("blog.example.com", None),
+            host("shop.example.com", None),
+        ];
+        let event = |host: &str| ChangeEvent {
+            host: host.to_string(),
+            summary: "A updated".to_string(),
+        };
+
+        send_changes(
+            &[
+                event("www.example.com"),
+                event("blog.example.com"),
+                event("shop.example.com"),
+            ],
+            |host| webhook_for(host, hosts.iter(), Some(&global.url)),
+        );
+
+        assert_eq!(
+            personal_texts.lock().unwrap()[..],
+            ["DNS records changed\nwww.example.com: A updated"]
+        );
+        assert_eq!(
+            global_texts.lock().unwrap()[..],
+            ["DNS records changed\nblog.example.com: A updated\nshop.example.com: A updated"]
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! Propagation check of changed records.
+//!
+//! When `CF_DNS_PROPAGATION_RESOLVERS` is set, every record which is created or updated is looked up on each of those
+//! public resolvers from a background thread, once the grace period has passed. Resolvers keep serving the previous
+//! answer until it expires from their cache, so the check waits for at least the TTL of the previous record. A warning
+//! is logged if fewer than the quorum of resolvers answer the new IP. Proxied records are not checked, since
+//! resolvers answer Cloudflare's addresses for them.
+
+use crate::clock::{Clock, SystemClock};
+use crate::dns;
+use crate::shutdown;
+use crate::{Record, Ttl};
+use log::{debug, info, warn};
+use std::thread;
+use std::time::Duration;
+
+/// TTL of records with an automatic TTL
+const AUTO_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PropagationCheck {
+    /// Resolvers which are queried, as IPs or host names with an optional port
+    pub resolvers: Vec<String>,
+    /// Number of resolvers which must answer the new IP
+    pub quorum: usize,
+    /// Minimum time to wait after the change before querying the resolvers
+    pub grace: Duration,
+}
+
+impl PropagationCheck {
+    /// Check from a background thread that `record`, which replaced a record with the TTL `previous_ttl` (`None` if
+    /// it was created), is visible from the resolvers
+    pub fn spawn(&self, record: &Record, previous_ttl: Option<Ttl>) {
+        if record.proxied {
+            debug!(
+                "Not checking the propagation of proxied '{}' record '{}'",
+                record.rtype(),
+                record.name
+            );
+            return;
+        }
+
+        let wait = self.wait(previous_ttl);
+        let check = self.clone();
+        let record = record.clone();
+        thread::spawn(move || {
+            SystemClock.sleep(wait);
+            if !shutdown::requested() {
+                check.run(&record);
+            }
+        });
+    }
+
+    /// Time to wait before checking a record which replaced one with the TTL `previous_ttl`, for the resolvers to
+    /// forget the previous record
+    fn wait(&self, previous_ttl: Option<Ttl>) -> Duration {
+        match previous_ttl {
+            Some(Ttl::Seconds(seconds)) => self.grace.max(Duration::from_secs(seconds.into())),
+            Some(Ttl::Auto) => self.grace.max(AUTO_TTL),
+            None => self.grace,
+        }
+    }
+
+    /// Query every resolver for `record`, report how many of them answer its IP, and return whether they are a quorum
+    fn run(&self, record: &Record) -> bool {
+        let rtype = record.rtype();
+        let answers = self
+            .resolvers
+            .iter()
+            .map(|resolver| (resolver, dns::query(&rtype, &record.name, resolver)))
+            .collect::<Vec<_>>();
+        let seen = answers
+            .iter()
+            .filter(|(_, answer)| answer.as_ref() == Ok(&record.content))
+            .count();
+
+        if seen >= self.quorum {
+            info!(
+                "'{rtype}' record '{}' with IP '{}' is visible from <{seen}> of <{}> resolvers",
+                record.name,
+                record.content,
+                self.resolvers.len()
+            );
+            return true;
+        }
+
+        let details = answers
+            .iter()
+            .map(|(resolver, answer)| match answer {
+                Ok(ip) => format!("'{resolver}' answered '{ip}'"),
+                Err(e) => format!("'{resolver}' failed: {e}"),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        warn!(
+            "'{rtype}' record '{}' with IP '{}' is only visible from <{seen}> of <{}> resolvers, less than the quorum of \
+            <{}>: {details}",
+            record.name,
+            record.content,
+            self.resolvers.len(),
+            self.quorum
+        );
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::stub_resolver;
+
+    fn check(resolvers: Vec<String>, quorum: usize) -> PropagationCheck {
+        PropagationCheck {
+            resolvers,
+            quorum,
+            grace: Duration::from_secs(60),
+        }
+    }
+
+    fn record() -> Record {
+        Record {
+            name: "www.example.com".to_string(),
+            ttl: Ttl::Auto,
+            content: "203.0.113.7".parse().unwrap(),
+            proxied: false,
+        }
+    }
+
+    #[test]
+    fn counts_the_resolvers_which_answer_the_new_ip() {
+        let resolvers = vec![
+            stub_resolver("203.0.113.7".parse().unwrap()),
+            stub_resolver("203.0.113.7".parse().unwrap()),
+            // Still caching the previous record
+            stub_resolver("198.51.100.1".parse().unwrap()),
+            // No answer of the record's type
+            stub_resolver("2001:db8::7".parse().unwrap()),
+        ];
+
+        assert!(check(resolvers.clone(), 2).run(&record()));
+        assert!(!check(resolvers, 3).run(&record()));
+    }
+
+    #[test]
+    fn waits_for_the_previous_record_to_expire() {
+        let check = check(Vec::new(), 1);
+
+        assert_eq!(check.wait(None), Duration::from_secs(60));
+        assert_eq!(check.wait(Some(Ttl::Seconds(30))), Duration::from_secs(60));
+        assert_eq!(
+            check.wait(Some(Ttl::Seconds(3600))),
+            Duration::from_secs(3600)
+        );
+        assert_eq!(check.wait(Some(Ttl::Auto)), AUTO_TTL);
+    }
+}
@@ -0,0 +1,241 @@
+//! DNS provider abstraction.
+//!
+//! The reconcile logic in [`crate::zone`] only changes records through a [`DnsProvider`], so that it does not depend on
+//! the Cloudflare API directly. [`crate::cloudflare::CloudflareProvider`] is the only implementation, besides the
+//! in-memory `FakeProvider` of the tests.
+
+use crate::{CfRecord, Record};
+use std::fmt::Debug;
+use std::net::IpAddr;
+
+/// Operations on the DNS records of a zone
+pub trait DnsProvider: Debug {
+    /// Get all `A` and `AAAA` records of the zone
+    fn list_records(&self, zone_id: &str) -> Result<Vec<CfRecord>, ()>;
+
+    /// Get the `A` and `AAAA` records of the zone which have the given tag
+    fn list_tagged_records(&self, zone_id: &str, tag: &str) -> Result<Vec<CfRecord>, ()>;
+
+    /// Get a single record of the zone
+    fn get_record(&self, zone_id: &str, record_id: &str) -> Result<CfRecord, ()>;
+
+    /// Create a record, with an optional comment. Returns the id of the created record.
+    fn create_record(
+        &self,
+        zone_id: &str,
+        record: &Record,
+        comment: Option<&str>,
+    ) -> Result<String, ()>;
+
+    /// Set the IP of the `current` record. Its comment is only changed if a `comment` is given.
+    fn update_record_ip(
+        &self,
+        zone_id: &str,
+        current: &CfRecord,
+        ip: &IpAddr,
+        comment: Option<&str>,
+    ) -> Result<(), ()>;
+
+    /// Set the name, content, TTL, proxied status and comment of the `current` record
+    fn update_record(
+        &self,
+        zone_id: &str,
+        current: &CfRecord,
+        record: &Record,
+        comment: &str,
+    ) -> Result<(), ()>;
+
+    /// Delete a record
+    fn delete_record(&self, zone_id: &str, record_id: &str) -> Result<(), ()>;
+
+    /// Get the status of the API token, e.g. `active`, `disabled` or `expired`
+    fn verify_token(&self) -> Result<String, ()>;
+}
+
+/// In-memory [`DnsProvider`] for tests, which records every write
+///
+/// Clones share the same records, so that a test can look at them after handing a clone to a
+/// [`crate::zone::ZoneClient`].
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
+pub struct FakeProvider {
+    records: std::rc::Rc<std::cell::RefCell<Vec<CfRecord>>>,
+    writes: std::rc::Rc<std::cell::RefCell<Vec<String>>>,
+    /// Whether every write fails
+    failing: std::rc::Rc<std::cell::Cell<bool>>,
+    /// Whether listing the records fails
+    unreachable: std::rc::Rc<std::cell::Cell<bool>>,
+    /// Whether getting a single record fails
+    unreadable: std::rc::Rc<std::cell::Cell<bool>>,
+    /// Status of the API token, `active` unless set
+    token_status: std::rc::Rc<std::cell::RefCell<Option<String>>>,
+}
+
+#[cfg(test)]
+impl FakeProvider {
+    pub fn new(records: Vec<CfRecord>) -> Self {
+        let provider = Self::default();
+        *provider.records.borrow_mut() = records;
+        provider
+    }
+
+    /// Make every following write fail
+    pub fn fail_writes(&self) {
+        self.failing.set(true);
+    }
+
+    /// Make every following listing of the records fail
+    pub fn fail_listing(&self) {
+        self.unreachable.set(true);
+    }
+
+    /// Make every following get of a single record fail
+    pub fn fail_reads(&self) {
+        self.unreadable.set(true);
+    }
+
+    /// Give the API token the `status`, e.g. `expired`
+    pub fn set_token_status(&self, status: &str) {
+        *self.token_status.borrow_mut() = Some(status.to_string());
+    }
+
+    /// Current records of the zone
+    pub fn records(&self) -> Vec<CfRecord> {
+        self.records.borrow().clone()
+    }
+
+    /// Writes made so far, e.g. `update www.example.com 203.0.113.7`
+    pub fn writes(&self) -> Vec<String> {
+        self.writes.borrow().clone()
+    }
+
+    fn write(&self, write: String) -> Result<(), ()> {
+        self.writes.borrow_mut().push(write);
+        match self.failing.get() {
+            true => Err(()),
+            false => Ok(()),
+        }
+    }
+
+    fn set(&self, record_id: &str, record: &Record, comment: Option<&str>) -> Result<(), ()> {
+        let mut records = self.records.borrow_mut();
+        let current = records.iter_mut().find(|r| r.id == record_id).ok_or(())?;
+        current.record = record.clone();
+        if let Some(comment) = comment {
+            current.comment = Some(comment.to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl DnsProvider for FakeProvider {
+    fn list_records(&self, _zone_id: &str) -> Result<Vec<CfRecord>, ()> {
+        match self.unreachable.get() {
+            true => Err(()),
+            false => Ok(self.records()),
+        }
+    }
+
+    fn list_tagged_records(&self, zone_id: &str, tag: &str) -> Result<Vec<CfRecord>, ()> {
+        let mut records = self.list_records(zone_id)?;
+        records.retain(|r| {
+            r.extra
+                .get("tags")
+                .and_then(|tags| tags.as_array())
+                .is_some_and(|tags| tags.iter().any(|t| t.as_str() == Some(tag)))
+        });
+        Ok(records)
+    }
+
+    fn get_record(&self, _zone_id: &str, record_id: &str) -> Result<CfRecord, ()> {
+        if self.unreadable.get() {
+            return Err(());
+        }
+        self.records()
+            .into_iter()
+            .find(|r| r.id == record_id)
+            .ok_or(())
+    }
+
+    fn create_record(
+        &self,
+        _zone_id: &str,
+        record: &Record,
+        comment: Option<&str>,
+    ) -> Result<String, ()> {
+        self.write(format!("create {} {}", record.name, record.content))?;
+        let id = format!("created-{}", self.records.borrow().len() + 1);
+        self.records
+            .borrow_mut()
+            .push(fake_record(&id, &record.name, record.content, comment));
+        Ok(id)
+    }
+
+    fn update_record_ip(
+        &self,
+        _zone_id: &str,
+        current: &CfRecord,
+        ip: &IpAddr,
+        comment: Option<&str>,
+    ) -> Result<(), ()> {
+        self.write(format!("update {} {ip}", current.record.name))?;
+        let record = Record {
+            content: *ip,
+            ..current.record.clone()
+        };
+        self.set(&current.id, &record, comment)
+    }
+
+    fn update_record(
+        &self,
+        _zone_id: &str,
+        current: &CfRecord,
+        record: &Record,
+        comment: &str,
+    ) -> Result<(), ()> {
+        self.write(format!("update {} {}", current.record.name, record.content))?;
+        self.set(&current.id, record, Some(comment))
+    }
+
+    fn delete_record(&self, _zone_id: &str, record_id: &str) -> Result<(), ()> {
+        let name = self
+            .records()
+            .into_iter()
+            .find(|r| r.id == record_id)
+            .map(|r| r.record.name)
+            .ok_or(())?;
+        self.write(format!("delete {name} {record_id}"))?;
+        self.records.borrow_mut().retain(|r| r.id != record_id);
+        Ok(())
+    }
+
+    fn verify_token(&self) -> Result<String, ()> {
+        Ok(self
+            .token_status
+            .borrow()
+            .clone()
+            .unwrap_or_else(|| "active".to_string()))
+    }
+}
+
+/// Record as listed by a provider, with the automatic TTL and not proxied
+#[cfg(test)]
+pub fn fake_record(id: &str, name: &str, ip: IpAddr, comment: Option<&str>) -> CfRecord {
+    CfRecord {
+        id: id.to_string(),
+        record: Record {
+            name: name.to_string(),
+            ttl: crate::Ttl::Auto,
+            content: ip,
+            proxied: false,
+        },
+        locked: false,
+        auto_added: false,
+        comment: comment.map(|comment| comment.to_string()),
+        modified_on: None,
+        zone_id: None,
+        zone_name: None,
+        extra: Default::default(),
+    }
+}
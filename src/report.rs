@@ -0,0 +1,603 @@
+//! Outcome of a single reconcile cycle, aggregated per host.
+//!
+//! When `CF_DNS_REPORT_FILE` is set, the report of the last cycle is also written to that file as JSON, for scripts and
+//! dashboards. The file is replaced atomically, so readers never see a partial report.
+
+use crate::RecordType;
+use crate::notify::ChangeEvent;
+use crate::status;
+use crate::timing::Phase;
+use log::{Level, info, warn};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs;
+use std::net::IpAddr;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Result of reconciling a single DNS record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpResult {
+    /// The record already had the current IP
+    Unchanged,
+    /// The record was updated to the current IP
+    Updated,
+    /// The record did not exist and was created
+    Created,
+    /// The record does not exist, and creating records is not allowed
+    Missing,
+    /// The record is no longer in the desired state, and was deleted
+    Deleted,
+    /// The record is locked by Cloudflare, and was left unchanged
+    Skipped,
+    /// The record was added automatically by Cloudflare, and was left unchanged
+    SkippedAutoAdded,
+    /// The record must be changed, but no write is left for this cycle
+    Deferred,
+    /// The record could not be updated or created
+    Failed,
+}
+
+impl OpResult {
+    pub fn is_failure(&self) -> bool {
+        matches!(self, Self::Missing | Self::Failed)
+    }
+}
+
+impl Display for OpResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OpResult::Unchanged => write!(f, "unchanged"),
+            OpResult::Updated => write!(f, "updated"),
+            OpResult::Created => write!(f, "created"),
+            OpResult::Missing => write!(f, "missing"),
+            OpResult::Deleted => write!(f, "deleted"),
+            OpResult::Skipped => write!(f, "skipped (locked)"),
+            OpResult::SkippedAutoAdded => write!(f, "skipped (auto-added)"),
+            OpResult::Deferred => write!(f, "deferred"),
+            OpResult::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// Results of reconciling the `A` and `AAAA` records of a single host.
+///
+/// A record type is `None` when it was not reconciled during the cycle (e.g. no IP of that type is monitored, or the
+/// current IP of that type could not be determined).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostOutcome {
+    pub a: Option<OpResult>,
+    pub aaaa: Option<OpResult>,
+}
+
+/// Overall status of a host, derived from its [`HostOutcome`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostStatus {
+    /// Every reconciled record is up to date
+    Ok,
+    /// Some records are up to date, while others failed
+    Partial,
+    /// Every reconciled record failed
+    Failed,
+}
+
+impl Display for HostStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HostStatus::Ok => write!(f, "ok"),
+            HostStatus::Partial => write!(f, "partially updated"),
+            HostStatus::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+impl HostOutcome {
+    pub fn set(&mut self, rtype: RecordType, result: OpResult) {
+        match rtype {
+            RecordType::A => self.a = Some(result),
+            RecordType::AAAA => self.aaaa = Some(result),
+        }
+    }
+
+    fn results(&self) -> impl Iterator<Item = (RecordType, OpResult)> {
+        [(RecordType::A, self.a), (RecordType::AAAA, self.aaaa)]
+            .into_iter()
+            .filter_map(|(rtype, result)| result.map(|r| (rtype, r)))
+    }
+
+    pub fn status(&self) -> HostStatus {
+        let failed = self.results().filter(|(_, r)| r.is_failure()).count();
+
+        if failed == 0 {
+            HostStatus::Ok
+        } else if failed == self.results().count() {
+            HostStatus::Failed
+        } else {
+            HostStatus::Partial
+        }
+    }
+
+    /// Whether any record of the host was updated, created or deleted
+    fn is_changed(&self) -> bool {
+        self.results()
+            .any(|(_, r)| matches!(r, OpResult::Updated | OpResult::Created | OpResult::Deleted))
+    }
+
+    /// Whether any record of the host was changed or failed
+    fn is_eventful(&self) -> bool {
+        self.results().any(|(_, r)| r != OpResult::Unchanged)
+    }
+}
+
+impl Display for HostOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let results = self
+            .results()
+            .map(|(rtype, r)| format!("{rtype} {r}"))
+            .collect::<Vec<_>>();
+
+        write!(f, "{} ({})", results.join(", "), self.status())
+    }
+}
+
+/// Outcome of a reconcile cycle for all hosts
+#[derive(Debug, Clone, Default)]
+pub struct ReconcileReport {
+    pub hosts: BTreeMap<String, HostOutcome>,
+    /// Duration of the cycle
+    pub duration: Duration,
+    /// Total duration of the operations of each phase of the cycle
+    pub breakdown: BTreeMap<Phase, Duration>,
+}
+
+impl ReconcileReport {
+    pub fn record(&mut self, host: &str, rtype: RecordType, result: OpResult) {
+        self.hosts
+            .entry(host.to_string())
+            .or_default()
+            .set(rtype, result);
+    }
+
+    /// Number of records with the given result
+    pub fn count(&self, result: OpResult) -> usize {
+        self.hosts
+            .values()
+            .flat_map(|outcome| outcome.results())
+            .filter(|(_, r)| *r == result)
+            .count()
+    }
+
+    /// Names and types of the records which were created
+    pub fn created(&self) -> Vec<(&str, RecordType)> {
+        self.hosts
+            .iter()
+            .flat_map(|(host, outcome)| {
+                outcome
+                    .results()
+                    .filter(|(_, r)| *r == OpResult::Created)
+                    .map(|(rtype, _)| (host.as_str(), rtype))
+            })
+            .collect()
+    }
+
+    /// Whether any record of any host failed
+    pub fn has_failures(&self) -> bool {
+        self.hosts
+            .values()
+            .any(|outcome| outcome.status() != HostStatus::Ok)
+    }
+
+    /// Whether at least one host was reconciled, and every reconciled host failed
+    pub fn all_failed(&self) -> bool {
+        !self.hosts.is_empty()
+            && self
+                .hosts
+                .values()
+                .all(|outcome| outcome.status() == HostStatus::Failed)
+    }
+
+    /// Log the status of every host which had changes or failures, followed by a summary line.
+    ///
+    /// When `verbose`, the status of every host and the summary are logged even if nothing changed.
+    pub fn log(&self, verbose: bool) {
+        for (level, line) in self.log_lines(verbose) {
+            log::log!(level, "{line}");
+        }
+    }
+
+    /// Lines logged by [`ReconcileReport::log()`], with their level
+    fn log_lines(&self, verbose: bool) -> Vec<(Level, String)> {
+        let mut lines = self
+            .hosts
+            .iter()
+            .filter(|(_, o)| verbose || o.is_eventful())
+            .map(|(host, outcome)| {
+                let level = match outcome.status() {
+                    HostStatus::Ok => Level::Info,
+                    HostStatus::Partial => Level::Warn,
+                    HostStatus::Failed => Level::Error,
+                };
+                (level, format!("Host '{host}': {outcome}"))
+            })
+            .collect::<Vec<_>>();
+
+        if verbose || self.hosts.values().any(|o| o.is_eventful()) {
+            lines.push((Level::Info, self.to_string()));
+        }
+        lines
+    }
+
+    /// Changes of every host whose records were updated, created or deleted
+    pub fn change_events(&self) -> Vec<ChangeEvent> {
+        self.hosts
+            .iter()
+            .filter(|(_, o)| o.is_changed())
+            .map(|(host, outcome)| ChangeEvent {
+                host: host.clone(),
+                summary: outcome.to_string(),
+            })
+            .collect()
+    }
+
+    /// JSON form of the report of the cycle which finished at `timestamp`, with the last error of every failed record
+    pub fn to_json(&self, timestamp: SystemTime) -> Value {
+        let last_errors = status::last_errors();
+        let hosts = self
+            .hosts
+            .iter()
+            .map(|(host, outcome)| {
+                let records = outcome
+                    .results()
+                    .map(|(rtype, result)| {
+                        let error = last_errors
+                            .get(&(host.clone(), rtype))
+                            .filter(|_| result.is_failure())
+                            .map(|last_error| last_error.message.clone());
+                        json!({
+                            "type": rtype.to_string(),
+                            "result": result.to_string(),
+                            "error": error,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                json!({
+                    "host": host,
+                    "status": outcome.status().to_string(),
+                    "changed": outcome.is_changed(),
+                    "records": records,
+                })
+            })
+            .collect::<Vec<_>>();
+        let breakdown = self
+            .breakdown
+            .iter()
+            .map(|(phase, duration)| (phase.to_string(), json!(duration.as_millis() as u64)))
+            .collect::<serde_json::Map<_, _>>();
+
+        json!({
+            "timestamp": jiff::Timestamp::try_from(timestamp)
+                .map(|timestamp| timestamp.to_string())
+                .unwrap_or_default(),
+            "duration_ms": self.duration.as_millis() as u64,
+            "breakdown_ms": breakdown,
+            "summary": {
+                "hosts": self.hosts.len(),
+                "updated": self.count(OpResult::Updated),
+                "created": self.count(OpResult::Created),
+                "deleted": self.count(OpResult::Deleted),
+                "unchanged": self.count(OpResult::Unchanged),
+                "missing": self.count(OpResult::Missing),
+                "skipped": self.count(OpResult::Skipped),
+                "skipped_auto_added": self.count(OpResult::SkippedAutoAdded),
+                "deferred": self.count(OpResult::Deferred),
+                "failed": self.count(OpResult::Failed),
+            },
+            "hosts": hosts,
+        })
+    }
+
+    /// Replace the file at `path` with the JSON form of the report, by writing a temporary file next to it and renaming
+    /// it over the previous report
+    pub fn write_file(&self, path: &str, timestamp: SystemTime) {
+        let json = self.to_json(timestamp);
+        let tmp = format!("{path}.tmp");
+        let written = serde_json::to_vec_pretty(&json)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| fs::write(&tmp, bytes).map_err(|e| e.to_string()))
+            .and_then(|_| fs::rename(&tmp, path).map_err(|e| e.to_string()));
+        if let Err(e) = written {
+            warn!("Could not write the reconcile report to '{path}': {e}");
+            let _ = fs::remove_file(&tmp);
+        }
+    }
+
+    /// Print the hosts which had changes, followed by the summary, to stdout. Nothing is printed if nothing changed.
+    ///
+    /// Used instead of logging when only errors are logged, so that e.g. cron only sends mail on real changes.
+    pub fn print_changes(&self) {
+        for line in self.change_lines() {
+            println!("{line}");
+        }
+    }
+
+    /// Lines printed by [`ReconcileReport::print_changes()`]
+    fn change_lines(&self) -> Vec<String> {
+        let mut lines = self
+            .hosts
+            .iter()
+            .filter(|(_, o)| o.is_changed())
+            .map(|(host, outcome)| format!("Host '{host}': {outcome}"))
+            .collect::<Vec<_>>();
+        if !lines.is_empty() {
+            lines.push(self.to_string());
+        }
+        lines
+    }
+}
+
+impl Display for ReconcileReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Reconciled <{}> hosts: {} updated, {} created, {} deleted, {} unchanged, {} missing, {} skipped (locked), {} skipped (auto-added), {} deferred, {} failed",
+            self.hosts.len(),
+            self.count(OpResult::Updated),
+            self.count(OpResult::Created),
+            self.count(OpResult::Deleted),
+            self.count(OpResult::Unchanged),
+            self.count(OpResult::Missing),
+            self.count(OpResult::Skipped),
+            self.count(OpResult::SkippedAutoAdded),
+            self.count(OpResult::Deferred),
+            self.count(OpResult::Failed),
+        )?;
+
+        write!(f, " in {}ms", self.duration.as_millis())?;
+        if !self.breakdown.is_empty() {
+            let breakdown = self
+                .breakdown
+                .iter()
+                .map(|(phase, duration)| format!("{phase} {}ms", duration.as_millis()))
+                .collect::<Vec<_>>();
+            write!(f, " ({})", breakdown.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Activity accumulated over all the cycles of a run, logged when the app exits
+#[derive(Debug, Clone)]
+pub struct RunSummary {
+    started: Instant,
+    cycles: u64,
+    failed_cycles: u64,
+    updated: usize,
+    created: usize,
+    deleted: usize,
+    failed: usize,
+    /// Last detected IP of each type
+    ips: BTreeMap<RecordType, IpAddr>,
+}
+
+impl RunSummary {
+    pub fn new(started: Instant) -> Self {
+        Self {
+            started,
+            cycles: 0,
+            failed_cycles: 0,
+            updated: 0,
+            created: 0,
+            deleted: 0,
+            failed: 0,
+            ips: BTreeMap::new(),
+        }
+    }
+
+    /// Add a finished cycle, with the IPs detected during it and its report if any records were reconciled
+    pub fn add_cycle(
+        &mut self,
+        ips: &BTreeMap<RecordType, IpAddr>,
+        report: Option<&ReconcileReport>,
+        failed: bool,
+    ) {
+        self.cycles += 1;
+        if failed {
+            self.failed_cycles += 1;
+        }
+        self.ips.extend(ips);
+        if let Some(report) = report {
+            self.updated += report.count(OpResult::Updated);
+            self.created += report.count(OpResult::Created);
+            self.deleted += report.count(OpResult::Deleted);
+            self.failed += report.count(OpResult::Failed) + report.count(OpResult::Missing);
+        }
+    }
+
+    /// Log the summary of the run, which ends at `now`
+    pub fn log(&self, now: Instant) {
+        let uptime = jiff::SignedDuration::from_secs(
+            now.saturating_duration_since(self.started).as_secs() as i64,
+        );
+        let ips = self
+            .ips
+            .iter()
+            .map(|(rtype, ip)| format!("'{rtype}' '{ip}'"))
+            .collect::<Vec<_>>();
+
+        info!("Run summary:");
+        info!("\tuptime: {uptime:#}");
+        info!("\tcycles: {} ({} failed)", self.cycles, self.failed_cycles);
+        info!(
+            "\trecords: {} updated, {} created, {} deleted, {} failed",
+            self.updated, self.created, self.deleted, self.failed
+        );
+        if ips.is_empty() {
+            info!("\tfinal IPs: none");
+        } else {
+            info!("\tfinal IPs: {}", ips.join(", "));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use uuid::Uuid;
+
+    fn report(results: &[(&str, RecordType, OpResult)]) -> ReconcileReport {
+        let mut report = ReconcileReport::default();
+        for (host, rtype, result) in results {
+            report.record(host, *rtype, *result);
+        }
+        report
+    }
+
+    #[test]
+    fn aggregates_the_results_of_a_half_updated_host() {
+        let report = report(&[
+            ("www.example.com", RecordType::A, OpResult::Failed),
+            ("www.example.com", RecordType::AAAA, OpResult::Created),
+            ("api.example.com", RecordType::A, OpResult::Unchanged),
+        ]);
+
+        let outcome = &report.hosts["www.example.com"];
+        assert_eq!(outcome.a, Some(OpResult::Failed));
+        assert_eq!(outcome.aaaa, Some(OpResult::Created));
+        assert_eq!(outcome.status(), HostStatus::Partial);
+        assert_eq!(
+            outcome.to_string(),
+            "A failed, AAAA created (partially updated)"
+        );
+        assert_eq!(report.hosts["api.example.com"].status(), HostStatus::Ok);
+        assert!(report.has_failures());
+        assert!(!report.all_failed());
+    }
+
+    #[test]
+    fn fails_a_host_whose_every_record_failed() {
+        let report = report(&[
+            ("www.example.com", RecordType::A, OpResult::Failed),
+            ("www.example.com", RecordType::AAAA, OpResult::Missing),
+        ]);
+
+        assert_eq!(report.hosts["www.example.com"].status(), HostStatus::Failed);
+        assert!(report.all_failed());
+        assert!(!ReconcileReport::default().all_failed());
+    }
+
+    #[test]
+    fn logs_every_host_only_when_verbose() {
+        let unchanged = report(&[("www.example.com", RecordType::A, OpResult::Unchanged)]);
+        let lines = unchanged.log_lines(true);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            (
+                Level::Info,
+                "Host 'www.example.com': A unchanged (ok)".to_string()
+            )
+        );
+        assert!(unchanged.log_lines(false).is_empty());
+
+        let changed = report(&[
+            ("www.example.com", RecordType::A, OpResult::Updated),
+            ("api.example.com", RecordType::A, OpResult::Unchanged),
+        ]);
+        let lines = changed.log_lines(false);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].1, "Host 'www.example.com': A updated (ok)");
+        assert!(lines[1].1.starts_with("Reconciled <2> hosts: 1 updated"));
+    }
+
+    #[test]
+    fn prints_nothing_when_nothing_changed() {
+        let unchanged = report(&[
+            ("www.example.com", RecordType::A, OpResult::Unchanged),
+            ("api.example.com", RecordType::A, OpResult::Failed),
+        ]);
+        assert!(unchanged.change_lines().is_empty());
+
+        let changed = report(&[
+            ("www.example.com", RecordType::A, OpResult::Created),
+            ("api.example.com", RecordType::A, OpResult::Unchanged),
+        ]);
+        let lines = changed.change_lines();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], "Host 'www.example.com': A created (ok)");
+        assert_eq!(lines[1], changed.to_string());
+    }
+
+    #[test]
+    fn replaces_the_report_file_with_the_json_report() {
+        let report = report(&[
+            ("www.example.com", RecordType::A, OpResult::Updated),
+            ("www.example.com", RecordType::AAAA, OpResult::Unchanged),
+        ]);
+        let dir = env::temp_dir().join(format!("cf-dns-rs-report-{}", Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("report.json");
+        fs::write(&path, "previous report").unwrap();
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        report.write_file(path.to_str().unwrap(), timestamp);
+
+        let written: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        let files = fs::read_dir(&dir).unwrap().count();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(written, report.to_json(timestamp));
+        assert_eq!(written["timestamp"], "2023-11-14T22:13:20Z");
+        assert_eq!(written["summary"]["updated"], 1);
+        assert_eq!(written["hosts"][0]["status"], "ok");
+        // The temporary file was renamed over the previous report
+        assert_eq!(files, 1);
+    }
+
+    #[test]
+    fn leaves_no_temporary_file_when_the_report_cannot_be_replaced() {
+        let dir = env::temp_dir().join(format!("cf-dns-rs-report-{}", Uuid::new_v4()));
+        // A directory can't be replaced by the renamed file
+        let path = dir.join("report.json");
+        fs::create_dir_all(&path).unwrap();
+
+        ReconcileReport::default().write_file(path.to_str().unwrap(), SystemTime::now());
+
+        let tmp_exists = dir.join("report.json.tmp").exists();
+        let is_dir = path.is_dir();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(!tmp_exists);
+        assert!(is_dir);
+    }
+
+    #[test]
+    fn aggregates_the_activity_of_the_cycles() {
+        let started = Instant::now();
+        let mut summary = RunSummary::new(started);
+        let v4 = |ip: &str| BTreeMap::from([(RecordType::A, ip.parse().unwrap())]);
+
+        let first = report(&[
+            ("www.example.com", RecordType::A, OpResult::Created),
+            ("api.example.com", RecordType::A, OpResult::Failed),
+        ]);
+        summary.add_cycle(&v4("198.51.100.1"), Some(&first), false);
+        summary.add_cycle(&BTreeMap::new(), None, true);
+        let second = report(&[
+            ("www.example.com", RecordType::A, OpResult::Updated),
+            ("api.example.com", RecordType::A, OpResult::Missing),
+            ("old.example.com", RecordType::A, OpResult::Deleted),
+        ]);
+        summary.add_cycle(&v4("203.0.113.7"), Some(&second), false);
+
+        assert_eq!((summary.cycles, summary.failed_cycles), (3, 1));
+        assert_eq!(
+            (
+                summary.updated,
+                summary.created,
+                summary.deleted,
+                summary.failed
+            ),
+            (1, 1, 1, 2)
+        );
+        assert_eq!(summary.ips, v4("203.0.113.7"));
+    }
+}
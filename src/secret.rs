@@ -0,0 +1,116 @@
+//! API token from the system keyring.
+//!
+//! When the app is built with the `keyring` feature and `CF_DNS_KEYRING_SERVICE` is set, the API token is read from the
+//! OS secret store (macOS Keychain, Windows Credential Manager or the Linux kernel keyring), under that service and the
+//! account in `CF_DNS_KEYRING_ACCOUNT` (`api_token` by default). `CF_DNS_API_TOKEN` is used if there is no such entry.
+
+#[cfg(not(feature = "keyring"))]
+use log::warn;
+#[cfg(feature = "keyring")]
+use log::{error, info};
+use std::env;
+
+/// Account under which the API token is stored, unless `CF_DNS_KEYRING_ACCOUNT` is set
+#[cfg(feature = "keyring")]
+const DEFAULT_ACCOUNT: &str = "api_token";
+
+/// Read the API token from the keyring, if one is configured
+///
+/// Returns `None` if no keyring is configured, or if it has no entry for the token.
+#[cfg(feature = "keyring")]
+pub fn keyring_api_token() -> Result<Option<String>, ()> {
+    let Ok(service) = env::var("CF_DNS_KEYRING_SERVICE") else {
+        return Ok(None);
+    };
+    let service = service.trim();
+    let account = env::var("CF_DNS_KEYRING_ACCOUNT").unwrap_or(DEFAULT_ACCOUNT.to_string());
+    let account = account.trim();
+
+    let entry = keyring::Entry::new(service, account).map_err(|e| {
+        error!("Could not open keyring entry '{account}' of service '{service}': {e}");
+    })?;
+    read_token(&entry, service, account)
+}
+
+/// Read the API token from the keyring `entry` of `account` of `service`, `None` if there is no such entry
+#[cfg(feature = "keyring")]
+fn read_token(entry: &keyring::Entry, service: &str, account: &str) -> Result<Option<String>, ()> {
+    match entry.get_password() {
+        Ok(token) => {
+            info!("Using the API token from keyring entry '{account}' of service '{service}'");
+            Ok(Some(token.trim().to_string()))
+        }
+        Err(keyring::Error::NoEntry) => {
+            info!(
+                "No keyring entry '{account}' of service '{service}', using `CF_DNS_API_TOKEN` instead"
+            );
+            Ok(None)
+        }
+        Err(e) => {
+            error!("Could not read keyring entry '{account}' of service '{service}': {e}");
+            Err(())
+        }
+    }
+}
+
+/// Read the API token from the keyring, which is not supported by this build
+#[cfg(not(feature = "keyring"))]
+pub fn keyring_api_token() -> Result<Option<String>, ()> {
+    if env::var("CF_DNS_KEYRING_SERVICE").is_ok() {
+        warn!(
+            "`CF_DNS_KEYRING_SERVICE` is set, but this build has no keyring support (`keyring` feature), using `CF_DNS_API_TOKEN` instead"
+        );
+    }
+    Ok(None)
+}
+
+#[cfg(all(test, feature = "keyring"))]
+mod tests {
+    use super::*;
+    use crate::logging;
+    use keyring::mock::{self, MockCredential};
+
+    fn mock_entry() -> keyring::Entry {
+        let credential = mock::default_credential_builder()
+            .build(None, "cf-dns-rs", DEFAULT_ACCOUNT)
+            .unwrap();
+        keyring::Entry::new_with_credential(credential)
+    }
+
+    #[test]
+    fn reads_the_api_token_from_the_keyring_entry() {
+        let entry = mock_entry();
+        entry.set_password(" s3cr3t-token\n").unwrap();
+
+        assert_eq!(
+            read_token(&entry, "cf-dns-rs", DEFAULT_ACCOUNT),
+            Ok(Some("s3cr3t-token".to_string()))
+        );
+    }
+
+    #[test]
+    fn falls_back_without_a_keyring_entry() {
+        assert_eq!(
+            read_token(&mock_entry(), "cf-dns-rs", DEFAULT_ACCOUNT),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn fails_when_the_keyring_cannot_be_read() {
+        logging::init_for_tests();
+        let entry = mock_entry();
+        let credential: &MockCredential = entry.get_credential().downcast_ref().unwrap();
+        credential.set_error(keyring::Error::PlatformFailure("locked".into()));
+
+        let (token, errors) =
+            logging::capture_errors(|| read_token(&entry, "cf-dns-rs", DEFAULT_ACCOUNT));
+
+        assert_eq!(token, Err(()));
+        assert!(
+            errors[0]
+                .starts_with("Could not read keyring entry 'api_token' of service 'cf-dns-rs'"),
+            "{errors:?}"
+        );
+    }
+}
@@ -0,0 +1,39 @@
+//! Graceful shutdown on `SIGINT` and `SIGTERM`.
+//!
+//! Once a shutdown is requested, the current cycle is completed and the app exits instead of starting the next one.
+//! Sleeping until the next cycle is interrupted by the shutdown request.
+
+use log::{error, info};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+static REQUESTED: Mutex<bool> = Mutex::new(false);
+static WAKEUP: Condvar = Condvar::new();
+
+/// Install the signal handler which requests a shutdown
+pub fn install() -> Result<(), ()> {
+    ctrlc::set_handler(|| {
+        info!("Shutdown requested, exiting after the current cycle");
+        request();
+    })
+    .map_err(|e| {
+        error!("Could not install the shutdown signal handler: {e}");
+    })
+}
+
+/// Request a shutdown, and wake up any thread waiting in [`wait()`]
+pub fn request() {
+    *REQUESTED.lock().unwrap_or_else(|e| e.into_inner()) = true;
+    WAKEUP.notify_all();
+}
+
+/// Whether a shutdown was requested
+pub fn requested() -> bool {
+    *REQUESTED.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Block for the given duration, or until a shutdown is requested
+pub fn wait(duration: Duration) {
+    let requested = REQUESTED.lock().unwrap_or_else(|e| e.into_inner());
+    let _ = WAKEUP.wait_timeout_while(requested, duration, |requested| !*requested);
+}
@@ -0,0 +1,344 @@
+//! Change events published to a message broker.
+//!
+//! When the app is built with the `event-sink` feature and `CF_DNS_EVENT_SINK` is set, the change events of every
+//! cycle are published as JSON (`{"timestamp": "...", "host": "...", "summary": "..."}`), one message per event, to:
+//! - `redis://[:password@]host[:port]/<channel>`: a Redis pub/sub channel, or a list which the events are pushed to
+//!   with `?mode=list`
+//! - `nats://[user:password@]host[:port]/<subject>`: a NATS subject
+//!
+//! A connection is opened for every cycle with changes. Failing to publish is only logged, and never stops the app.
+
+use crate::notify::ChangeEvent;
+use log::{debug, error, warn};
+use reqwest::Url;
+use serde_json::json;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::{Duration, SystemTime};
+
+/// Timeout of connecting to the broker, and of every read and write
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Broker protocol, with what the events are published to
+#[derive(Clone, PartialEq, Eq)]
+enum Target {
+    /// Redis pub/sub channel
+    RedisChannel(String),
+    /// Redis list, to which the events are appended
+    RedisList(String),
+    /// NATS subject
+    Nats(String),
+}
+
+/// Message broker to which the change events are published
+#[derive(Clone, PartialEq, Eq)]
+pub struct EventSink {
+    /// URL as configured, which may contain credentials
+    url: String,
+    addr: String,
+    user: Option<String>,
+    password: Option<String>,
+    target: Target,
+}
+
+impl std::fmt::Debug for EventSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The URL is left out since it may contain a password
+        write!(f, "EventSink({})", self.addr)
+    }
+}
+
+impl TryFrom<&str> for EventSink {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let url = Url::parse(value.trim()).map_err(|e| e.to_string())?;
+        let host = url.host_str().ok_or("it has no host")?;
+        let name = url.path().trim_start_matches('/');
+        if name.is_empty() {
+            return Err("it has no channel, list or subject as its path".to_string());
+        }
+        let list = url.query_pairs().any(|(k, v)| k == "mode" && v == "list");
+        let (target, default_port) = match url.scheme() {
+            "redis" if list => (Target::RedisList(name.to_string()), 6379),
+            "redis" => (Target::RedisChannel(name.to_string()), 6379),
+            "nats" => (Target::Nats(name.to_string()), 4222),
+            scheme => return Err(format!("scheme '{scheme}' is not one of `redis` or `nats`")),
+        };
+
+        Ok(Self {
+            url: value.trim().to_string(),
+            addr: format!("{host}:{}", url.port().unwrap_or(default_port)),
+            user: Some(url.username().to_string()).filter(|user| !user.is_empty()),
+            password: url.password().map(|password| password.to_string()),
+            target,
+        })
+    }
+}
+
+impl EventSink {
+    /// URL of the broker as configured, which may contain credentials
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Publish every one of the `events` of the cycle which finished at `timestamp`
+    pub fn publish(&self, events: &[ChangeEvent], timestamp: SystemTime) {
+        if events.is_empty() {
+            return;
+        }
+        let timestamp = jiff::Timestamp::try_from(timestamp)
+            .map(|timestamp| timestamp.to_string())
+            .unwrap_or_default();
+        let payloads = events
+            .iter()
+            .map(|event| {
+                json!({
+                    "timestamp": timestamp,
+                    "host": event.host,
+                    "summary": event.summary,
+                })
+                .to_string()
+            })
+            .collect::<Vec<_>>();
+
+        match self.send(&payloads) {
+            Ok(_) => debug!(
+                "Published <{}> change events to '{}'",
+                payloads.len(),
+                self.addr
+            ),
+            Err(e) => warn!(
+                "Could not publish <{}> change events to '{}': {e}",
+                payloads.len(),
+                self.addr
+            ),
+        }
+    }
+
+    fn send(&self, payloads: &[String]) -> Result<(), String> {
+        let addr = std::net::ToSocketAddrs::to_socket_addrs(&self.addr)
+            .map_err(|e| e.to_string())?
+            .next()
+            .ok_or("the host has no address")?;
+        let stream = TcpStream::connect_timeout(&addr, TIMEOUT).map_err(|e| e.to_string())?;
+        stream
+            .set_read_timeout(Some(TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(TIMEOUT)))
+            .map_err(|e| e.to_string())?;
+        let mut reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+        let mut stream = stream;
+
+        match &self.target {
+            Target::RedisChannel(key) | Target::RedisList(key) => {
+                let command = match self.target {
+                    Target::RedisList(_) => "RPUSH",
+                    _ => "PUBLISH",
+                };
+                if let Some(password) = &self.password {
+                    let auth = match &self.user {
+                        Some(user) => resp(&["AUTH", user, password]),
+                        None => resp(&["AUTH", password]),
+                    };
+                    redis_call(&mut stream, &mut reader, &auth)?;
+                }
+                for payload in payloads {
+                    redis_call(&mut stream, &mut reader, &resp(&[command, key, payload]))?;
+                }
+            }
+            Target::Nats(subject) => {
+                // The server greets with its `INFO`
+                read_line(&mut reader)?;
+                let mut connect = json!({ "verbose": false, "pedantic": false });
+                if let (Some(user), Some(password)) = (&self.user, &self.password) {
+                    connect["user"] = json!(user);
+                    connect["pass"] = json!(password);
+                }
+                let mut out = format!("CONNECT {connect}\r\n");
+                for payload in payloads {
+                    out += &format!("PUB {subject} {}\r\n{payload}\r\n", payload.len());
+                }
+                // The server answers `PONG` once it processed everything before the `PING`, or an `-ERR`
+                out += "PING\r\n";
+                stream
+                    .write_all(out.as_bytes())
+                    .map_err(|e| e.to_string())?;
+                loop {
+                    let line = read_line(&mut reader)?;
+                    match line.as_str() {
+                        "PONG" => break,
+                        "PING" => stream.write_all(b"PONG\r\n").map_err(|e| e.to_string())?,
+                        line if line.starts_with("-ERR") => return Err(line.to_string()),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Encode a Redis command in the RESP protocol
+fn resp(args: &[&str]) -> String {
+    let mut out = format!("*{}\r\n", args.len());
+    for arg in args {
+        out += &format!("${}\r\n{arg}\r\n", arg.len());
+    }
+    out
+}
+
+/// Send a Redis `command` and check that its reply is not an error
+fn redis_call(
+    stream: &mut TcpStream,
+    reader: &mut BufReader<TcpStream>,
+    command: &str,
+) -> Result<(), String> {
+    stream
+        .write_all(command.as_bytes())
+        .map_err(|e| e.to_string())?;
+    let reply = read_line(reader)?;
+    match reply.strip_prefix('-') {
+        Some(error) => Err(error.to_string()),
+        None => Ok(()),
+    }
+}
+
+fn read_line(reader: &mut BufReader<TcpStream>) -> Result<String, String> {
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) => Err("the connection was closed".to_string()),
+        Ok(_) => Ok(line.trim_end().to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Read the broker from `CF_DNS_EVENT_SINK`
+pub fn event_sink() -> Result<Option<EventSink>, ()> {
+    match std::env::var("CF_DNS_EVENT_SINK") {
+        Ok(url) => EventSink::try_from(url.as_str()).map(Some).map_err(|e| {
+            error!("Could not parse `CF_DNS_EVENT_SINK`: {e}");
+        }),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::{Arc, Mutex};
+
+    /// In-memory Redis server, which accepts every command and keeps its arguments
+    fn fake_redis() -> (SocketAddr, Arc<Mutex<Vec<Vec<String>>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let received = commands.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().map_while(Result::ok) {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut stream = stream;
+                while let Ok(count) = read_line(&mut reader) {
+                    let count = count.trim_start_matches('*').parse().unwrap();
+                    let args = (0..count)
+                        .map(|_| {
+                            read_line(&mut reader).unwrap();
+                            read_line(&mut reader).unwrap()
+                        })
+                        .collect::<Vec<_>>();
+                    received.lock().unwrap().push(args);
+                    stream.write_all(b":1\r\n").unwrap();
+                }
+            }
+        });
+        (addr, commands)
+    }
+
+    fn events() -> Vec<ChangeEvent> {
+        ["www.example.com", "api.example.com"]
+            .map(|host| ChangeEvent {
+                host: host.to_string(),
+                summary: "A updated".to_string(),
+            })
+            .to_vec()
+    }
+
+    fn payload(host: &str) -> String {
+        json!({
+            "timestamp": "1970-01-01T00:00:00Z",
+            "host": host,
+            "summary": "A updated",
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn publishes_each_change_event_as_json_to_a_redis_channel_or_list() {
+        let (addr, commands) = fake_redis();
+        let channel = EventSink::try_from(format!("redis://{addr}/dns").as_str()).unwrap();
+        let list =
+            EventSink::try_from(format!("redis://:s3cr3t@{addr}/dns?mode=list").as_str()).unwrap();
+
+        channel.publish(&events(), SystemTime::UNIX_EPOCH);
+        list.publish(&events(), SystemTime::UNIX_EPOCH);
+
+        let command = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            *commands.lock().unwrap(),
+            [
+                command(&["PUBLISH", "dns", &payload("www.example.com")]),
+                command(&["PUBLISH", "dns", &payload("api.example.com")]),
+                command(&["AUTH", "s3cr3t"]),
+                command(&["RPUSH", "dns", &payload("www.example.com")]),
+                command(&["RPUSH", "dns", &payload("api.example.com")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn publishes_each_change_event_as_json_to_a_nats_subject() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("nats://{}/dns.changes", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"INFO {}\r\n").unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut lines = Vec::new();
+            loop {
+                let line = read_line(&mut reader).unwrap();
+                if line == "PING" {
+                    stream.write_all(b"PONG\r\n").unwrap();
+                    return lines;
+                }
+                lines.push(line);
+            }
+        });
+
+        EventSink::try_from(url.as_str())
+            .unwrap()
+            .publish(&events(), SystemTime::UNIX_EPOCH);
+
+        let www = payload("www.example.com");
+        let api = payload("api.example.com");
+        assert_eq!(
+            server.join().unwrap(),
+            [
+                r#"CONNECT {"pedantic":false,"verbose":false}"#.to_string(),
+                format!("PUB dns.changes {}", www.len()),
+                www,
+                format!("PUB dns.changes {}", api.len()),
+                api,
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_a_broker_which_cannot_be_reached() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let sink = EventSink::try_from(format!("redis://{addr}/dns").as_str()).unwrap();
+
+        assert!(sink.send(&[payload("www.example.com")]).is_err());
+    }
+}
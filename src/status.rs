@@ -0,0 +1,97 @@
+//! Last error of each monitored record.
+//!
+//! The reason of the most recent failure of each host and record type is kept until the record is reconciled
+//! successfully, and is exposed through the control socket's `status` command and the metrics.
+
+use crate::RecordType;
+use crate::report::OpResult;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+static LAST_ERRORS: Mutex<BTreeMap<(String, RecordType), LastError>> = Mutex::new(BTreeMap::new());
+
+/// Most recent failure of a record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LastError {
+    pub message: String,
+    pub timestamp: SystemTime,
+}
+
+/// Record the `result` of reconciling the `rtype` record of `host` at `now`, with the `errors` logged meanwhile
+///
+/// A failure replaces the last error of the record, and a success clears it.
+pub fn record(host: &str, rtype: RecordType, result: OpResult, errors: &[String], now: SystemTime) {
+    let mut last_errors = LAST_ERRORS.lock().unwrap_or_else(|e| e.into_inner());
+    match result {
+        result if result.is_failure() => {
+            let message = if errors.is_empty() {
+                result.to_string()
+            } else {
+                errors.join("; ")
+            };
+            last_errors.insert(
+                (host.to_string(), rtype),
+                LastError {
+                    message,
+                    timestamp: now,
+                },
+            );
+        }
+        // Nothing was attempted, so the last error still applies
+        OpResult::Deferred => {}
+        _ => {
+            last_errors.remove(&(host.to_string(), rtype));
+        }
+    }
+}
+
+/// Last error of every record which is currently failing, by host and record type
+pub fn last_errors() -> BTreeMap<(String, RecordType), LastError> {
+    LAST_ERRORS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn keeps_the_last_error_of_a_record_until_it_succeeds() {
+        // A host of its own, since the last errors are shared by every test
+        let host = "status.example.com";
+        let key = (host.to_string(), RecordType::A);
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+
+        record(
+            host,
+            RecordType::A,
+            OpResult::Failed,
+            &["timed out".to_string()],
+            at(10),
+        );
+        record(
+            host,
+            RecordType::A,
+            OpResult::Failed,
+            &["a".to_string(), "b".to_string()],
+            at(20),
+        );
+        assert_eq!(
+            last_errors().get(&key),
+            Some(&LastError {
+                message: "a; b".to_string(),
+                timestamp: at(20),
+            })
+        );
+
+        record(host, RecordType::A, OpResult::Deferred, &[], at(30));
+        assert_eq!(last_errors()[&key].timestamp, at(20));
+
+        record(host, RecordType::A, OpResult::Updated, &[], at(40));
+        assert!(!last_errors().contains_key(&key));
+    }
+}
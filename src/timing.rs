@@ -0,0 +1,124 @@
+//! Duration of individual operations.
+//!
+//! A [`Timer`] logs the duration of an operation at debug level when it is dropped, and adds it to the total of the
+//! operation's [`Phase`] for the current cycle.
+
+use log::debug;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+static TOTALS: Mutex<BTreeMap<Phase, Duration>> = Mutex::new(BTreeMap::new());
+
+/// Kind of operation, for the breakdown of a cycle's duration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    IpDetection,
+    CloudflareApi,
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Phase::IpDetection => write!(f, "IP detection"),
+            Phase::CloudflareApi => write!(f, "Cloudflare API"),
+        }
+    }
+}
+
+/// Measures an operation, from its creation until it is dropped
+#[derive(Debug)]
+pub struct Timer {
+    phase: Phase,
+    label: String,
+    start: Instant,
+}
+
+impl Timer {
+    pub fn start(phase: Phase, label: impl Into<String>) -> Self {
+        Self {
+            phase,
+            label: label.into(),
+            start: Instant::now(),
+        }
+    }
+
+    /// Time since the operation started
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let mut totals = TOTALS.lock().unwrap_or_else(|e| e.into_inner());
+        debug!(
+            "{}",
+            record(&mut totals, self.phase, &self.label, self.elapsed())
+        );
+    }
+}
+
+/// Add the `elapsed` duration of an operation to the total of its `phase`, and describe it
+fn record(
+    totals: &mut BTreeMap<Phase, Duration>,
+    phase: Phase,
+    label: &str,
+    elapsed: Duration,
+) -> String {
+    *totals.entry(phase).or_default() += elapsed;
+    format!("{label} took {}ms", elapsed.as_millis())
+}
+
+/// Total duration of the operations of each phase since the last call
+pub fn take_totals() -> BTreeMap<Phase, Duration> {
+    std::mem::take(&mut *TOTALS.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+
+    #[test]
+    fn adds_up_the_duration_of_the_operations_of_each_phase() {
+        let clock = MockClock::new(Duration::ZERO);
+        let mut totals = BTreeMap::new();
+        let mut time = |phase, label, duration| {
+            let start = clock.now();
+            clock.advance(duration);
+            record(&mut totals, phase, label, clock.now() - start)
+        };
+
+        let lines = [
+            time(Phase::IpDetection, "Get the IP", Duration::from_millis(30)),
+            time(
+                Phase::CloudflareApi,
+                "Update www A",
+                Duration::from_millis(240),
+            ),
+            time(
+                Phase::CloudflareApi,
+                "Update www AAAA",
+                Duration::from_millis(60),
+            ),
+        ];
+
+        assert_eq!(
+            lines,
+            [
+                "Get the IP took 30ms",
+                "Update www A took 240ms",
+                "Update www AAAA took 60ms"
+            ]
+        );
+        assert_eq!(
+            totals,
+            BTreeMap::from([
+                (Phase::IpDetection, Duration::from_millis(30)),
+                (Phase::CloudflareApi, Duration::from_millis(300)),
+            ])
+        );
+    }
+}
@@ -0,0 +1,249 @@
+//! Detection of record writes which don't stick.
+//!
+//! Every written record is fetched again on the next refresh, and should then have the written IP. When it has another
+//! IP, either another process (e.g. the dynamic DNS client of a router) changed it back, or Cloudflare accepted the
+//! write without applying it. The two are told apart by the `modified_on` time of the record: if it is the same as
+//! before the write, the record was never changed. A record which is changed back [`FLAP_THRESHOLD`] times in a row is
+//! reported as flapping, since this app and the other process will keep overwriting each other. Each condition is
+//! reported once, until the record is modified or the write sticks.
+
+use crate::{CfRecord, RecordType};
+use log::warn;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::net::IpAddr;
+
+/// Number of times in a row a written record must be changed back to be reported as flapping
+const FLAP_THRESHOLD: u32 = 3;
+
+/// Last write of a record which was not yet seen applied
+#[derive(Debug, Clone)]
+struct Write {
+    name: String,
+    rtype: RecordType,
+    content: IpAddr,
+    /// `modified_on` of the record before it was written
+    modified_on: Option<String>,
+    /// Number of times in a row the record was changed back after being written
+    reverts: u32,
+    /// Whether the write was already reported as accepted without being applied
+    silent_failure_reported: bool,
+}
+
+/// Problem found with a write when checking it
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Finding {
+    /// Cloudflare accepted the write without applying it
+    SilentFailure {
+        name: String,
+        rtype: RecordType,
+        current: IpAddr,
+        written: IpAddr,
+    },
+    /// Another process changed the record back [`FLAP_THRESHOLD`] times in a row
+    Flapping {
+        name: String,
+        rtype: RecordType,
+        current: IpAddr,
+        written: IpAddr,
+    },
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Finding::SilentFailure {
+                name,
+                rtype,
+                current,
+                written,
+            } => write!(
+                f,
+                "'{rtype}' record '{name}' still has IP '{current}' although it was updated to '{written}', and it was \
+                not modified since then. Cloudflare accepted the update without applying it"
+            ),
+            Finding::Flapping {
+                name,
+                rtype,
+                current,
+                written,
+            } => write!(
+                f,
+                "'{rtype}' record '{name}' was changed back to IP '{current}' <{FLAP_THRESHOLD}> times in a row after \
+                being updated to '{written}'. Another process is likely managing it too, and both keep overwriting \
+                each other"
+            ),
+        }
+    }
+}
+
+/// Writes of records which must be checked on the next refresh, by record id
+#[derive(Debug, Default)]
+pub struct Watchdog {
+    writes: BTreeMap<String, Write>,
+}
+
+impl Watchdog {
+    /// Record that `cf_rec` was written with the IP `content`
+    pub fn wrote(&mut self, cf_rec: &CfRecord, content: IpAddr) {
+        let previous = self.writes.get(&cf_rec.id);
+        let reverts = previous.map(|write| write.reverts).unwrap_or_default();
+        // Writing again a record which was never modified keeps the silent failure reported
+        let silent_failure_reported = previous.is_some_and(|write| {
+            write.silent_failure_reported && write.modified_on == cf_rec.modified_on
+        });
+        self.writes.insert(
+            cf_rec.id.clone(),
+            Write {
+                name: cf_rec.record.name.clone(),
+                rtype: cf_rec.record.rtype(),
+                content,
+                modified_on: cf_rec.modified_on.clone(),
+                reverts,
+                silent_failure_reported,
+            },
+        );
+    }
+
+    /// Check the freshly fetched `records` against the writes made since the previous fetch
+    pub fn check(&mut self, records: &[CfRecord]) {
+        for finding in self.findings(records) {
+            warn!("{finding}");
+        }
+    }
+
+    /// Check the writes against `records`, and return the problems which were not reported yet
+    fn findings(&mut self, records: &[CfRecord]) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        self.writes.retain(|id, write| {
+            // A deleted record can't be checked anymore
+            let Some(cf_rec) = records.iter().find(|r| &r.id == id) else {
+                return false;
+            };
+            if cf_rec.record.content == write.content {
+                return false;
+            }
+
+            if cf_rec.modified_on.is_some() && cf_rec.modified_on == write.modified_on {
+                if !write.silent_failure_reported {
+                    write.silent_failure_reported = true;
+                    findings.push(Finding::SilentFailure {
+                        name: write.name.clone(),
+                        rtype: write.rtype,
+                        current: cf_rec.record.content,
+                        written: write.content,
+                    });
+                }
+            } else {
+                write.silent_failure_reported = false;
+                write.reverts += 1;
+                if write.reverts == FLAP_THRESHOLD {
+                    findings.push(Finding::Flapping {
+                        name: write.name.clone(),
+                        rtype: write.rtype,
+                        current: cf_rec.record.content,
+                        written: write.content,
+                    });
+                }
+            }
+            true
+        });
+        findings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::fake_record;
+
+    const OLD_IP: &str = "198.51.100.1";
+    const NEW_IP: &str = "203.0.113.7";
+
+    fn record(ip: &str, modified_on: &str) -> CfRecord {
+        let mut cf_rec = fake_record("1", "www.example.com", ip.parse().unwrap(), None);
+        cf_rec.modified_on = Some(modified_on.to_string());
+        cf_rec
+    }
+
+    /// Write the new IP over `before`, then check it against `after`
+    fn cycle(watchdog: &mut Watchdog, before: &CfRecord, after: &CfRecord) -> Vec<Finding> {
+        watchdog.wrote(before, NEW_IP.parse().unwrap());
+        watchdog.findings(std::slice::from_ref(after))
+    }
+
+    fn is_silent_failure(findings: &[Finding]) -> bool {
+        matches!(findings, [Finding::SilentFailure { .. }])
+    }
+
+    #[test]
+    fn reports_a_silent_failure_once() {
+        let mut watchdog = Watchdog::default();
+        let unchanged = record(OLD_IP, "t1");
+
+        assert!(is_silent_failure(&cycle(
+            &mut watchdog,
+            &unchanged,
+            &unchanged
+        )));
+        assert!(cycle(&mut watchdog, &unchanged, &unchanged).is_empty());
+        assert!(cycle(&mut watchdog, &unchanged, &unchanged).is_empty());
+    }
+
+    #[test]
+    fn reports_a_silent_failure_again_after_the_record_is_modified() {
+        let mut watchdog = Watchdog::default();
+        let unchanged = record(OLD_IP, "t1");
+        let reverted = record(OLD_IP, "t2");
+
+        assert!(is_silent_failure(&cycle(
+            &mut watchdog,
+            &unchanged,
+            &unchanged
+        )));
+        assert!(cycle(&mut watchdog, &unchanged, &reverted).is_empty());
+        assert!(is_silent_failure(&cycle(
+            &mut watchdog,
+            &reverted,
+            &reverted
+        )));
+    }
+
+    #[test]
+    fn reports_a_silent_failure_again_after_it_clears() {
+        let mut watchdog = Watchdog::default();
+        let unchanged = record(OLD_IP, "t1");
+        let applied = record(NEW_IP, "t2");
+        let unchanged_again = record(OLD_IP, "t3");
+
+        assert!(is_silent_failure(&cycle(
+            &mut watchdog,
+            &unchanged,
+            &unchanged
+        )));
+        assert!(cycle(&mut watchdog, &unchanged, &applied).is_empty());
+        assert!(is_silent_failure(&cycle(
+            &mut watchdog,
+            &unchanged_again,
+            &unchanged_again
+        )));
+    }
+
+    #[test]
+    fn reports_flapping_once_at_the_threshold() {
+        let mut watchdog = Watchdog::default();
+        let mut reported = 0;
+        for cycle_nr in 0..2 * FLAP_THRESHOLD {
+            let before = record(OLD_IP, &format!("t{cycle_nr}"));
+            let reverted = record(OLD_IP, &format!("t{}", cycle_nr + 1));
+            let findings = cycle(&mut watchdog, &before, &reverted);
+            if matches!(findings.as_slice(), [Finding::Flapping { .. }]) {
+                assert_eq!(cycle_nr + 1, FLAP_THRESHOLD);
+                reported += 1;
+            } else {
+                assert!(findings.is_empty());
+            }
+        }
+        assert_eq!(reported, 1);
+    }
+}
@@ -0,0 +1,2324 @@
+//! Cloudflare zones and the operations on their DNS records.
+
+use crate::audit::{self, Action};
+use crate::cli;
+use crate::clock::{Clock, Deadline, SystemClock};
+use crate::cloudflare::{CfApi, CloudflareProvider, cf_get_zone, cf_resolve_zone_id};
+use crate::cloudflare_ips;
+use crate::comment;
+use crate::config::{Config, HostConfig, Manage, VerifyCreate, ZoneConfig, ZoneRef};
+use crate::desired::{self, DesiredRecord, Drift, Op};
+use crate::logging;
+use crate::propagation::PropagationCheck;
+use crate::provider::DnsProvider;
+use crate::report::{OpResult, ReconcileReport};
+use crate::status;
+use crate::timing::{Phase, Timer};
+use crate::watchdog::Watchdog;
+use crate::{CfRecord, Record, RecordType, TtlLimits};
+use log::{debug, error, info, warn};
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Last error of the records which were not reconciled before the run timeout expired
+const TIMED_OUT: &str = "The run timeout expired before the record was reconciled";
+
+/// A Cloudflare zone
+#[derive(Debug, Clone)]
+pub struct Zone {
+    pub id: String,
+    /// Zone name, looked up from Cloudflare
+    pub name: Option<String>,
+    /// Zone status (e.g. `active` or `pending`), looked up from Cloudflare
+    pub status: Option<String>,
+    /// Whether records created in this zone are proxied, unless overridden by the host. Overrides the global default.
+    pub proxied_default: Option<bool>,
+}
+
+impl Zone {
+    /// Resolve the host `@` to the zone's apex, which Cloudflare names after the zone itself, and explain how a proxied
+    /// apex host is handled
+    fn apex_host(
+        &self,
+        host: &HostConfig,
+        config: &Config,
+        zone_ref: &ZoneRef,
+    ) -> Result<HostConfig, ()> {
+        let zone_name = match (&self.name, zone_ref) {
+            (Some(name), _) | (None, ZoneRef::Name(name)) => Some(name),
+            (None, ZoneRef::Id(_)) => None,
+        };
+
+        let mut host = host.clone();
+        if host.name == "@" {
+            let Some(zone_name) = zone_name else {
+                error!(
+                    "Host '@' is the apex of zone '{}', whose name could not be looked up",
+                    self.id
+                );
+                return Err(());
+            };
+            host.name = zone_name.to_lowercase();
+        }
+
+        if zone_name.is_some_and(|zone_name| host.name.eq_ignore_ascii_case(zone_name))
+            && config
+                .endpoints
+                .keys()
+                .any(|rtype| self.proxied(&host, config.proxied_for(*rtype)))
+        {
+            info!(
+                "'{}' is the apex of its zone and is proxied: Cloudflare answers its own addresses for it, while its \
+                'A' and 'AAAA' records keep the current IPs. Keep them as 'A' and 'AAAA' records, since a CNAME at the \
+                apex is flattened and can't track the IP",
+                host.name
+            );
+        }
+        Ok(host)
+    }
+
+    /// Whether a record created for `host` is proxied.
+    ///
+    /// Resolution order: host override > zone default > `global_default`, which is the default for the record's type.
+    pub fn proxied(&self, host: &HostConfig, global_default: bool) -> bool {
+        host.proxied
+            .or(self.proxied_default)
+            .unwrap_or(global_default)
+    }
+}
+
+/// Client for the DNS records of a single zone
+///
+/// The zone's records are fetched once per cycle by [`ZoneClient::refresh()`], and kept up to date as records are
+/// created, updated and deleted.
+#[derive(Debug)]
+pub struct ZoneClient {
+    pub zone: Zone,
+    pub hosts: Vec<HostConfig>,
+    /// Records which should exist in the zone, besides those of the monitored hosts. `None` if the zone's records are
+    /// not managed from a desired state.
+    pub desired: Option<Vec<DesiredRecord>>,
+    /// Whether every record is updated by the next reconcile, even if it already has the desired state (`--force`)
+    pub force: bool,
+    /// Records already updated by the pending `--force`, by lowercase name and type. The force is kept until none of its
+    /// updates was deferred by `CF_DNS_MAX_WRITES_PER_CYCLE`, without updating these records again.
+    forced: BTreeSet<(String, RecordType)>,
+    /// Whether only missing records are created, and existing records are left alone (`--provision-only`)
+    pub provision_only: bool,
+    /// Provider through which the zone's records are changed
+    provider: Box<dyn DnsProvider>,
+    verify_create: VerifyCreate,
+    /// Whether records added automatically by Cloudflare are updated and pruned like the others
+    manage_auto_added: bool,
+    ttl_limits: TtlLimits,
+    /// Check that changed records are visible from public resolvers
+    propagation: Option<PropagationCheck>,
+    /// Number of writes which can still be made during the current cycle, `None` if unlimited
+    writes_left: Option<u32>,
+    /// Time during which a record which was just written is assumed to be current
+    cooldown: Duration,
+    /// End of the cooldown of each record which was written recently, by lowercase name and type
+    cooldowns: BTreeMap<(String, RecordType), Instant>,
+    /// Writes which are checked when the records are fetched again
+    watchdog: Watchdog,
+    /// Drift of the desired records as of the last reconcile, `None` before the first one
+    drift: Option<Drift>,
+    /// Records of the zone, as of the last refresh
+    records: Vec<CfRecord>,
+    /// Number of cycles for which the records can be reused instead of being fetched again
+    cache_cycles: u32,
+    /// Time during which the records can be reused instead of being fetched again, whatever the number of cycles
+    cache_ttl: Option<Duration>,
+    /// Time at which the records were last fetched, `None` before the first fetch
+    fetched_at: Option<Instant>,
+    /// Number of cycles for which the records were reused since they were fetched, `None` if they must be fetched on
+    /// the next refresh
+    reused_cycles: Option<u32>,
+    /// Whether the records were fetched ahead of the next refresh, which reuses them
+    prefetched: bool,
+    /// Tag of the managed records, through which only the tagged records are listed (`CF_DNS_MANAGED_TAG`)
+    managed_tag: Option<String>,
+    /// Types of the hosts' records, which must all be tagged for the tagged records to be listed
+    host_types: Vec<RecordType>,
+}
+
+/// Make sure that the API token of `provider` is active, `whose` naming it in the log messages
+pub fn verify_token(provider: &dyn DnsProvider, whose: &str) -> Result<(), ()> {
+    let status = {
+        let _timer = Timer::start(Phase::CloudflareApi, format!("Verify the {whose}"));
+        provider.verify_token()
+    };
+    match status {
+        Ok(status) if status == "active" => Ok(()),
+        Ok(status) => {
+            error!("The {whose} has status '{status}' instead of 'active'");
+            Err(())
+        }
+        Err(_) => {
+            error!("Could not verify the {whose}");
+            Err(())
+        }
+    }
+}
+
+/// Warn that the zone with the given `name` and `id` is not active yet, e.g. since it is `pending`, or refuse it if
+/// `require_active`
+fn check_zone_status(name: &str, id: &str, status: &str, require_active: bool) -> Result<(), ()> {
+    if status == "active" {
+        return Ok(());
+    }
+    if require_active {
+        error!("Zone '{name}' ({id}) has status '{status}' instead of 'active'");
+        return Err(());
+    }
+    warn!(
+        "Zone '{name}' ({id}) has status '{status}' instead of 'active', DNS updates may not take effect"
+    );
+    Ok(())
+}
+
+impl ZoneClient {
+    /// Look up the zone's name and status, and make sure it is usable
+    ///
+    /// A zone configured by name is resolved to its id, unless one of the `known` zones has the same name.
+    pub fn connect(zone_config: &ZoneConfig, config: &Config, known: &[Zone]) -> Result<Self, ()> {
+        let api = CfApi::with_config(zone_config.auth(config), config);
+        // The global API token is verified once for all the zones
+        if zone_config.auth.is_some() {
+            let provider = CloudflareProvider::new(api.clone());
+            verify_token(
+                &provider,
+                &format!("API token of zone '{}'", zone_config.zone_ref),
+            )?;
+        }
+
+        let id = match &zone_config.zone_ref {
+            ZoneRef::Id(id) => id.clone(),
+            ZoneRef::Name(name) => match known.iter().find(|zone| zone.name.as_ref() == Some(name))
+            {
+                Some(zone) => zone.id.clone(),
+                None => {
+                    let id = {
+                        let _timer =
+                            Timer::start(Phase::CloudflareApi, format!("Resolve zone '{name}'"));
+                        cf_resolve_zone_id(name, &api, &SystemClock)?
+                    };
+                    info!("Resolved zone '{name}' to id '{id}'");
+                    id
+                }
+            },
+        };
+
+        let mut zone = Zone {
+            id,
+            name: None,
+            status: None,
+            proxied_default: zone_config.proxied_default,
+        };
+
+        let info = {
+            let _timer = Timer::start(Phase::CloudflareApi, format!("Look up zone '{}'", zone.id));
+            cf_get_zone(&zone.id, &api, &SystemClock)
+        };
+        match info {
+            Ok(info) => {
+                check_zone_status(
+                    &info.name,
+                    &zone.id,
+                    &info.status,
+                    config.require_active_zone,
+                )?;
+                zone.name = Some(info.name);
+                zone.status = Some(info.status);
+            }
+            Err(_) => warn!(
+                "Could not look up zone '{}', its status is unknown",
+                zone.id
+            ),
+        }
+
+        let hosts = zone_config
+            .hosts
+            .iter()
+            .map(|host| zone.apex_host(host, config, &zone_config.zone_ref))
+            .collect::<Result<_, ()>>()?;
+
+        Ok(Self::new(
+            zone,
+            hosts,
+            zone_config.records.clone(),
+            config,
+            Box::new(CloudflareProvider::new(api)),
+        ))
+    }
+
+    /// Client for the records of `zone`, changed through `provider`
+    fn new(
+        zone: Zone,
+        hosts: Vec<HostConfig>,
+        desired: Option<Vec<DesiredRecord>>,
+        config: &Config,
+        provider: Box<dyn DnsProvider>,
+    ) -> Self {
+        Self {
+            zone,
+            hosts,
+            // Clamped here, so that a desired TTL outside of the limits is not seen as a change on every cycle
+            desired: desired.map(|records| {
+                records
+                    .into_iter()
+                    .map(|record| DesiredRecord {
+                        ttl: config.ttl_limits.clamp(record.ttl, &record.name),
+                        ..record
+                    })
+                    .collect()
+            }),
+            force: false,
+            forced: BTreeSet::new(),
+            provision_only: false,
+            provider,
+            verify_create: config.verify_create,
+            manage_auto_added: config.manage_auto_added,
+            ttl_limits: config.ttl_limits,
+            propagation: config.propagation.clone(),
+            writes_left: None,
+            cooldown: config.post_update_cooldown,
+            cooldowns: BTreeMap::new(),
+            watchdog: Watchdog::default(),
+            drift: None,
+            records: Vec::new(),
+            cache_cycles: config.record_cache_cycles,
+            cache_ttl: config.record_cache_ttl,
+            fetched_at: None,
+            reused_cycles: None,
+            prefetched: false,
+            managed_tag: config.managed_tag.clone(),
+            host_types: config.endpoints.keys().copied().collect(),
+        }
+    }
+
+    /// Take over the state of `old`, the client of the same zone before the config was reloaded
+    ///
+    /// The cooldowns, pending write checks, drift and cached records are kept, so that a reload doesn't write or fetch
+    /// again what the previous cycles already did. `old` was connected with `old_hosts`: the hosts it gained or lost
+    /// since then, through the control socket, are added or removed again.
+    pub fn carry_over(&mut self, old: ZoneClient, old_hosts: &[HostConfig]) {
+        let configured = |hosts: &[HostConfig], name: &str| hosts.iter().any(|h| h.name == name);
+        self.hosts.retain(|host| {
+            configured(&old.hosts, &host.name) || !configured(old_hosts, &host.name)
+        });
+        for host in old.hosts {
+            if !configured(old_hosts, &host.name) && !configured(&self.hosts, &host.name) {
+                self.hosts.push(host);
+            }
+        }
+
+        self.force = old.force;
+        self.forced = old.forced;
+        self.provision_only = old.provision_only;
+        self.cooldowns = old.cooldowns;
+        self.watchdog = old.watchdog;
+        self.drift = old.drift;
+        self.records = old.records;
+        self.fetched_at = old.fetched_at;
+        self.reused_cycles = old.reused_cycles;
+        self.prefetched = old.prefetched;
+    }
+
+    /// Whether there is nothing to reconcile in the zone
+    pub fn is_idle(&self) -> bool {
+        self.hosts.is_empty() && self.desired.is_none()
+    }
+
+    /// Fetch the zone's current records
+    ///
+    /// The records of the previous cycle are reused instead for up to `CF_DNS_RECORD_CACHE_CYCLES` cycles, as long as
+    /// no record was written since they were fetched.
+    pub fn refresh(&mut self, clock: &impl Clock) -> Result<(), ()> {
+        if std::mem::take(&mut self.prefetched) {
+            return Ok(());
+        }
+        let now = clock.now();
+        if let Some(reused) = self.reused_cycles
+            && (reused < self.cache_cycles
+                || self
+                    .cache_ttl
+                    .zip(self.fetched_at)
+                    .is_some_and(|(ttl, fetched_at)| {
+                        now.saturating_duration_since(fetched_at) < ttl
+                    }))
+        {
+            debug!(
+                "Reusing the records of zone '{}' fetched <{}> cycles and <{}> seconds ago",
+                self.zone.id,
+                reused + 1,
+                self.fetched_at
+                    .map(|fetched_at| now.saturating_duration_since(fetched_at))
+                    .unwrap_or_default()
+                    .as_secs()
+            );
+            self.reused_cycles = Some(reused + 1);
+            return Ok(());
+        }
+
+        self.reused_cycles = None;
+        let _timer = Timer::start(
+            Phase::CloudflareApi,
+            format!("List records of zone '{}'", self.zone.id),
+        );
+        self.records = match &self.managed_tag {
+            Some(tag) => self.list_tagged_records(tag)?,
+            None => self.provider.list_records(&self.zone.id)?,
+        };
+        self.watchdog.check(&self.records);
+        self.fetched_at = Some(now);
+        self.reused_cycles = Some(0);
+        Ok(())
+    }
+
+    /// List the zone's records which have the managed `tag`, or all its records if an enabled host or a desired record
+    /// has no tagged record: tags are not in use for it yet, e.g. since it was created before the tag was configured
+    fn list_tagged_records(&self, tag: &str) -> Result<Vec<CfRecord>, ()> {
+        let records = self.provider.list_tagged_records(&self.zone.id, tag)?;
+        let is_listed = |name: &str, rtype: RecordType| {
+            records
+                .iter()
+                .any(|r| r.record.name.eq_ignore_ascii_case(name) && r.record.rtype() == rtype)
+        };
+        let hosts = self.hosts.iter().filter(|host| host.enabled);
+        let untagged = hosts
+            .flat_map(|host| {
+                self.host_types
+                    .iter()
+                    .map(|rtype| (host.name.as_str(), *rtype))
+            })
+            .chain(
+                self.desired
+                    .iter()
+                    .flatten()
+                    .map(|desired| (desired.name.as_str(), desired.rtype)),
+            )
+            .find(|(name, rtype)| !is_listed(name, *rtype));
+
+        match untagged {
+            Some((name, rtype)) => {
+                debug!(
+                    "'{name}' has no '{rtype}' record tagged '{tag}' in zone '{}', listing all the records",
+                    self.zone.id
+                );
+                self.provider.list_records(&self.zone.id)
+            }
+            None => Ok(records),
+        }
+    }
+
+    /// Log whether the desired records are in sync with the live records, when that changed since the last reconcile
+    fn log_drift(&mut self, drift: Drift) {
+        if self.drift == Some(drift) {
+            return;
+        }
+        if drift.in_sync() {
+            info!(
+                "Desired records of zone '{}' are in sync, state hash '{:016x}'",
+                self.zone.id, drift.desired
+            );
+        } else {
+            info!(
+                "Desired records of zone '{}' differ from the live records by <{}> records, desired state hash \
+                '{:016x}', live state hash '{:016x}'",
+                self.zone.id, drift.differences, drift.desired, drift.live
+            );
+        }
+        self.drift = Some(drift);
+    }
+
+    /// Fetch the zone's records ahead of the next reconcile, and return the type and IP of the enabled hosts' records
+    pub fn host_ips(&mut self, clock: &impl Clock) -> Result<Vec<(RecordType, IpAddr)>, ()> {
+        self.refresh(clock)?;
+        self.prefetched = true;
+        Ok(self
+            .records
+            .iter()
+            .filter(|r| {
+                self.hosts
+                    .iter()
+                    .any(|host| host.enabled && host.name.eq_ignore_ascii_case(&r.record.name))
+            })
+            .map(|r| (r.record.rtype(), r.record.content))
+            .collect())
+    }
+
+    /// Fetch the zone's records, and return those of the monitored hosts and the desired records
+    pub fn managed_records(&mut self, clock: &impl Clock) -> Result<Vec<Record>, ()> {
+        self.refresh(clock)?;
+        Ok(self
+            .records
+            .iter()
+            .filter(|r| {
+                self.hosts
+                    .iter()
+                    .any(|host| host.name.eq_ignore_ascii_case(&r.record.name))
+                    || self.desired.iter().flatten().any(|desired| {
+                        desired.name.eq_ignore_ascii_case(&r.record.name)
+                            && desired.rtype == r.record.rtype()
+                    })
+            })
+            .map(|r| r.record.clone())
+            .collect())
+    }
+
+    /// Mark the existing records of the monitored hosts and the desired records as managed, leaving the rest of each
+    /// record unchanged, and return the ids of the records which were marked
+    ///
+    /// The managed marker is added in front of the record's comment. Locked records, records added automatically by
+    /// Cloudflare (unless `CF_DNS_MANAGE_AUTO_ADDED` is set) and records which are already managed are left alone.
+    pub fn adopt(&mut self, clock: &impl Clock) -> Result<Vec<String>, ()> {
+        self.refresh(clock)?;
+        let candidates = self
+            .records
+            .iter()
+            .filter(|r| {
+                self.hosts
+                    .iter()
+                    .any(|host| host.name.eq_ignore_ascii_case(&r.record.name))
+                    || self.desired.iter().flatten().any(|desired| {
+                        desired.name.eq_ignore_ascii_case(&r.record.name)
+                            && desired.rtype == r.record.rtype()
+                    })
+            })
+            .filter(|r| !comment::is_managed(r.comment.as_deref()))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut adopted = Vec::new();
+        for cf_rec in candidates {
+            let rtype = cf_rec.record.rtype();
+            if cf_rec.locked {
+                warn!(
+                    "'{rtype}' record '{}' is locked, not adopting it",
+                    cf_rec.record.name
+                );
+                continue;
+            }
+            if self.skips_auto_added(&cf_rec) {
+                continue;
+            }
+
+            let comment = match cf_rec.comment.as_deref().filter(|c| !c.is_empty()) {
+                Some(comment) => format!("{} {comment}", comment::MANAGED_MARKER),
+                None => comment::MANAGED_MARKER.to_string(),
+            };
+            let comment = comment
+                .chars()
+                .take(comment::MAX_COMMENT_LEN)
+                .collect::<String>();
+            if cli::dry_run() {
+                info!(
+                    "Dry run, not adopting '{rtype}' record '{}' with id '{}'",
+                    cf_rec.record.name, cf_rec.id
+                );
+                continue;
+            }
+            // The record is sent back as it is, only its comment changes
+            match self
+                .provider
+                .update_record(&self.zone.id, &cf_rec, &cf_rec.record, &comment)
+            {
+                Ok(_) => {
+                    info!(
+                        "Adopted '{rtype}' record '{}' with id '{}' and IP '{}'",
+                        cf_rec.record.name, cf_rec.id, cf_rec.record.content
+                    );
+                    if let Some(cached) = self.records.iter_mut().find(|r| r.id == cf_rec.id) {
+                        cached.comment = Some(comment);
+                    }
+                    adopted.push(cf_rec.id);
+                }
+                Err(_) => error!(
+                    "Failed to adopt '{rtype}' record '{}' with id '{}'",
+                    cf_rec.record.name, cf_rec.id
+                ),
+            }
+        }
+        Ok(adopted)
+    }
+
+    /// Make sure the DNS records of all hosts in the zone point to the current IPs, creating missing records if
+    /// allowed, and make the zone's records match the desired state
+    ///
+    /// Once the `deadline` has passed, the remaining hosts are not reconciled and are reported as failed. At most
+    /// `writes_left` records are written, if set, and it is decreased by the number of writes made.
+    pub fn reconcile(
+        &mut self,
+        cur_ips: &BTreeMap<RecordType, IpAddr>,
+        config: &Config,
+        clock: &impl Clock,
+        deadline: Deadline,
+        writes_left: &mut Option<u32>,
+    ) -> ReconcileReport {
+        let mut report = ReconcileReport::default();
+        let mut force_deferred = false;
+        if self.is_idle() {
+            return report;
+        }
+
+        // Every result is also recorded in the status, with the errors logged while reconciling the record
+        let record = |report: &mut ReconcileReport,
+                      name: &str,
+                      rtype: RecordType,
+                      (result, errors): (OpResult, Vec<String>)| {
+            status::record(name, rtype, result, &errors, clock.wall_now());
+            report.record(name, rtype, result);
+        };
+        let timed_out = || (OpResult::Failed, vec![TIMED_OUT.to_string()]);
+
+        let refreshed = if deadline.expired(clock) {
+            Err(vec![TIMED_OUT.to_string()])
+        } else {
+            let (refreshed, errors) = logging::capture_errors(|| self.refresh(clock));
+            refreshed.map_err(|_| errors)
+        };
+        if let Err(errors) = refreshed {
+            for rtype in cur_ips.keys() {
+                for host in self.hosts.iter().filter(|h| h.enabled) {
+                    let failed = (OpResult::Failed, errors.clone());
+                    record(&mut report, &host.name, *rtype, failed);
+                }
+            }
+            for desired in self.desired.iter().flatten() {
+                let failed = (OpResult::Failed, errors.clone());
+                record(&mut report, &desired.name, desired.rtype, failed);
+            }
+            return report;
+        }
+        self.writes_left = *writes_left;
+
+        let now = clock.now();
+        self.cooldowns.retain(|_, end| *end > now);
+
+        for host in self.hosts.iter().filter(|h| !h.enabled) {
+            debug!(
+                "Host '{}' is disabled, leaving its records alone",
+                host.name
+            );
+        }
+        let enabled = self
+            .hosts
+            .iter()
+            .filter(|h| h.enabled)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for (rtype, cur_ip) in cur_ips {
+            for host in enabled.clone() {
+                let cooling_down = self.cooling_down(&host.name, *rtype);
+                let forced = self.forces(&host.name, *rtype);
+                let outcome = if deadline.expired(clock) {
+                    timed_out()
+                } else if cooling_down {
+                    (OpResult::Unchanged, Vec::new())
+                } else {
+                    logging::capture_errors(|| self.reconcile_host(&host, *rtype, cur_ip, config))
+                };
+                self.start_cooldown(&host.name, *rtype, outcome.0, clock);
+                let result = outcome.0;
+                if forced {
+                    force_deferred |= self.end_force(&host.name, *rtype, result);
+                }
+                record(&mut report, &host.name, *rtype, outcome);
+
+                // Only the duplicates of a record which was just reconciled, and may be changed, are pruned
+                let reconciled = matches!(
+                    result,
+                    OpResult::Unchanged | OpResult::Updated | OpResult::Created
+                ) && !cooling_down;
+                if config.prune_duplicates
+                    && config.prunes(*rtype)
+                    && reconciled
+                    && !self.provision_only
+                    && host.manage != Manage::CreateOnly
+                    && !deadline.expired(clock)
+                    && let (Some(pruned), errors) =
+                        logging::capture_errors(|| self.prune(&host.name, *rtype))
+                    && (pruned != OpResult::Deleted || result == OpResult::Unchanged)
+                {
+                    // A deletion is reported unless the record itself changed, and a failure always is
+                    record(&mut report, &host.name, *rtype, (pruned, errors));
+                }
+            }
+        }
+
+        if config.replace_on_family_change && config.endpoints.len() == 1 && !self.provision_only {
+            for host in enabled.iter().filter(|h| h.manage == Manage::Full) {
+                for rtype in [RecordType::A, RecordType::AAAA] {
+                    if config.endpoints.contains_key(&rtype)
+                        || !config.prunes(rtype)
+                        || deadline.expired(clock)
+                    {
+                        continue;
+                    }
+                    if let (Some(result), errors) =
+                        logging::capture_errors(|| self.remove_family(&host.name, rtype))
+                    {
+                        record(&mut report, &host.name, rtype, (result, errors));
+                    }
+                }
+            }
+        }
+
+        let proxied_default = self.zone.proxied_default.unwrap_or(config.proxied_default);
+        let ops = match &self.desired {
+            Some(desired) => desired::diff(
+                desired,
+                &self.records,
+                cur_ips,
+                proxied_default,
+                config.normalize_name_case,
+            ),
+            None => Vec::new(),
+        };
+        // The managed records of the monitored hosts are not part of the desired state, but are still wanted
+        let ops = ops
+            .into_iter()
+            .filter(|op| match op {
+                Op::Delete(r) => !self
+                    .hosts
+                    .iter()
+                    .any(|h| h.name.eq_ignore_ascii_case(&r.record.name)),
+                _ => true,
+            })
+            .collect::<Vec<_>>();
+        if self.desired.is_some() {
+            self.log_drift(Drift::of(&ops));
+        }
+        let ops = ops
+            .into_iter()
+            .filter(|op| !self.provision_only || matches!(op, Op::Keep(_) | Op::Create(_)))
+            .filter(|op| match op {
+                Op::Delete(r) if !config.prunes(r.record.rtype()) => {
+                    debug!(
+                        "Keeping '{}' record '{}' which is no longer desired, since '{}' records are not in `CF_DNS_PRUNE_TYPES`",
+                        r.record.rtype(),
+                        r.record.name,
+                        r.record.rtype()
+                    );
+                    false
+                }
+                _ => true,
+            })
+            .map(|op| match op {
+                Op::Keep(current)
+                    if self.forces(&current.record.name, current.record.rtype()) =>
+                {
+                    Op::Update {
+                        desired: current.record.clone(),
+                        current,
+                    }
+                }
+                op => op,
+            });
+        for op in ops.collect::<Vec<_>>() {
+            let (name, rtype) = op.target();
+            let name = name.to_string();
+            let forced = matches!(op, Op::Update { .. }) && self.forces(&name, rtype);
+            let outcome = if deadline.expired(clock) {
+                timed_out()
+            } else if matches!(op, Op::Update { .. }) && self.cooling_down(&name, rtype) {
+                (OpResult::Unchanged, Vec::new())
+            } else {
+                logging::capture_errors(|| self.apply(op, config))
+            };
+            self.start_cooldown(&name, rtype, outcome.0, clock);
+            if forced {
+                force_deferred |= self.end_force(&name, rtype, outcome.0);
+            }
+            record(&mut report, &name, rtype, outcome);
+        }
+
+        *writes_left = self.writes_left;
+        if !force_deferred {
+            self.force = false;
+            self.forced.clear();
+        }
+        report
+    }
+
+    /// Apply an operation which makes the zone's records match the desired state
+    fn apply(&mut self, op: Op, config: &Config) -> OpResult {
+        let template = config.comment_template.as_ref();
+
+        match op {
+            Op::Keep(_) => OpResult::Unchanged,
+            Op::Update { current, .. } if current.locked => {
+                warn!(
+                    "'{}' record '{}' is locked by Cloudflare and cannot be updated, skipping it",
+                    current.record.rtype(),
+                    current.record.name
+                );
+                OpResult::Skipped
+            }
+            Op::Update { current, .. } if self.skips_auto_added(&current) => {
+                OpResult::SkippedAutoAdded
+            }
+            _ if !self.take_write() => OpResult::Deferred,
+            Op::Update { current, desired } => {
+                let comment = comment::managed(template, &desired.name, &desired.content);
+                match self.update_record(&current, &desired, &comment) {
+                    Ok(_) => {
+                        info!(
+                            "Updated '{}' record '{}' to IP '{}', TTL '{}', proxied '{}'",
+                            desired.rtype(),
+                            desired.name,
+                            desired.content,
+                            desired.ttl,
+                            desired.proxied
+                        );
+                        OpResult::Updated
+                    }
+                    Err(_) => {
+                        error!(
+                            "Failed to update '{}' record '{}' to IP '{}'",
+                            desired.rtype(),
+                            desired.name,
+                            desired.content
+                        );
+                        OpResult::Failed
+                    }
+                }
+            }
+            Op::Create(record) => {
+                let comment = comment::managed(template, &record.name, &record.content);
+                let (name, rtype, ip) = (record.name.clone(), record.rtype(), record.content);
+                match self.create(record, Some(&comment)) {
+                    Ok(_) => {
+                        info!("Created '{rtype}' record '{name}' with IP '{ip}'");
+                        OpResult::Created
+                    }
+                    Err(_) => {
+                        error!("Failed to create '{rtype}' record '{name}' with IP '{ip}'");
+                        OpResult::Failed
+                    }
+                }
+            }
+            Op::Delete(current) => {
+                let (name, rtype, ip) = (
+                    &current.record.name,
+                    current.record.rtype(),
+                    current.record.content,
+                );
+                match self.delete(&current) {
+                    Ok(_) => {
+                        info!(
+                            "Deleted '{rtype}' record '{name}' with IP '{ip}', which is no longer desired"
+                        );
+                        OpResult::Deleted
+                    }
+                    Err(_) => {
+                        error!(
+                            "Failed to delete '{rtype}' record '{name}' with IP '{ip}', which is no longer desired"
+                        );
+                        OpResult::Failed
+                    }
+                }
+            }
+        }
+    }
+
+    /// Make sure the record of the given type for `host` points to `cur_ip`, creating it if allowed
+    pub fn reconcile_host(
+        &mut self,
+        host: &HostConfig,
+        rtype: RecordType,
+        cur_ip: &IpAddr,
+        config: &Config,
+    ) -> OpResult {
+        let template = config.comment_template.as_ref();
+        // The comment of a managed record keeps the managed marker
+        let comment = |cf_rec: &CfRecord| {
+            if comment::is_managed(cf_rec.comment.as_deref()) {
+                Some(comment::managed(template, &host.name, cur_ip))
+            } else {
+                template.map(|t| t.render(&host.name, cur_ip))
+            }
+        };
+
+        match self.find(&host.name, rtype).cloned() {
+            Some(cf_rec) if cf_rec.locked => {
+                warn!(
+                    "'{}' record '{}' is locked by Cloudflare and cannot be updated, skipping it",
+                    rtype, host.name
+                );
+                OpResult::Skipped
+            }
+            Some(cf_rec) if self.skips_auto_added(&cf_rec) => OpResult::SkippedAutoAdded,
+            Some(_) if self.provision_only => OpResult::Unchanged,
+            Some(_) if host.manage == Manage::CreateOnly => {
+                debug!(
+                    "'{rtype}' record '{}' exists and the host is create-only, leaving it alone",
+                    host.name
+                );
+                OpResult::Unchanged
+            }
+            Some(_) if self.forces(&host.name, rtype) && !self.take_write() => OpResult::Deferred,
+            Some(cf_rec) if self.forces(&host.name, rtype) => {
+                let record = Record {
+                    name: host.name.clone(),
+                    content: *cur_ip,
+                    ..cf_rec.record.clone()
+                };
+                let comment = comment(&cf_rec)
+                    .or(cf_rec.comment.clone())
+                    .unwrap_or_default();
+                match self.update_record(&cf_rec, &record, &comment) {
+                    Ok(_) => {
+                        info!(
+                            "Force updated '{rtype}' record '{}' with IP '{cur_ip}'",
+                            host.name
+                        );
+                        OpResult::Updated
+                    }
+                    Err(_) => {
+                        error!(
+                            "Failed to force update '{rtype}' record '{}' with IP '{cur_ip}'",
+                            host.name
+                        );
+                        OpResult::Failed
+                    }
+                }
+            }
+            Some(cf_rec)
+                if config.normalize_name_case
+                    && cf_rec.record.name != host.name
+                    && !self.take_write() =>
+            {
+                OpResult::Deferred
+            }
+            Some(cf_rec) if config.normalize_name_case && cf_rec.record.name != host.name => {
+                let content = if config
+                    .ignore_change_within
+                    .is_change(&cf_rec.record.content, cur_ip)
+                {
+                    *cur_ip
+                } else {
+                    cf_rec.record.content
+                };
+                let record = Record {
+                    name: host.name.clone(),
+                    content,
+                    ..cf_rec.record.clone()
+                };
+                let comment = comment(&cf_rec)
+                    .or(cf_rec.comment.clone())
+                    .unwrap_or_default();
+                match self.update_record(&cf_rec, &record, &comment) {
+                    Ok(_) => {
+                        info!(
+                            "Renamed '{rtype}' record '{}' to '{}', with IP '{}'",
+                            cf_rec.record.name, host.name, content
+                        );
+                        OpResult::Updated
+                    }
+                    Err(_) => {
+                        error!(
+                            "Failed to rename '{rtype}' record '{}' to '{}'",
+                            cf_rec.record.name, host.name
+                        );
+                        OpResult::Failed
+                    }
+                }
+            }
+            Some(cf_rec) => {
+                if cloudflare_ips::is_cloudflare_ip(&cf_rec.record.content) {
+                    warn!(
+                        "'{rtype}' record '{}' has IP '{}', which is one of Cloudflare's addresses. A record must have \
+                        the origin IP, even when it is proxied: it was likely copied from a lookup of a proxied name",
+                        host.name, cf_rec.record.content
+                    );
+                }
+                if cf_rec.record.content != *cur_ip
+                    && !config
+                        .ignore_change_within
+                        .is_change(&cf_rec.record.content, cur_ip)
+                {
+                    debug!(
+                        "'{rtype}' record '{}' has IP '{}', in the same prefix as '{cur_ip}', leaving it unchanged",
+                        host.name, cf_rec.record.content
+                    );
+                    OpResult::Unchanged
+                } else if cf_rec.record.content != *cur_ip && !self.take_write() {
+                    OpResult::Deferred
+                } else if cf_rec.record.content != *cur_ip {
+                    match self.update(&cf_rec, cur_ip, comment(&cf_rec).as_deref()) {
+                        Ok(_) => {
+                            info!(
+                                "Updated '{}' record '{}' from IP '{}' to '{}'",
+                                cf_rec.record.rtype(),
+                                cf_rec.record.name,
+                                cf_rec.record.content,
+                                cur_ip
+                            );
+                            OpResult::Updated
+                        }
+                        Err(_) => {
+                            error!(
+                                "Failed to update '{}' record '{}' from IP '{}' to '{}'",
+                                cf_rec.record.rtype(),
+                                cf_rec.record.name,
+                                cf_rec.record.content,
+                                cur_ip
+                            );
+                            OpResult::Failed
+                        }
+                    }
+                } else {
+                    // Nothing to update, IPs are identical
+                    OpResult::Unchanged
+                }
+            }
+            None if config.create_records_allowed && !self.take_write() => OpResult::Deferred,
+            None => {
+                if config.create_records_allowed {
+                    let record = Record {
+                        name: host.name.clone(),
+                        ttl: config.ttl_for(rtype),
+                        content: *cur_ip,
+                        proxied: self.zone.proxied(host, config.proxied_for(rtype)),
+                    };
+
+                    let comment = comment::managed(template, &host.name, cur_ip);
+                    match self.create(record, Some(&comment)) {
+                        Ok(_) => {
+                            info!(
+                                "Created '{}' record '{}' with IP '{}'",
+                                rtype, host.name, cur_ip
+                            );
+                            OpResult::Created
+                        }
+                        Err(_) => {
+                            error!(
+                                "Failed to create '{}' record '{}' with IP '{}'",
+                                rtype, host.name, cur_ip
+                            );
+                            OpResult::Failed
+                        }
+                    }
+                } else {
+                    error!(
+                        "No cloudlflare record found with name '{}' of type '{}'",
+                        host.name, rtype
+                    );
+                    OpResult::Missing
+                }
+            }
+        }
+    }
+
+    /// Delete the managed records of `name` with the given type, which is no longer monitored
+    ///
+    /// Returns `None` if there was no such record.
+    fn remove_family(&mut self, name: &str, rtype: RecordType) -> Option<OpResult> {
+        let stale = self
+            .records
+            .iter()
+            .filter(|r| r.record.name.eq_ignore_ascii_case(name) && (r.record.rtype() == rtype))
+            .filter(|r| !r.locked && comment::is_managed(r.comment.as_deref()))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut result = None;
+        for record in stale {
+            if !self.take_write() {
+                return Some(OpResult::Deferred);
+            }
+            match self.delete(&record) {
+                Ok(_) => {
+                    info!(
+                        "Deleted '{rtype}' record '{name}' with IP '{}', since '{rtype}' records are no longer monitored",
+                        record.record.content
+                    );
+                    result.get_or_insert(OpResult::Deleted);
+                }
+                Err(_) => {
+                    error!(
+                        "Failed to delete '{rtype}' record '{name}' with IP '{}', which is no longer monitored",
+                        record.record.content
+                    );
+                    result = Some(OpResult::Failed);
+                }
+            }
+        }
+        result
+    }
+
+    /// Whether the `rtype` record of `name` was written less than `CF_DNS_POST_UPDATE_COOLDOWN_SECONDS` ago, so that it
+    /// is assumed to be current even if the records listed by Cloudflare don't show the change yet
+    fn cooling_down(&self, name: &str, rtype: RecordType) -> bool {
+        let cooling_down = self
+            .cooldowns
+            .contains_key(&(name.to_ascii_lowercase(), rtype));
+        if cooling_down {
+            debug!("'{rtype}' record '{name}' was written recently, not checking it again yet");
+        }
+        cooling_down
+    }
+
+    /// Start the cooldown of the `rtype` record of `name` if it was just written
+    fn start_cooldown(
+        &mut self,
+        name: &str,
+        rtype: RecordType,
+        result: OpResult,
+        clock: &impl Clock,
+    ) {
+        if !self.cooldown.is_zero() && matches!(result, OpResult::Updated | OpResult::Created) {
+            self.cooldowns.insert(
+                (name.to_ascii_lowercase(), rtype),
+                clock.now() + self.cooldown,
+            );
+        }
+    }
+
+    /// Whether the `rtype` record of `name` is updated by the pending `--force`, which didn't update it yet
+    fn forces(&self, name: &str, rtype: RecordType) -> bool {
+        self.force && !self.forced.contains(&(name.to_ascii_lowercase(), rtype))
+    }
+
+    /// Note the `result` of the forced update of the `rtype` record of `name`
+    ///
+    /// Returns `true` if the update was deferred, in which case the force is kept for the next cycle.
+    fn end_force(&mut self, name: &str, rtype: RecordType, result: OpResult) -> bool {
+        if result == OpResult::Deferred {
+            return true;
+        }
+        self.forced.insert((name.to_ascii_lowercase(), rtype));
+        false
+    }
+
+    /// Count a write against `CF_DNS_MAX_WRITES_PER_CYCLE`
+    ///
+    /// Returns `false` if no write is left for this cycle, in which case the write is deferred to a later cycle.
+    fn take_write(&mut self) -> bool {
+        match &mut self.writes_left {
+            Some(0) => false,
+            Some(left) => {
+                *left -= 1;
+                true
+            }
+            None => true,
+        }
+    }
+
+    /// Whether `cf_rec` was added automatically by Cloudflare and must be left alone, logging a warning if so
+    fn skips_auto_added(&self, cf_rec: &CfRecord) -> bool {
+        let skip = cf_rec.auto_added && !self.manage_auto_added;
+        if skip {
+            warn!(
+                "'{}' record '{}' was added automatically by Cloudflare, skipping it. Set `CF_DNS_MANAGE_AUTO_ADDED=true` to update it anyway",
+                cf_rec.record.rtype(),
+                cf_rec.record.name
+            );
+        }
+        skip
+    }
+
+    /// Find the first record with the given name, in any case, and type
+    fn find(&self, name: &str, rtype: RecordType) -> Option<&CfRecord> {
+        self.records
+            .iter()
+            .find(|r| r.record.name.eq_ignore_ascii_case(name) && (r.record.rtype() == rtype))
+    }
+
+    /// Set the IP of an existing record
+    pub fn update(
+        &mut self,
+        cf_rec: &CfRecord,
+        ip: &IpAddr,
+        comment: Option<&str>,
+    ) -> Result<(), ()> {
+        if cli::dry_run() {
+            info!(
+                "Dry run, not sending the update of '{}' record '{}' to IP '{ip}'",
+                cf_rec.record.rtype(),
+                cf_rec.record.name
+            );
+            return Ok(());
+        }
+        self.reused_cycles = None;
+        let timer = Timer::start(
+            Phase::CloudflareApi,
+            format!("Update '{}' {}", cf_rec.record.name, cf_rec.record.rtype()),
+        );
+        self.provider
+            .update_record_ip(&self.zone.id, cf_rec, ip, comment)?;
+        drop(timer);
+        audit::record(&audit::Entry {
+            action: Action::Update,
+            zone_id: &self.zone.id,
+            record_id: &cf_rec.id,
+            host: &cf_rec.record.name,
+            rtype: cf_rec.record.rtype(),
+            old_content: Some(cf_rec.record.content),
+            new_content: Some(*ip),
+        });
+        self.watchdog.wrote(cf_rec, *ip);
+        if let Some(check) = &self.propagation {
+            let record = Record {
+                content: *ip,
+                ..cf_rec.record.clone()
+            };
+            check.spawn(&record, Some(cf_rec.record.ttl));
+        }
+
+        if let Some(cached) = self.records.iter_mut().find(|r| r.id == cf_rec.id) {
+            cached.record.content = *ip;
+            if let Some(comment) = comment {
+                cached.comment = Some(comment.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the name, content, TTL, proxied status and comment of an existing record
+    pub fn update_record(
+        &mut self,
+        cf_rec: &CfRecord,
+        record: &Record,
+        comment: &str,
+    ) -> Result<(), ()> {
+        if cli::dry_run() {
+            info!(
+                "Dry run, not sending the update of '{}' record '{}' to '{}' with IP '{}', TTL '{}', proxied '{}'",
+                cf_rec.record.rtype(),
+                cf_rec.record.name,
+                record.name,
+                record.content,
+                record.ttl,
+                record.proxied
+            );
+            return Ok(());
+        }
+        self.reused_cycles = None;
+        let record = &Record {
+            ttl: self.ttl_limits.clamp(record.ttl, &record.name),
+            ..record.clone()
+        };
+        let timer = Timer::start(
+            Phase::CloudflareApi,
+            format!("Update '{}' {}", cf_rec.record.name, cf_rec.record.rtype()),
+        );
+        self.provider
+            .update_record(&self.zone.id, cf_rec, record, comment)?;
+        drop(timer);
+        audit::record(&audit::Entry {
+            action: Action::Update,
+            zone_id: &self.zone.id,
+            record_id: &cf_rec.id,
+            host: &cf_rec.record.name,
+            rtype: cf_rec.record.rtype(),
+            old_content: Some(cf_rec.record.content),
+            new_content: Some(record.content),
+        });
+        self.watchdog.wrote(cf_rec, record.content);
+        if let Some(check) = &self.propagation {
+            check.spawn(record, Some(cf_rec.record.ttl));
+        }
+
+        if let Some(cached) = self.records.iter_mut().find(|r| r.id == cf_rec.id) {
+            cached.record = record.clone();
+            cached.comment = Some(comment.to_string());
+        }
+        Ok(())
+    }
+
+    /// Create a new record
+    pub fn create(&mut self, mut record: Record, comment: Option<&str>) -> Result<(), ()> {
+        if cli::dry_run() {
+            info!(
+                "Dry run, not sending the creation of '{}' record '{}' with IP '{}'",
+                record.rtype(),
+                record.name,
+                record.content
+            );
+            return Ok(());
+        }
+        self.reused_cycles = None;
+        record.ttl = self.ttl_limits.clamp(record.ttl, &record.name);
+        let timer = Timer::start(
+            Phase::CloudflareApi,
+            format!("Create '{}' {}", record.name, record.rtype()),
+        );
+        let id = self
+            .provider
+            .create_record(&self.zone.id, &record, comment)?;
+        drop(timer);
+        audit::record(&audit::Entry {
+            action: Action::Create,
+            zone_id: &self.zone.id,
+            record_id: &id,
+            host: &record.name,
+            rtype: record.rtype(),
+            old_content: None,
+            new_content: Some(record.content),
+        });
+        if let Some(check) = &self.propagation {
+            check.spawn(&record, None);
+        }
+
+        // The created record as requested, which is kept if it is not fetched back
+        let requested = CfRecord {
+            id: id.clone(),
+            record: record.clone(),
+            locked: false,
+            auto_added: false,
+            comment: comment.map(|comment| comment.to_string()),
+            modified_on: None,
+            zone_id: Some(self.zone.id.clone()),
+            zone_name: self.zone.name.clone(),
+            extra: Default::default(),
+        };
+        if self.verify_create == VerifyCreate::Off {
+            self.records.push(requested);
+            return Ok(());
+        }
+
+        let created = {
+            let _timer = Timer::start(
+                Phase::CloudflareApi,
+                format!("Verify '{}' {}", record.name, record.rtype()),
+            );
+            self.provider.get_record(&self.zone.id, &id)
+        };
+        let Ok(created) = created else {
+            warn!(
+                "Could not verify created '{}' record '{}'",
+                record.rtype(),
+                record.name
+            );
+            // The record was created all the same, and must not be created again by the next cycle
+            self.records.push(requested);
+            return match self.verify_create {
+                VerifyCreate::Fail => Err(()),
+                _ => Ok(()),
+            };
+        };
+
+        let verified = created.record.matches(&record);
+        if !verified {
+            warn!(
+                "Created '{}' record '{}' has IP '{}', TTL '{}', proxied '{}' instead of IP '{}', TTL '{}', proxied '{}'",
+                record.rtype(),
+                record.name,
+                created.record.content,
+                created.record.ttl,
+                created.record.proxied,
+                record.content,
+                record.ttl,
+                record.proxied
+            );
+        }
+        self.records.push(created);
+
+        match (verified, self.verify_create) {
+            (false, VerifyCreate::Fail) => Err(()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Delete the managed duplicate records with the given name and type, keeping the first one (i.e. the one which is
+    /// reconciled). Records which are not managed, e.g. deliberate round-robin records, are left alone.
+    ///
+    /// Returns `None` if there was no duplicate to delete, and else whether they were deleted, deferred or failed.
+    pub fn prune(&mut self, name: &str, rtype: RecordType) -> Option<OpResult> {
+        let duplicates = self
+            .records
+            .iter()
+            .filter(|r| r.record.name.eq_ignore_ascii_case(name) && (r.record.rtype() == rtype))
+            .skip(1)
+            .filter(|r| !r.locked && comment::is_managed(r.comment.as_deref()))
+            .filter(|r| self.manage_auto_added || !r.auto_added)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut result = None;
+        for duplicate in duplicates {
+            if !self.take_write() {
+                return Some(OpResult::Deferred);
+            }
+            match self.delete(&duplicate) {
+                Ok(_) => {
+                    info!(
+                        "Deleted managed duplicate '{}' record '{}' with IP '{}'",
+                        rtype, name, duplicate.record.content
+                    );
+                    result.get_or_insert(OpResult::Deleted);
+                }
+                Err(_) => {
+                    error!(
+                        "Failed to delete managed duplicate '{}' record '{}' with IP '{}'",
+                        rtype, name, duplicate.record.content
+                    );
+                    result = Some(OpResult::Failed);
+                }
+            }
+        }
+        result
+    }
+
+    /// Delete a record
+    pub fn delete(&mut self, cf_rec: &CfRecord) -> Result<(), ()> {
+        if cli::dry_run() {
+            info!(
+                "Dry run, not sending the deletion of '{}' record '{}' with IP '{}'",
+                cf_rec.record.rtype(),
+                cf_rec.record.name,
+                cf_rec.record.content
+            );
+            return Ok(());
+        }
+        self.reused_cycles = None;
+        let timer = Timer::start(
+            Phase::CloudflareApi,
+            format!("Delete '{}' {}", cf_rec.record.name, cf_rec.record.rtype()),
+        );
+        self.provider.delete_record(&self.zone.id, &cf_rec.id)?;
+        drop(timer);
+        audit::record(&audit::Entry {
+            action: Action::Delete,
+            zone_id: &self.zone.id,
+            record_id: &cf_rec.id,
+            host: &cf_rec.record.name,
+            rtype: cf_rec.record.rtype(),
+            old_content: Some(cf_rec.record.content),
+            new_content: None,
+        });
+
+        self.records.retain(|r| r.id != cf_rec.id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl ZoneClient {
+    /// Client for the `hosts` of zone `example.com`, whose records are changed through `provider`
+    pub fn for_tests(
+        hosts: &[&str],
+        config: &Config,
+        provider: &crate::provider::FakeProvider,
+    ) -> Self {
+        let zone = Zone {
+            id: "zone".to_string(),
+            name: Some("example.com".to_string()),
+            status: Some("active".to_string()),
+            proxied_default: None,
+        };
+        let hosts = hosts
+            .iter()
+            .map(|name| HostConfig {
+                name: name.to_string(),
+                proxied: None,
+                webhook: None,
+                enabled: true,
+                manage: Manage::Full,
+            })
+            .collect();
+        Self::new(zone, hosts, None, config, Box::new(provider.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Ttl;
+    use crate::clock::MockClock;
+    use crate::desired::Content;
+    use crate::provider::{FakeProvider, fake_record};
+
+    const CUR_IP: &str = "203.0.113.7";
+    const OLD_IP: &str = "198.51.100.1";
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn host(name: &str) -> HostConfig {
+        HostConfig {
+            name: name.to_string(),
+            proxied: None,
+            webhook: None,
+            enabled: true,
+            manage: Manage::Full,
+        }
+    }
+
+    fn client(
+        hosts: &[&str],
+        desired: Option<Vec<DesiredRecord>>,
+        config: &Config,
+        provider: &FakeProvider,
+    ) -> ZoneClient {
+        let zone = Zone {
+            id: "zone".to_string(),
+            name: Some("example.com".to_string()),
+            status: Some("active".to_string()),
+            proxied_default: None,
+        };
+        let hosts = hosts.iter().map(|name| host(name)).collect();
+        ZoneClient::new(zone, hosts, desired, config, Box::new(provider.clone()))
+    }
+
+    fn reconcile(zone: &mut ZoneClient, config: &Config) -> ReconcileReport {
+        let cur_ips = BTreeMap::from([(RecordType::A, ip(CUR_IP))]);
+        let clock = MockClock::new(Duration::ZERO);
+        zone.reconcile(&cur_ips, config, &clock, Deadline::default(), &mut None)
+    }
+
+    #[test]
+    fn verifies_that_the_api_token_is_active() {
+        logging::init_for_tests();
+        let provider = FakeProvider::new(Vec::new());
+        assert_eq!(verify_token(&provider, "global API token"), Ok(()));
+
+        provider.set_token_status("expired");
+        let (verified, errors) =
+            logging::capture_errors(|| verify_token(&provider, "global API token"));
+
+        assert_eq!(verified, Err(()));
+        assert_eq!(
+            errors,
+            ["The global API token has status 'expired' instead of 'active'"]
+        );
+    }
+
+    #[test]
+    fn creates_a_missing_record_when_allowed() {
+        let mut config = Config::for_tests(&[RecordType::A]);
+        config.create_records_allowed = true;
+        let provider = FakeProvider::new(Vec::new());
+        let mut zone = client(&["www.example.com"], None, &config, &provider);
+
+        let report = reconcile(&mut zone, &config);
+
+        assert_eq!(report.count(OpResult::Created), 1);
+        assert_eq!(provider.writes(), ["create www.example.com 203.0.113.7"]);
+        let records = provider.records();
+        assert_eq!(records[0].record.content, ip(CUR_IP));
+        assert!(comment::is_managed(records[0].comment.as_deref()));
+    }
+
+    fn verifying_client(verify: VerifyCreate, provider: &FakeProvider) -> ZoneClient {
+        let mut config = Config::for_tests(&[RecordType::A]);
+        config.verify_create = verify;
+        client(&[], None, &config, provider)
+    }
+
+    fn created(proxied: bool) -> Record {
+        Record {
+            name: "www.example.com".to_string(),
+            ttl: Ttl::Auto,
+            content: ip(CUR_IP),
+            proxied,
+        }
+    }
+
+    #[test]
+    fn verifies_a_created_record() {
+        let provider = FakeProvider::new(Vec::new());
+        let mut zone = verifying_client(VerifyCreate::Fail, &provider);
+
+        assert_eq!(zone.create(created(false), None), Ok(()));
+        assert_eq!(zone.records.len(), 1);
+    }
+
+    #[test]
+    fn fails_or_warns_when_the_created_record_does_not_match() {
+        // The fake provider never proxies the records it creates
+        let provider = FakeProvider::new(Vec::new());
+        let mut zone = verifying_client(VerifyCreate::Fail, &provider);
+        assert_eq!(zone.create(created(true), None), Err(()));
+
+        let mut zone = verifying_client(VerifyCreate::Warn, &provider);
+        assert_eq!(zone.create(created(true), None), Ok(()));
+        assert!(!zone.records[0].record.proxied);
+    }
+
+    #[test]
+    fn keeps_a_created_record_which_cannot_be_fetched_back() {
+        let provider = FakeProvider::new(Vec::new());
+        provider.fail_reads();
+        let mut zone = verifying_client(VerifyCreate::Warn, &provider);
+
+        assert_eq!(zone.create(created(false), None), Ok(()));
+        assert_eq!(zone.records.len(), 1);
+        assert!(zone.records[0].record.matches(&created(false)));
+    }
+
+    #[test]
+    fn reports_a_missing_record_when_creating_is_not_allowed() {
+        let config = Config::for_tests(&[RecordType::A]);
+        let provider = FakeProvider::new(Vec::new());
+        let mut zone = client(&["www.example.com"], None, &config, &provider);
+
+        let report = reconcile(&mut zone, &config);
+
+        assert_eq!(report.count(OpResult::Missing), 1);
+        assert!(provider.writes().is_empty());
+    }
+
+    #[test]
+    fn updates_a_record_with_an_old_ip() {
+        let config = Config::for_tests(&[RecordType::A]);
+        let provider =
+            FakeProvider::new(vec![fake_record("1", "www.example.com", ip(OLD_IP), None)]);
+        let mut zone = client(&["www.example.com"], None, &config, &provider);
+
+        let report = reconcile(&mut zone, &config);
+
+        assert_eq!(report.count(OpResult::Updated), 1);
+        assert_eq!(provider.writes(), ["update www.example.com 203.0.113.7"]);
+        assert_eq!(provider.records()[0].record.content, ip(CUR_IP));
+    }
+
+    #[test]
+    fn leaves_a_current_record_alone() {
+        let config = Config::for_tests(&[RecordType::A]);
+        let provider =
+            FakeProvider::new(vec![fake_record("1", "www.example.com", ip(CUR_IP), None)]);
+        let mut zone = client(&["www.example.com"], None, &config, &provider);
+
+        let report = reconcile(&mut zone, &config);
+
+        assert_eq!(report.count(OpResult::Unchanged), 1);
+        assert!(provider.writes().is_empty());
+    }
+
+    #[test]
+    fn deletes_only_the_managed_records_which_are_no_longer_desired() {
+        let config = Config::for_tests(&[RecordType::A]);
+        let provider = FakeProvider::new(vec![
+            fake_record("1", "old.example.com", ip(OLD_IP), Some("[cf-dns-rs]")),
+            fake_record("2", "manual.example.com", ip(OLD_IP), Some("by hand")),
+        ]);
+        let desired = vec![DesiredRecord {
+            name: "api.example.com".to_string(),
+            rtype: RecordType::A,
+            content: Content::Dynamic,
+            ttl: Ttl::Auto,
+            proxied: None,
+            depends_on: None,
+        }];
+        let mut zone = client(&[], Some(desired), &config, &provider);
+
+        let report = reconcile(&mut zone, &config);
+
+        assert_eq!(report.count(OpResult::Deleted), 1);
+        assert_eq!(report.count(OpResult::Created), 1);
+        let mut names = provider
+            .records()
+            .into_iter()
+            .map(|r| r.record.name)
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["api.example.com", "manual.example.com"]);
+    }
+
+    #[test]
+    fn keeps_forcing_the_updates_deferred_by_the_write_limit() {
+        let config = Config::for_tests(&[RecordType::A]);
+        let provider = FakeProvider::new(vec![
+            fake_record("1", "a.example.com", ip(CUR_IP), Some("[cf-dns-rs]")),
+            fake_record("2", "b.example.com", ip(CUR_IP), Some("[cf-dns-rs]")),
+        ]);
+        let mut zone = client(
+            &["a.example.com", "b.example.com"],
+            None,
+            &config,
+            &provider,
+        );
+        zone.force = true;
+        let cur_ips = BTreeMap::from([(RecordType::A, ip(CUR_IP))]);
+        let clock = MockClock::new(Duration::ZERO);
+        let mut cycle =
+            || zone.reconcile(&cur_ips, &config, &clock, Deadline::default(), &mut Some(1));
+
+        assert_eq!(cycle().count(OpResult::Deferred), 1);
+        assert_eq!(provider.writes(), ["update a.example.com 203.0.113.7"]);
+        cycle();
+        cycle();
+
+        assert_eq!(
+            provider.writes(),
+            [
+                "update a.example.com 203.0.113.7",
+                "update b.example.com 203.0.113.7"
+            ]
+        );
+    }
+
+    #[test]
+    fn prunes_only_the_managed_duplicates() {
+        let mut config = Config::for_tests(&[RecordType::A]);
+        config.prune_duplicates = true;
+        let provider = FakeProvider::new(vec![
+            fake_record("1", "www.example.com", ip(CUR_IP), Some("[cf-dns-rs]")),
+            fake_record("2", "www.example.com", ip(OLD_IP), Some("[cf-dns-rs]")),
+            fake_record("3", "www.example.com", ip("192.0.2.1"), Some("round-robin")),
+        ]);
+        let mut zone = client(&["www.example.com"], None, &config, &provider);
+
+        let report = reconcile(&mut zone, &config);
+
+        assert_eq!(provider.writes(), ["delete www.example.com 2"]);
+        assert_eq!(report.count(OpResult::Deleted), 1);
+    }
+
+    fn duplicates() -> FakeProvider {
+        FakeProvider::new(vec![
+            fake_record("1", "www.example.com", ip(CUR_IP), Some("[cf-dns-rs]")),
+            fake_record("2", "www.example.com", ip(OLD_IP), Some("[cf-dns-rs]")),
+        ])
+    }
+
+    #[test]
+    fn does_not_prune_when_provisioning_only() {
+        let mut config = Config::for_tests(&[RecordType::A]);
+        config.prune_duplicates = true;
+        let provider = duplicates();
+        let mut zone = client(&["www.example.com"], None, &config, &provider);
+        zone.provision_only = true;
+
+        reconcile(&mut zone, &config);
+
+        assert!(provider.writes().is_empty());
+    }
+
+    #[test]
+    fn does_not_prune_the_duplicates_of_a_create_only_host() {
+        let mut config = Config::for_tests(&[RecordType::A]);
+        config.prune_duplicates = true;
+        let provider = duplicates();
+        let mut zone = client(&["www.example.com"], None, &config, &provider);
+        zone.hosts[0].manage = Manage::CreateOnly;
+
+        reconcile(&mut zone, &config);
+
+        assert!(provider.writes().is_empty());
+    }
+
+    #[test]
+    fn skips_an_auto_added_record_as_auto_added() {
+        let config = Config::for_tests(&[RecordType::A]);
+        let mut record = fake_record("1", "www.example.com", ip(OLD_IP), None);
+        record.auto_added = true;
+        let provider = FakeProvider::new(vec![record]);
+        let mut zone = client(&["www.example.com"], None, &config, &provider);
+
+        let report = reconcile(&mut zone, &config);
+
+        assert_eq!(report.count(OpResult::SkippedAutoAdded), 1);
+        assert_eq!(report.count(OpResult::Skipped), 0);
+        assert!(provider.writes().is_empty());
+    }
+
+    #[test]
+    fn reports_a_failed_update_and_keeps_the_record() {
+        let config = Config::for_tests(&[RecordType::A]);
+        let provider =
+            FakeProvider::new(vec![fake_record("1", "www.example.com", ip(OLD_IP), None)]);
+        provider.fail_writes();
+        let mut zone = client(&["www.example.com"], None, &config, &provider);
+
+        let report = reconcile(&mut zone, &config);
+
+        assert_eq!(report.count(OpResult::Failed), 1);
+        assert!(report.has_failures());
+        assert_eq!(provider.records()[0].record.content, ip(OLD_IP));
+    }
+
+    #[test]
+    fn reports_every_record_as_failed_when_the_records_cannot_be_listed() {
+        let config = Config::for_tests(&[RecordType::A]);
+        let provider = FakeProvider::default();
+        provider.fail_listing();
+        let mut zone = client(&["www.example.com"], None, &config, &provider);
+
+        let report = reconcile(&mut zone, &config);
+
+        assert!(report.all_failed());
+    }
+
+    #[test]
+    fn carries_the_control_socket_host_changes_over_a_reload() {
+        let config = Config::for_tests(&[RecordType::A]);
+        let provider = FakeProvider::default();
+        // `b` was removed and `c` added through the control socket, then the reloaded config adds `d`
+        let old = client(
+            &["a.example.com", "c.example.com"],
+            None,
+            &config,
+            &provider,
+        );
+        let old_hosts = [host("a.example.com"), host("b.example.com")];
+        let mut zone = client(
+            &["a.example.com", "b.example.com", "d.example.com"],
+            None,
+            &config,
+            &provider,
+        );
+
+        zone.carry_over(old, &old_hosts);
+
+        let names = zone
+            .hosts
+            .iter()
+            .map(|h| h.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a.example.com", "d.example.com", "c.example.com"]);
+    }
+
+    #[test]
+    fn reconciles_the_hosts_of_the_reloaded_config_on_the_next_cycle() {
+        let mut config = Config::for_tests(&[RecordType::A]);
+        config.create_records_allowed = true;
+        let provider = FakeProvider::new(vec![
+            fake_record("1", "a.example.com", ip(CUR_IP), Some("[cf-dns-rs]")),
+            fake_record("2", "b.example.com", ip(OLD_IP), Some("[cf-dns-rs]")),
+        ]);
+        let mut old = client(&["a.example.com"], None, &config, &provider);
+        reconcile(&mut old, &config);
+        // The reloaded config adds `b` and `c`, and removes `a`
+        let mut zone = client(
+            &["b.example.com", "c.example.com"],
+            None,
+            &config,
+            &provider,
+        );
+        zone.carry_over(old, &[host("a.example.com")]);
+
+        let report = reconcile(&mut zone, &config);
+
+        assert_eq!(
+            report.hosts.keys().collect::<Vec<_>>(),
+            ["b.example.com", "c.example.com"]
+        );
+        assert_eq!(
+            provider.writes(),
+            [
+                "update b.example.com 203.0.113.7",
+                "create c.example.com 203.0.113.7"
+            ]
+        );
+    }
+
+    #[test]
+    fn reuses_the_cached_records_after_a_reload() {
+        let mut config = Config::for_tests(&[RecordType::A]);
+        config.record_cache_cycles = 1;
+        let provider =
+            FakeProvider::new(vec![fake_record("1", "www.example.com", ip(CUR_IP), None)]);
+        let mut old = client(&["www.example.com"], None, &config, &provider);
+        reconcile(&mut old, &config);
+        provider.fail_listing();
+        let mut zone = client(&["www.example.com"], None, &config, &provider);
+
+        zone.carry_over(old, &[host("www.example.com")]);
+        let report = reconcile(&mut zone, &config);
+
+        assert_eq!(report.count(OpResult::Unchanged), 1);
+    }
+
+    #[test]
+    fn lists_only_the_tagged_records_unless_a_host_has_none() {
+        let mut config = Config::for_tests(&[RecordType::A]);
+        config.managed_tag = Some("cf-dns-rs".to_string());
+        let mut tagged = fake_record("1", "www.example.com", ip(CUR_IP), None);
+        tagged
+            .extra
+            .insert("tags".to_string(), serde_json::json!(["cf-dns-rs"]));
+        let provider = FakeProvider::new(vec![
+            tagged,
+            fake_record("2", "api.example.com", ip(OLD_IP), None),
+        ]);
+        let clock = MockClock::new(Duration::ZERO);
+        let ids = |zone: &ZoneClient| {
+            zone.records
+                .iter()
+                .map(|r| r.id.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let mut zone = client(&["www.example.com"], None, &config, &provider);
+        zone.refresh(&clock).unwrap();
+        assert_eq!(ids(&zone), ["1"]);
+
+        // The record of `api.example.com` is not tagged yet
+        let mut zone = client(
+            &["www.example.com", "api.example.com"],
+            None,
+            &config,
+            &provider,
+        );
+        zone.refresh(&clock).unwrap();
+        assert_eq!(ids(&zone), ["1", "2"]);
+        assert_eq!(reconcile(&mut zone, &config).count(OpResult::Created), 0);
+    }
+
+    #[test]
+    fn resolves_the_proxied_status_from_the_host_then_the_zone_then_the_global_default() {
+        let zone = |proxied_default| Zone {
+            id: "zone".to_string(),
+            name: None,
+            status: None,
+            proxied_default,
+        };
+        let overridden = HostConfig {
+            proxied: Some(false),
+            ..host("www.example.com")
+        };
+
+        assert!(!zone(Some(true)).proxied(&overridden, true));
+        assert!(zone(Some(true)).proxied(&host("www.example.com"), false));
+        assert!(!zone(Some(false)).proxied(&host("www.example.com"), true));
+        assert!(zone(None).proxied(&host("www.example.com"), true));
+        assert!(!zone(None).proxied(&host("www.example.com"), false));
+    }
+
+    #[test]
+    fn warns_about_a_pending_zone_or_refuses_it() {
+        logging::init_for_tests();
+
+        let (checked, errors) =
+            logging::capture_errors(|| check_zone_status("example.com", "abc", "pending", false));
+        assert_eq!((checked, errors.len()), (Ok(()), 0));
+
+        let (checked, errors) =
+            logging::capture_errors(|| check_zone_status("example.com", "abc", "pending", true));
+        assert_eq!(checked, Err(()));
+        assert_eq!(
+            errors,
+            ["Zone 'example.com' (abc) has status 'pending' instead of 'active'"]
+        );
+        assert_eq!(
+            check_zone_status("example.com", "abc", "active", true),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn skips_a_locked_record_without_updating_it() {
+        let config = Config::for_tests(&[RecordType::A]);
+        let mut record = fake_record("1", "www.example.com", ip(OLD_IP), None);
+        record.locked = true;
+        let provider = FakeProvider::new(vec![record]);
+        let mut zone = client(&["www.example.com"], None, &config, &provider);
+
+        let report = reconcile(&mut zone, &config);
+
+        assert_eq!(report.count(OpResult::Skipped), 1);
+        assert!(!report.has_failures());
+        assert!(report.to_string().contains("1 skipped"), "{report}");
+        assert!(provider.writes().is_empty());
+    }
+
+    fn other_family() -> FakeProvider {
+        FakeProvider::new(vec![
+            fake_record("1", "a.example.com", ip(CUR_IP), Some("[cf-dns-rs]")),
+            fake_record("2", "a.example.com", ip("2001:db8::1"), Some("[cf-dns-rs]")),
+            fake_record("3", "b.example.com", ip(CUR_IP), Some("[cf-dns-rs]")),
+            fake_record("4", "b.example.com", ip("2001:db8::2"), Some("by hand")),
+        ])
+    }
+
+    #[test]
+    fn deletes_the_managed_records_of_the_family_no_longer_monitored() {
+        let mut config = Config::for_tests(&[RecordType::A]);
+        config.replace_on_family_change = true;
+        let provider = other_family();
+        let mut zone = client(
+            &["a.example.com", "b.example.com"],
+            None,
+            &config,
+            &provider,
+        );
+
+        let report = reconcile(&mut zone, &config);
+
+        assert_eq!(provider.writes(), ["delete a.example.com 2"]);
+        assert_eq!(report.count(OpResult::Deleted), 1);
+        assert_eq!(report.count(OpResult::Unchanged), 2);
+    }
+
+    #[test]
+    fn keeps_the_records_of_the_other_family_by_default() {
+        let config = Config::for_tests(&[RecordType::A]);
+        let provider = other_family();
+        let mut zone = client(
+            &["a.example.com", "b.example.com"],
+            None,
+            &config,
+            &provider,
+        );
+
+        reconcile(&mut zone, &config);
+
+        assert!(provider.writes().is_empty());
+    }
+
+    #[test]
+    fn renames_a_record_to_the_configured_case_only_when_asked() {
+        let mut config = Config::for_tests(&[RecordType::A]);
+        let record = fake_record("1", "WWW.Example.com", ip(CUR_IP), None);
+        let provider = FakeProvider::new(vec![record.clone()]);
+        let mut zone = client(&["www.example.com"], None, &config, &provider);
+
+        let report = reconcile(&mut zone, &config);
+
+        assert_eq!(report.count(OpResult::Unchanged), 1);
+        assert!(provider.writes().is_empty());
+
+        config.normalize_name_case = true;
+        let provider = FakeProvider::new(vec![record]);
+        let mut zone = client(&["www.example.com"], None, &config, &provider);
+
+        let report = reconcile(&mut zone, &config);
+
+        assert_eq!(report.count(OpResult::Updated), 1);
+        assert_eq!(provider.writes(), ["update WWW.Example.com 203.0.113.7"]);
+        assert_eq!(provider.records()[0].record.name, "www.example.com");
+    }
+
+    #[test]
+    fn creates_a_record_with_a_clamped_ttl() {
+        let mut config = Config::for_tests(&[RecordType::A]);
+        config.ttl_limits = TtlLimits {
+            min: Some(120),
+            max: None,
+        };
+        let provider = FakeProvider::new(Vec::new());
+        let mut zone = client(&[], None, &config, &provider);
+
+        let record = Record {
+            ttl: Ttl::Seconds(60),
+            ..created(false)
+        };
+        assert_eq!(zone.create(record, None), Ok(()));
+
+        assert_eq!(zone.records[0].record.ttl, Ttl::Seconds(120));
+    }
+
+    #[test]
+    fn applies_at_most_the_write_limit_per_cycle_until_drained() {
+        let config = Config::for_tests(&[RecordType::A]);
+        let names = ["a", "b", "c", "d", "e"].map(|host| format!("{host}.example.com"));
+        let provider = FakeProvider::new(
+            names
+                .iter()
+                .enumerate()
+                .map(|(id, name)| fake_record(&id.to_string(), name, ip(OLD_IP), None))
+                .collect(),
+        );
+        let mut zone = client(
+            &names.each_ref().map(String::as_str),
+            None,
+            &config,
+            &provider,
+        );
+        let cur_ips = BTreeMap::from([(RecordType::A, ip(CUR_IP))]);
+        let clock = MockClock::new(Duration::ZERO);
+        let mut cycle = || {
+            let report =
+                zone.reconcile(&cur_ips, &config, &clock, Deadline::default(), &mut Some(2));
+            (
+                report.count(OpResult::Updated),
+                report.count(OpResult::Deferred),
+            )
+        };
+
+        assert_eq!(cycle(), (2, 3));
+        assert_eq!(provider.writes().len(), 2);
+        assert_eq!(cycle(), (2, 1));
+        assert_eq!(provider.writes().len(), 4);
+        assert_eq!(cycle(), (1, 0));
+        assert_eq!(cycle(), (0, 0));
+        assert_eq!(provider.writes().len(), 5);
+        assert!(
+            provider
+                .records()
+                .iter()
+                .all(|r| r.record.content == ip(CUR_IP))
+        );
+    }
+
+    fn apex_zone(name: Option<&str>) -> Zone {
+        Zone {
+            id: "zone".to_string(),
+            name: name.map(|name| name.to_string()),
+            status: Some("active".to_string()),
+            proxied_default: None,
+        }
+    }
+
+    #[test]
+    fn resolves_the_at_host_to_the_zone_apex() {
+        logging::init_for_tests();
+        let config = Config::for_tests(&[RecordType::A]);
+        let by_name = ZoneRef::Name("Example.com".to_string());
+        let by_id = ZoneRef::Id("zone".to_string());
+
+        let apex = |zone: Zone, name, zone_ref| {
+            zone.apex_host(&host(name), &config, zone_ref)
+                .map(|host| host.name)
+        };
+        assert_eq!(
+            apex(apex_zone(Some("example.com")), "@", &by_id),
+            Ok("example.com".to_string())
+        );
+        assert_eq!(
+            apex(apex_zone(None), "@", &by_name),
+            Ok("example.com".to_string())
+        );
+        assert_eq!(
+            apex(apex_zone(None), "www.example.com", &by_id),
+            Ok("www.example.com".to_string())
+        );
+
+        let (apex, errors) = logging::capture_errors(|| apex(apex_zone(None), "@", &by_id));
+        assert_eq!(apex, Err(()));
+        assert_eq!(
+            errors,
+            ["Host '@' is the apex of zone 'zone', whose name could not be looked up"]
+        );
+    }
+
+    #[test]
+    fn creates_then_updates_the_a_record_of_a_proxied_apex() {
+        let mut config = Config::for_tests(&[RecordType::A]);
+        config.create_records_allowed = true;
+        config.verify_create = VerifyCreate::Warn;
+        let zone_ref = ZoneRef::Id("zone".to_string());
+        let apex = HostConfig {
+            proxied: Some(true),
+            ..host("@")
+        };
+        let apex = apex_zone(Some("example.com"))
+            .apex_host(&apex, &config, &zone_ref)
+            .unwrap();
+        let apex_client = |provider: &FakeProvider| {
+            let zone = apex_zone(Some("example.com"));
+            ZoneClient::new(
+                zone,
+                vec![apex.clone()],
+                None,
+                &config,
+                Box::new(provider.clone()),
+            )
+        };
+
+        let provider = FakeProvider::new(Vec::new());
+        let report = reconcile(&mut apex_client(&provider), &config);
+        assert_eq!(report.count(OpResult::Created), 1);
+        assert_eq!(provider.writes(), ["create example.com 203.0.113.7"]);
+
+        let provider = FakeProvider::new(vec![fake_record("1", "example.com", ip(OLD_IP), None)]);
+        let report = reconcile(&mut apex_client(&provider), &config);
+        assert_eq!(report.count(OpResult::Updated), 1);
+        assert_eq!(provider.writes(), ["update example.com 203.0.113.7"]);
+    }
+
+    #[test]
+    fn skips_a_just_updated_record_during_its_cooldown() {
+        let mut config = Config::for_tests(&[RecordType::A]);
+        config.post_update_cooldown = Duration::from_secs(60);
+        let provider =
+            FakeProvider::new(vec![fake_record("1", "www.example.com", ip(OLD_IP), None)]);
+        let mut zone = client(&["www.example.com"], None, &config, &provider);
+        let clock = MockClock::new(Duration::ZERO);
+        let mut reconcile_with = |cur_ip| {
+            let cur_ips = BTreeMap::from([(RecordType::A, ip(cur_ip))]);
+            zone.reconcile(&cur_ips, &config, &clock, Deadline::default(), &mut None)
+        };
+
+        assert_eq!(reconcile_with(CUR_IP).count(OpResult::Updated), 1);
+
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(reconcile_with(OLD_IP).count(OpResult::Unchanged), 1);
+        assert_eq!(provider.writes(), ["update www.example.com 203.0.113.7"]);
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(reconcile_with(OLD_IP).count(OpResult::Updated), 1);
+        assert_eq!(
+            provider.writes(),
+            [
+                "update www.example.com 203.0.113.7",
+                "update www.example.com 198.51.100.1"
+            ]
+        );
+    }
+
+    #[test]
+    fn reuses_the_cached_records_until_they_expire_or_a_record_is_written() {
+        let mut config = Config::for_tests(&[RecordType::A]);
+        config.record_cache_ttl = Some(Duration::from_secs(60));
+        let clock = MockClock::new(Duration::ZERO);
+
+        let provider =
+            FakeProvider::new(vec![fake_record("1", "www.example.com", ip(CUR_IP), None)]);
+        let mut zone = client(&["www.example.com"], None, &config, &provider);
+        assert_eq!(zone.refresh(&clock), Ok(()));
+        provider.fail_listing();
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(zone.refresh(&clock), Ok(()));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(zone.refresh(&clock), Err(()));
+
+        let provider =
+            FakeProvider::new(vec![fake_record("1", "www.example.com", ip(OLD_IP), None)]);
+        let mut zone = client(&["www.example.com"], None, &config, &provider);
+        let cur_ips = BTreeMap::from([(RecordType::A, ip(CUR_IP))]);
+        let report = zone.reconcile(&cur_ips, &config, &clock, Deadline::default(), &mut None);
+        assert_eq!(report.count(OpResult::Updated), 1);
+        provider.fail_listing();
+        assert_eq!(zone.refresh(&clock), Err(()));
+    }
+
+    #[test]
+    fn leaves_the_records_of_a_disabled_host_alone() {
+        let mut config = Config::for_tests(&[RecordType::A]);
+        config.create_records_allowed = true;
+        config.prune_duplicates = true;
+        let provider = duplicates();
+        let mut zone = client(
+            &["www.example.com", "api.example.com"],
+            None,
+            &config,
+            &provider,
+        );
+        for host in &mut zone.hosts {
+            host.enabled = false;
+        }
+
+        let report = reconcile(&mut zone, &config);
+
+        assert!(provider.writes().is_empty());
+        assert_eq!(report.count(OpResult::Updated), 0);
+        assert_eq!(report.count(OpResult::Created), 0);
+        assert_eq!(report.count(OpResult::Deleted), 0);
+    }
+
+    #[test]
+    fn provisions_only_the_missing_records() {
+        let mut config = Config::for_tests(&[RecordType::A]);
+        config.create_records_allowed = true;
+        let provider =
+            FakeProvider::new(vec![fake_record("1", "www.example.com", ip(OLD_IP), None)]);
+        let mut zone = client(
+            &["www.example.com", "api.example.com", "mail.example.com"],
+            None,
+            &config,
+            &provider,
+        );
+        zone.provision_only = true;
+
+        let report = reconcile(&mut zone, &config);
+
+        assert_eq!(
+            report.created(),
+            [
+                ("api.example.com", RecordType::A),
+                ("mail.example.com", RecordType::A)
+            ]
+        );
+        assert_eq!(report.count(OpResult::Unchanged), 1);
+        assert_eq!(
+            provider.writes(),
+            [
+                "create api.example.com 203.0.113.7",
+                "create mail.example.com 203.0.113.7"
+            ]
+        );
+    }
+
+    #[test]
+    fn creates_the_record_of_a_create_only_host_then_never_updates_it() {
+        let mut config = Config::for_tests(&[RecordType::A]);
+        config.create_records_allowed = true;
+        let provider = FakeProvider::new(Vec::new());
+        let mut zone = client(&["www.example.com"], None, &config, &provider);
+        zone.hosts[0].manage = Manage::CreateOnly;
+
+        let report = reconcile(&mut zone, &config);
+        assert_eq!(report.count(OpResult::Created), 1);
+
+        let cur_ips = BTreeMap::from([(RecordType::A, ip(OLD_IP))]);
+        let clock = MockClock::new(Duration::ZERO);
+        zone.force = true;
+        let report = zone.reconcile(&cur_ips, &config, &clock, Deadline::default(), &mut None);
+
+        assert_eq!(report.count(OpResult::Unchanged), 1);
+        assert_eq!(provider.writes(), ["create www.example.com 203.0.113.7"]);
+    }
+
+    #[test]
+    fn adopts_the_unmanaged_records_of_the_hosts_without_changing_their_content() {
+        let config = Config::for_tests(&[RecordType::A]);
+        let mut locked = fake_record("3", "mail.example.com", ip(OLD_IP), None);
+        locked.locked = true;
+        let provider = FakeProvider::new(vec![
+            fake_record("1", "www.example.com", ip(OLD_IP), Some("by hand")),
+            fake_record("2", "api.example.com", ip(OLD_IP), Some("[cf-dns-rs]")),
+            locked,
+            fake_record("4", "other.example.com", ip(OLD_IP), None),
+        ]);
+        let mut zone = client(
+            &["www.example.com", "api.example.com", "mail.example.com"],
+            None,
+            &config,
+            &provider,
+        );
+
+        let adopted = zone.adopt(&MockClock::new(Duration::ZERO));
+
+        assert_eq!(adopted, Ok(vec!["1".to_string()]));
+        assert_eq!(provider.writes(), ["update www.example.com 198.51.100.1"]);
+        let records = provider.records();
+        assert_eq!(
+            records[0].comment.as_deref(),
+            Some(format!("{} by hand", comment::MANAGED_MARKER).as_str())
+        );
+        assert_eq!(records[0].record.content, ip(OLD_IP));
+        assert_eq!(records[2].comment, None);
+        assert_eq!(records[3].comment, None);
+    }
+
+    #[test]
+    fn prunes_only_the_records_of_the_prune_types() {
+        let mut config = Config::for_tests(&[RecordType::A, RecordType::AAAA]);
+        config.prune_duplicates = true;
+        config.prune_types = Some(vec![RecordType::A]);
+        let cur_v6 = "2001:db8::7";
+        let provider = FakeProvider::new(vec![
+            fake_record("1", "www.example.com", ip(CUR_IP), Some("[cf-dns-rs]")),
+            fake_record("2", "www.example.com", ip(OLD_IP), Some("[cf-dns-rs]")),
+            fake_record("3", "www.example.com", ip(cur_v6), Some("[cf-dns-rs]")),
+            fake_record(
+                "4",
+                "www.example.com",
+                ip("2001:db8::1"),
+                Some("[cf-dns-rs]"),
+            ),
+        ]);
+        let mut zone = client(&["www.example.com"], None, &config, &provider);
+        let cur_ips = BTreeMap::from([(RecordType::A, ip(CUR_IP)), (RecordType::AAAA, ip(cur_v6))]);
+        let clock = MockClock::new(Duration::ZERO);
+
+        zone.reconcile(&cur_ips, &config, &clock, Deadline::default(), &mut None);
+
+        assert_eq!(provider.writes(), ["delete www.example.com 2"]);
+        let ids = provider
+            .records()
+            .into_iter()
+            .map(|r| r.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, ["1", "3", "4"]);
+    }
+}